@@ -0,0 +1,197 @@
+//! Backup archive layout (integers are big-endian):
+//!
+//! ```text
+//! header   MAGIC, u32 format version
+//! records  u8 kind, u16 name length, name, u64 data length, data
+//! trailer  u64 offset of the manifest record, MAGIC
+//! ```
+//!
+//! File records come first and the single manifest record last, so an
+//! archive can be written in one pass while hashing what goes into it.
+//! Readers either stream it front to back or jump to the manifest
+//! through the trailer.
+
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const FORMAT_VERSION: u32 = 1;
+const MAGIC: &[u8; 8] = b"AETHBAK\0";
+const KIND_FILE: u8 = 1;
+const KIND_MANIFEST: u8 = 2;
+const MANIFEST_NAME: &str = "manifest.json";
+/// Manifests list one line per blob; anything larger is not ours.
+const MAX_MANIFEST: u64 = 256 * 1024 * 1024;
+
+pub struct ArchiveWriter<W: Write> {
+    out: W,
+    offset: u64,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        out.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        Ok(Self {
+            out,
+            offset: (MAGIC.len() + 4) as u64,
+        })
+    }
+
+    /// Append `len` bytes of `data` under `name`, returning their SHA-256.
+    /// Fails if `data` ends early, e.g. a file truncated mid-backup.
+    pub fn add_file(&mut self, name: &str, len: u64, data: impl Read) -> io::Result<String> {
+        self.write_header(KIND_FILE, name, len)?;
+        let (copied, hash) = copy_hashed(&mut data.take(len), &mut self.out)?;
+        if copied != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} changed while being archived", name),
+            ));
+        }
+        self.offset += len;
+        Ok(hash)
+    }
+
+    /// Write the manifest and trailer, handing back the output to flush.
+    pub fn finish(mut self, manifest: &[u8]) -> io::Result<W> {
+        let at = self.offset;
+        self.write_header(KIND_MANIFEST, MANIFEST_NAME, manifest.len() as u64)?;
+        self.out.write_all(manifest)?;
+        self.out.write_all(&at.to_be_bytes())?;
+        self.out.write_all(MAGIC)?;
+        Ok(self.out)
+    }
+
+    fn write_header(&mut self, kind: u8, name: &str, len: u64) -> io::Result<()> {
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Name too long: {}", name)))?;
+        self.out.write_all(&[kind])?;
+        self.out.write_all(&name_len.to_be_bytes())?;
+        self.out.write_all(name.as_bytes())?;
+        self.out.write_all(&len.to_be_bytes())?;
+        self.offset += 1 + 2 + name.len() as u64 + 8;
+        Ok(())
+    }
+}
+
+pub enum Record {
+    /// Header of a file whose `len` bytes must be consumed with
+    /// `ArchiveReader::copy_file` before reading the next record.
+    File { name: String, len: u64 },
+    Manifest(Vec<u8>),
+}
+
+pub struct ArchiveReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not a backup archive"));
+        }
+        let version = read_u32(&mut input)?;
+        if version != FORMAT_VERSION {
+            return Err(invalid(&format!("Unsupported backup format version {}", version)));
+        }
+        Ok(Self { input })
+    }
+
+    pub fn next_record(&mut self) -> io::Result<Record> {
+        let mut kind = [0u8; 1];
+        self.input.read_exact(&mut kind)?;
+        let (name, len) = read_header(&mut self.input)?;
+        match kind[0] {
+            KIND_FILE => Ok(Record::File { name, len }),
+            KIND_MANIFEST => Ok(Record::Manifest(read_manifest_body(&mut self.input, len)?)),
+            other => Err(invalid(&format!("Unknown record kind {}", other))),
+        }
+    }
+
+    /// Copy the current file's data to `out`, returning its SHA-256.
+    pub fn copy_file(&mut self, len: u64, out: &mut impl Write) -> io::Result<String> {
+        let (copied, hash) = copy_hashed(&mut (&mut self.input).take(len), out)?;
+        if copied != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Backup archive is truncated"));
+        }
+        Ok(hash)
+    }
+}
+
+/// The manifest of an archive, read through the trailer without
+/// touching the files before it.
+pub fn read_manifest(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    ArchiveReader::new(&mut file)?;
+
+    file.seek(SeekFrom::End(-(8 + MAGIC.len() as i64)))?;
+    let at = read_u64(&mut file)?;
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("Backup archive is truncated"));
+    }
+
+    file.seek(SeekFrom::Start(at))?;
+    let mut kind = [0u8; 1];
+    file.read_exact(&mut kind)?;
+    let (_, len) = read_header(&mut file)?;
+    if kind[0] != KIND_MANIFEST {
+        return Err(invalid("Backup archive is corrupt"));
+    }
+    read_manifest_body(&mut file, len)
+}
+
+fn read_header(input: &mut impl Read) -> io::Result<(String, u64)> {
+    let mut name_len = [0u8; 2];
+    input.read_exact(&mut name_len)?;
+    let mut name = vec![0u8; u16::from_be_bytes(name_len) as usize];
+    input.read_exact(&mut name)?;
+    let name = String::from_utf8(name).map_err(|_| invalid("Invalid record name"))?;
+    Ok((name, read_u64(input)?))
+}
+
+fn read_manifest_body(input: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_MANIFEST {
+        return Err(invalid("Backup manifest is too large"));
+    }
+    let mut body = vec![0u8; len as usize];
+    input.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn copy_hashed(input: &mut impl Read, out: &mut impl Write) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    let mut copied = 0u64;
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    Ok((copied, format!("{:x}", hasher.finalize())))
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,682 @@
+use base64::{engine::general_purpose, Engine};
+use chrono::Utc;
+use rusqlite::backup::Progress;
+use rusqlite::{params, Connection, DatabaseName};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::{crypto, db, geo, oplog, vault};
+use crate::vault::MEDIA_DIR;
+
+mod archive;
+
+use archive::{ArchiveReader, ArchiveWriter, Record};
+
+const MANIFEST_VERSION: u32 = 2;
+const DATABASE_ENTRY: &str = "database.sqlite";
+const BLOB_PREFIX: &str = "blobs/";
+/// PBKDF2 rounds for the key that seals the database entry. Archives
+/// record their own count; tests use few so unoptimised builds stay quick.
+#[cfg(not(test))]
+const KEY_ITERATIONS: u32 = 600_000;
+#[cfg(test)]
+const KEY_ITERATIONS: u32 = 1_000;
+
+/// Tables a merge copies rows from, with the column left for the local
+/// database to assign. Rows already present locally win.
+const MERGE_TABLES: &[(&str, Option<&str>)] = &[
+    ("memories", None),
+    ("memory_ops", None),
+    ("memory_locations", Some("id")),
+    ("people", None),
+    ("memory_people", None),
+    ("transcript_segments", Some("id")),
+];
+
+/// What a backup archive holds. The database snapshot includes the
+/// memory keys, so it is stored encrypted under the backup passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub id: String,
+    pub created_at: String,
+    /// Backup this one is relative to; blobs unchanged since then are
+    /// listed but not stored again.
+    pub base: Option<String>,
+    pub database: ArchivedFile,
+    pub database_key: KeyDerivation,
+    pub blobs: Vec<ArchivedFile>,
+}
+
+/// How the key sealing the database entry is derived from the passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyDerivation {
+    /// Base64 of the random PBKDF2-HMAC-SHA256 salt.
+    pub salt: String,
+    pub iterations: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedFile {
+    pub name: String,
+    pub size: u64,
+    pub hash: String,
+    /// Stored in this archive rather than in one of its bases.
+    pub included: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreMode {
+    /// Make the vault the backup: its database replaces ours.
+    Replace,
+    /// Add what the backup has and the vault lacks, keeping local rows.
+    Merge,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RestoreReport {
+    pub backup_id: String,
+    /// Memories taken from the backup: all of them when replacing, the
+    /// ones missing locally when merging.
+    pub memories: usize,
+    pub blobs_restored: usize,
+    /// Blobs already in `media_store` and left alone.
+    pub blobs_kept: usize,
+}
+
+/// Write the vault to a single archive at `path`: a snapshot of the
+/// database taken with SQLite's online backup API and encrypted with a
+/// key derived from `passphrase`, every encrypted blob and a manifest of
+/// their hashes. Given the archive of an earlier backup as `base`, blobs
+/// whose hash has not changed since are left out. The archive only
+/// appears at `path` once complete.
+pub fn export_backup(path: &Path, base: Option<&Path>, passphrase: &str) -> Result<Manifest, String> {
+    if passphrase.is_empty() {
+        return Err("A backup passphrase is required".into());
+    }
+    let base = base.map(read_manifest).transpose()?;
+    let previous: HashMap<&str, &str> = base
+        .iter()
+        .flat_map(|m| m.blobs.iter().map(|b| (b.name.as_str(), b.hash.as_str())))
+        .collect();
+
+    // The plain snapshot stays in the vault, never next to the archive
+    let snapshot = PathBuf::from(format!(
+        ".aethersync-backup-{}.db{}",
+        Uuid::new_v4().simple(),
+        vault::TEMP_SUFFIX
+    ));
+    let temp = sibling(path, "")?;
+    let result = (|| {
+        let conn = db::get_connection().map_err(|e| e.to_string())?;
+        conn.backup(DatabaseName::Main, &snapshot, None)
+            .map_err(|e| format!("Database snapshot failed: {}", e))?;
+        drop(conn);
+
+        let salt: [u8; 16] = rand::random();
+        let database_key = KeyDerivation {
+            salt: general_purpose::STANDARD.encode(salt),
+            iterations: KEY_ITERATIONS,
+        };
+        let key = crypto::derive_passphrase_key(passphrase, &salt, database_key.iterations);
+        let sealed = crypto::encrypt_bytes(&fs::read(&snapshot).map_err(|e| e.to_string())?, &key)?;
+
+        let out = BufWriter::new(File::create(&temp).map_err(|e| e.to_string())?);
+        let mut writer = ArchiveWriter::new(out).map_err(|e| e.to_string())?;
+        let hash = writer
+            .add_file(DATABASE_ENTRY, sealed.len() as u64, sealed.as_slice())
+            .map_err(|e| e.to_string())?;
+        let database = ArchivedFile {
+            name: DATABASE_ENTRY.to_string(),
+            size: sealed.len() as u64,
+            hash,
+            included: true,
+        };
+
+        let mut blobs = Vec::new();
+        for (name, blob) in blob_files()? {
+            if let Some(hash) = previous.get(name.as_str()) {
+                let current = vault::hash_file(&blob).map_err(|e| e.to_string())?;
+                if current == *hash {
+                    let size = fs::metadata(&blob).map_err(|e| e.to_string())?.len();
+                    blobs.push(ArchivedFile {
+                        name,
+                        size,
+                        hash: current,
+                        included: false,
+                    });
+                    continue;
+                }
+            }
+            let entry = add_file(&mut writer, &format!("{}{}", BLOB_PREFIX, name), &blob)?;
+            blobs.push(ArchivedFile { name, ..entry });
+        }
+
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            id: Uuid::new_v4().to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            base: base.as_ref().map(|m| m.id.clone()),
+            database,
+            database_key,
+            blobs,
+        };
+        let json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+        let out = writer.finish(&json).map_err(|e| e.to_string())?;
+        let file = out.into_inner().map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+        fs::rename(&temp, path).map_err(|e| e.to_string())?;
+        Ok(manifest)
+    })();
+
+    let _ = fs::remove_file(&snapshot);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// The manifest of a backup archive, without reading the rest of it.
+pub fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    let json = archive::read_manifest(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_manifest(&json)
+}
+
+/// Restore the vault from the archive at `path`, whose database opens
+/// with `passphrase`. An incremental backup also needs the archives it
+/// builds on in `bases`, in any order; each must be one of its bases, or
+/// a base of one of those.
+///
+/// Every archive is unpacked to a staging folder and checked against its
+/// manifest, and the database snapshot must decrypt and pass SQLite's
+/// integrity check, before anything in the vault is touched. Blobs go
+/// into `media_store` before the database changes, so a restored row
+/// never points at a blob that is not there yet.
+pub fn restore_backup(
+    path: &Path,
+    bases: &[PathBuf],
+    mode: RestoreMode,
+    passphrase: &str,
+) -> Result<RestoreReport, String> {
+    let staging = PathBuf::from(format!(".aethersync-restore-{}", Uuid::new_v4().simple()));
+    fs::create_dir_all(staging.join("blobs")).map_err(|e| e.to_string())?;
+
+    let result = (|| {
+        let manifest = unpack(path, &staging, true)?;
+        if manifest.blobs.iter().any(|b| !b.included) {
+            let mut base_manifests = HashMap::new();
+            for base in bases {
+                let base_manifest = unpack(base, &staging, false)?;
+                base_manifests.insert(base_manifest.id.clone(), (base, base_manifest));
+            }
+            check_base_chain(&manifest, &base_manifests)?;
+        }
+
+        for blob in &manifest.blobs {
+            check_blob_name(&blob.name)?;
+            vault::check_hash(&blob.hash)?;
+            if !staged_blob(&staging, &blob.hash).is_file() {
+                return Err(format!(
+                    "{} is not in this backup or any of the given base backups",
+                    blob.name
+                ));
+            }
+        }
+        let database = staging.join(DATABASE_ENTRY);
+        open_database(&database, &manifest.database_key, passphrase)?;
+        check_database(&database)?;
+
+        let mut report = RestoreReport {
+            backup_id: manifest.id.clone(),
+            ..Default::default()
+        };
+        let restored = restore_blobs(&staging, &manifest, mode, &mut report)?;
+        report.memories = match mode {
+            RestoreMode::Replace => replace_database(&database)?,
+            RestoreMode::Merge => merge_database(&database)?,
+        };
+        unevict(&restored)?;
+        Ok(report)
+    })();
+
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Unpack an archive into `staging`, blobs named by their hash so several
+/// archives can share the folder, and verify it against its manifest.
+fn unpack(path: &Path, staging: &Path, keep_database: bool) -> Result<Manifest, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut reader = ArchiveReader::new(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut hashes: HashMap<String, String> = HashMap::new();
+    let manifest = loop {
+        match reader.next_record().map_err(|e| e.to_string())? {
+            Record::File { name, len } => {
+                let hash = if name == DATABASE_ENTRY && keep_database {
+                    let mut out = File::create(staging.join(DATABASE_ENTRY)).map_err(|e| e.to_string())?;
+                    reader.copy_file(len, &mut out).map_err(|e| e.to_string())?
+                } else if name.starts_with(BLOB_PREFIX) {
+                    let temp = staging.join(format!("blobs/{}", Uuid::new_v4().simple()));
+                    let mut out = File::create(&temp).map_err(|e| e.to_string())?;
+                    let hash = reader.copy_file(len, &mut out).map_err(|e| e.to_string())?;
+                    fs::rename(&temp, staged_blob(staging, &hash)).map_err(|e| e.to_string())?;
+                    hash
+                } else {
+                    reader.copy_file(len, &mut io::sink()).map_err(|e| e.to_string())?
+                };
+                hashes.insert(name, hash);
+            }
+            Record::Manifest(json) => break parse_manifest(&json)?,
+        }
+    };
+
+    let expected = std::iter::once((DATABASE_ENTRY.to_string(), &manifest.database)).chain(
+        manifest
+            .blobs
+            .iter()
+            .filter(|b| b.included)
+            .map(|b| (format!("{}{}", BLOB_PREFIX, b.name), b)),
+    );
+    for (name, entry) in expected {
+        match hashes.get(&name) {
+            Some(hash) if *hash == entry.hash => {}
+            Some(_) => return Err(format!("{} in {} is corrupt", name, path.display())),
+            None => return Err(format!("{} is missing from {}", name, path.display())),
+        }
+    }
+    Ok(manifest)
+}
+
+/// Refuse base archives that are not in the chain of bases `manifest`
+/// builds on: their blobs could stand in for the ones it lists.
+fn check_base_chain(manifest: &Manifest, bases: &HashMap<String, (&PathBuf, Manifest)>) -> Result<(), String> {
+    let mut chain = HashSet::new();
+    let mut next = manifest.base.as_ref();
+    while let Some((_, base)) = next.and_then(|id| bases.get(id)) {
+        if !chain.insert(base.id.as_str()) {
+            break;
+        }
+        next = base.base.as_ref();
+    }
+    if let Some((path, _)) = bases.values().find(|(_, base)| !chain.contains(base.id.as_str())) {
+        return Err(format!("{} is not a base of this backup", path.display()));
+    }
+    Ok(())
+}
+
+/// Decrypt the staged database entry in place.
+fn open_database(path: &Path, derivation: &KeyDerivation, passphrase: &str) -> Result<(), String> {
+    let salt = general_purpose::STANDARD
+        .decode(&derivation.salt)
+        .map_err(|e| format!("Invalid backup manifest: {}", e))?;
+    let key = crypto::derive_passphrase_key(passphrase, &salt, derivation.iterations);
+    let sealed = fs::read(path).map_err(|e| e.to_string())?;
+    let plain = crypto::decrypt_bytes(&sealed, &key)
+        .map_err(|_| "Wrong passphrase for this backup".to_string())?;
+    fs::write(path, plain).map_err(|e| e.to_string())
+}
+
+fn check_database(path: &Path) -> Result<(), String> {
+    let conn = Connection::open(path).map_err(|e| e.to_string())?;
+    let result: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if result != "ok" {
+        return Err(format!("Backup database failed its integrity check: {}", result));
+    }
+    Ok(())
+}
+
+/// Copy the snapshot over the live database through the backup API, so
+/// open connections see either the old or the restored vault.
+fn replace_database(database: &Path) -> Result<usize, String> {
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.restore(DatabaseName::Main, database, None::<fn(Progress)>)
+        .map_err(|e| format!("Database restore failed: {}", e))?;
+    drop(conn);
+    // Snapshots from older versions still need today's columns
+    db::init_db()?;
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let memories: i64 = conn
+        .query_row("SELECT COUNT(*) FROM memories", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    Ok(memories as usize)
+}
+
+/// Insert rows the vault lacks, then rebuild the metadata of every memory
+/// whose history the backup contributed to.
+fn merge_database(database: &Path) -> Result<usize, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "ATTACH DATABASE ?1 AS backup",
+        params![database.to_string_lossy()],
+    )
+    .map_err(|e| e.to_string())?;
+
+    let result = (|| {
+        let mut memories = 0;
+        for (table, skip) in MERGE_TABLES {
+            let columns: Vec<String> = common_columns(&conn, table)?
+                .into_iter()
+                .filter(|c| Some(c.as_str()) != *skip)
+                .collect();
+            if columns.is_empty() {
+                continue;
+            }
+            let columns = columns.join(", ");
+            let inserted = conn
+                .execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO main.{} ({}) SELECT {} FROM backup.{}",
+                        table, columns, columns, table
+                    ),
+                    [],
+                )
+                .map_err(|e| e.to_string())?;
+            if *table == "memories" {
+                memories = inserted;
+            }
+        }
+
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT memory_id FROM backup.memory_ops")
+            .map_err(|e| e.to_string())?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        for id in ids {
+            oplog::materialize(&conn, &id)?;
+        }
+        // Merged locations have new row ids and no map entries yet
+        geo::index_missing(&conn)?;
+
+        Ok(memories)
+    })();
+
+    let _ = conn.execute("DETACH DATABASE backup", []);
+    result
+}
+
+/// Columns of `table` present in both the vault and the backup, which
+/// may predate some of them.
+fn common_columns(conn: &Connection, table: &str) -> Result<Vec<String>, String> {
+    let columns = |schema: &str| -> Result<Vec<String>, String> {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA {}.table_info({})", schema, table))
+            .map_err(|e| e.to_string())?;
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(1))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string());
+        names
+    };
+    let backup: HashSet<String> = columns("backup")?.into_iter().collect();
+    Ok(columns("main")?.into_iter().filter(|c| backup.contains(c)).collect())
+}
+
+/// Put the backup's blobs into `media_store`. Replacing overwrites blobs
+/// that differ; merging only adds missing ones. Returns the media
+/// filenames of the originals written.
+fn restore_blobs(
+    staging: &Path,
+    manifest: &Manifest,
+    mode: RestoreMode,
+    report: &mut RestoreReport,
+) -> Result<Vec<String>, String> {
+    let media_dir = Path::new(MEDIA_DIR);
+    fs::create_dir_all(media_dir).map_err(|e| e.to_string())?;
+
+    let mut originals = Vec::new();
+    for blob in &manifest.blobs {
+        let dst = media_dir.join(&blob.name);
+        let same = dst.is_file() && vault::hash_file(&dst).map_err(|e| e.to_string())? == blob.hash;
+        if same || (dst.exists() && matches!(mode, RestoreMode::Merge)) {
+            report.blobs_kept += 1;
+            continue;
+        }
+        vault::copy_atomic(&staged_blob(staging, &blob.hash), &dst, Some(&blob.hash))
+            .map_err(|e| e.to_string())?;
+        report.blobs_restored += 1;
+        originals.extend(original_filename(&blob.name).map(str::to_string));
+    }
+    Ok(originals)
+}
+
+/// A restored original is no longer evicted.
+fn unevict(filenames: &[String]) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    for filename in filenames {
+        conn.execute(
+            "UPDATE memories SET evicted_at = NULL WHERE filename = ?1",
+            params![filename],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Encrypted originals and thumbnails in `media_store`, by name.
+fn blob_files() -> Result<Vec<(String, PathBuf)>, String> {
+    let media_dir = Path::new(MEDIA_DIR);
+    if !media_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut blobs = Vec::new();
+    for entry in fs::read_dir(media_dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type().map_err(|e| e.to_string())?.is_file()
+            && name.ends_with(".enc")
+            && !vault::is_temp_file(&path)
+        {
+            blobs.push((name, path));
+        }
+    }
+    blobs.sort();
+    Ok(blobs)
+}
+
+fn add_file<W: Write>(writer: &mut ArchiveWriter<W>, name: &str, path: &Path) -> Result<ArchivedFile, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+    let hash = writer
+        .add_file(name, size, BufReader::new(file))
+        .map_err(|e| e.to_string())?;
+    Ok(ArchivedFile {
+        name: name.to_string(),
+        size,
+        hash,
+        included: true,
+    })
+}
+
+fn parse_manifest(json: &[u8]) -> Result<Manifest, String> {
+    let manifest: Manifest = serde_json::from_slice(json).map_err(|e| format!("Invalid backup manifest: {}", e))?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(format!("Unsupported backup manifest version {}", manifest.version));
+    }
+    Ok(manifest)
+}
+
+/// Blob names come from the archive; only plain file names may reach
+/// `media_store`.
+fn check_blob_name(name: &str) -> Result<(), String> {
+    if name.starts_with('.') || Path::new(name).file_name().map_or(true, |f| f != name) {
+        return Err(format!("Invalid blob name in backup: {}", name));
+    }
+    Ok(())
+}
+
+/// Media filename of an original's blob; thumbnails have none.
+fn original_filename(blob: &str) -> Option<&str> {
+    if blob.ends_with(".thumb.enc") {
+        return None;
+    }
+    blob.strip_suffix(".enc")
+}
+
+fn staged_blob(staging: &Path, hash: &str) -> PathBuf {
+    staging.join("blobs").join(hash)
+}
+
+/// Hidden temp file next to `path`, cleaned up like any other after a crash.
+fn sibling(path: &Path, extension: &str) -> Result<PathBuf, String> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path
+        .file_name()
+        .ok_or("Invalid backup path")?
+        .to_string_lossy();
+    Ok(parent.join(format!(
+        ".{}.{}{}{}",
+        name,
+        Uuid::new_v4().simple(),
+        extension,
+        vault::TEMP_SUFFIX
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+
+    const THUMBNAIL: &[u8] = b"sealed thumbnail bytes";
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    fn archive_path() -> PathBuf {
+        std::env::temp_dir().join(format!("aethersync-backup-{}", Uuid::new_v4().simple()))
+    }
+
+    fn add_memory(id: &str) {
+        db::add_memory(db::Memory {
+            id: id.into(),
+            title: id.into(),
+            tags: String::new(),
+            created_at: Utc::now().to_rfc3339(),
+            media_type: "text/plain".into(),
+            filename: String::new(),
+        })
+        .unwrap();
+    }
+
+    fn memory_count() -> i64 {
+        let conn = db::get_connection().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM memories", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn memory_keys_only_leave_under_the_passphrase() {
+        let _vault = TempVault::new();
+        add_memory("m1");
+        let conn = db::get_connection().unwrap();
+        conn.execute("UPDATE memories SET key_encrypted = 'memory-key-marker' WHERE id = 'm1'", [])
+            .unwrap();
+        let path = archive_path();
+        assert!(export_backup(&path, None, "").is_err());
+        export_backup(&path, None, PASSPHRASE).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        for clear in [&b"memory-key-marker"[..], b"SQLite format 3"] {
+            assert!(!bytes.windows(clear.len()).any(|w| w == clear));
+        }
+
+        let error = restore_backup(&path, &[], RestoreMode::Replace, "wrong").unwrap_err();
+        assert!(error.contains("Wrong passphrase"), "{}", error);
+        restore_backup(&path, &[], RestoreMode::Replace, PASSPHRASE).unwrap();
+        let key: String = conn
+            .query_row("SELECT key_encrypted FROM memories WHERE id = 'm1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(key, "memory-key-marker");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replace_keeps_the_database_when_a_blob_cannot_be_placed() {
+        let _vault = TempVault::new();
+        let thumbnail = Path::new(MEDIA_DIR).join("photo.jpg.thumb.enc");
+        fs::write(&thumbnail, THUMBNAIL).unwrap();
+        let path = archive_path();
+        export_backup(&path, None, PASSPHRASE).unwrap();
+
+        add_memory("m1");
+        fs::remove_file(&thumbnail).unwrap();
+        fs::create_dir(&thumbnail).unwrap();
+        assert!(restore_backup(&path, &[], RestoreMode::Replace, PASSPHRASE).is_err());
+        assert_eq!(memory_count(), 1);
+
+        fs::remove_dir(&thumbnail).unwrap();
+        let report = restore_backup(&path, &[], RestoreMode::Replace, PASSPHRASE).unwrap();
+        assert_eq!((report.memories, report.blobs_restored), (0, 1));
+        assert_eq!(memory_count(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_refuses_an_archive_with_a_flipped_byte() {
+        let _vault = TempVault::new();
+        fs::write(Path::new(MEDIA_DIR).join("photo.jpg.thumb.enc"), THUMBNAIL).unwrap();
+        let path = archive_path();
+        export_backup(&path, None, PASSPHRASE).unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        let at = bytes.windows(THUMBNAIL.len()).position(|w| w == THUMBNAIL).unwrap();
+        bytes[at + 3] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        fs::remove_file(Path::new(MEDIA_DIR).join("photo.jpg.thumb.enc")).unwrap();
+
+        let error = restore_backup(&path, &[], RestoreMode::Replace, PASSPHRASE).unwrap_err();
+        assert!(error.contains("corrupt"), "{}", error);
+        assert!(!Path::new(MEDIA_DIR).join("photo.jpg.thumb.enc").exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_takes_only_bases_the_backup_builds_on() {
+        let _vault = TempVault::new();
+        fs::write(Path::new(MEDIA_DIR).join("photo.jpg.thumb.enc"), THUMBNAIL).unwrap();
+        let (base, unrelated, incremental) = (archive_path(), archive_path(), archive_path());
+        export_backup(&base, None, PASSPHRASE).unwrap();
+        export_backup(&unrelated, None, PASSPHRASE).unwrap();
+        let manifest = export_backup(&incremental, Some(&base), PASSPHRASE).unwrap();
+        assert!(manifest.blobs.iter().all(|b| !b.included));
+
+        let error = restore_backup(&incremental, std::slice::from_ref(&unrelated), RestoreMode::Merge, PASSPHRASE)
+            .unwrap_err();
+        assert!(error.contains("is not a base"), "{}", error);
+        restore_backup(&incremental, std::slice::from_ref(&base), RestoreMode::Merge, PASSPHRASE).unwrap();
+
+        for path in [base, unrelated, incremental] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn merged_locations_show_on_the_map() {
+        let path = archive_path();
+        {
+            let _vault = TempVault::new();
+            add_memory("m1");
+            geo::set_location("m1", 48.8566, 2.3522, "manual").unwrap();
+            export_backup(&path, None, PASSPHRASE).unwrap();
+        }
+
+        let _vault = TempVault::new();
+        let report = restore_backup(&path, &[], RestoreMode::Merge, PASSPHRASE).unwrap();
+        assert_eq!(report.memories, 1);
+        let found = geo::query_bbox(48.0, 2.0, 49.0, 3.0).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].memory_id, "m1");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,7 +1,27 @@
-use tauri::command;
+use tauri::{command, AppHandle, State};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crate::backup::{self, RestoreMode};
 use crate::db;
 use crate::crypto;
 use crate::db::Memory;
+use crate::faces;
+use crate::fsck;
+use crate::geo;
+use crate::ipfs::{self, IpfsSettings};
+use crate::jobs;
+use crate::oplog;
+use crate::p2p::{self, P2pState};
+use crate::people;
+use crate::storage::{self, StoragePolicy};
+use crate::sync::profiles::{self, ProfileInput, SyncProfile};
+use crate::sync::SyncRunner;
+use crate::syncthing::{self, SyncthingSettings};
+use crate::timeline;
+use crate::transcripts;
+use crate::vault;
+use crate::whisper::subtitles::SubtitleFormat;
+use crate::whisper::{self, LanguageSettings, TranscriberState};
 
 #[command]
 pub fn get_all_memories() -> Result<Vec<Memory>, String> {
@@ -19,3 +39,532 @@ pub fn decrypt_text(cipher: String, key: String) -> Result<String, String> {
     crypto::decrypt_aes(&cipher, &key)
 }
 
+// ----------- Memory map ------------
+
+#[command]
+pub fn set_memory_location(memory_id: String, latitude: f64, longitude: f64) -> Result<geo::MemoryLocation, String> {
+    geo::set_location(&memory_id, latitude, longitude, "manual")
+}
+
+#[command]
+pub fn clear_memory_location(memory_id: String) -> Result<(), String> {
+    geo::clear_location(&memory_id)
+}
+
+#[command]
+pub fn get_memory_location(memory_id: String) -> Result<Option<geo::MemoryLocation>, String> {
+    geo::get_location(&memory_id)
+}
+
+#[command]
+pub fn memories_in_bbox(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Result<Vec<geo::MemoryLocation>, String> {
+    geo::query_bbox(min_lat, min_lon, max_lat, max_lon)
+}
+
+#[command]
+pub fn map_markers(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64, zoom: u8) -> Result<Vec<geo::MapMarker>, String> {
+    geo::cluster_markers(min_lat, min_lon, max_lat, max_lon, zoom)
+}
+
+#[command]
+pub fn search_memories_by_place(query: String, year: Option<i32>) -> Result<Vec<geo::MemoryLocation>, String> {
+    geo::search_by_place(&query, year)
+}
+
+#[command]
+pub fn reverse_geocode(latitude: f64, longitude: f64) -> Option<geo::Place> {
+    geo::reverse_geocode(latitude, longitude)
+}
+
+// ----------- Timeline ------------
+
+#[command]
+pub fn timeline_buckets(
+    granularity: timeline::Granularity,
+    range: Option<timeline::TimelineRange>,
+    filters: Option<timeline::TimelineFilters>,
+) -> Result<Vec<timeline::TimelineBucket>, String> {
+    timeline::timeline_buckets(granularity, range.unwrap_or_default(), filters.unwrap_or_default())
+}
+
+#[command]
+pub fn on_this_day(
+    date: Option<String>,
+    filters: Option<timeline::TimelineFilters>,
+) -> Result<Vec<timeline::TimelineThumbnail>, String> {
+    timeline::on_this_day(date, filters.unwrap_or_default())
+}
+
+// ----------- Faces ------------
+
+#[command]
+pub fn set_face_model_dir(state: State<'_, faces::FaceState>, model_dir: String) {
+    state.set_model_dir(&model_dir);
+}
+
+#[command(async)]
+pub fn scan_memory_faces(state: State<'_, faces::FaceState>, memory_id: String) -> Result<Vec<faces::Face>, String> {
+    state.scan_memory(&memory_id)
+}
+
+#[command]
+pub fn list_face_clusters() -> Result<Vec<faces::FaceCluster>, String> {
+    faces::list_clusters()
+}
+
+#[command]
+pub fn list_cluster_faces(cluster_id: i64) -> Result<Vec<faces::Face>, String> {
+    faces::list_cluster_faces(cluster_id)
+}
+
+#[command]
+pub fn name_face_cluster(cluster_id: i64, name: String) -> Result<(), String> {
+    faces::name_cluster(cluster_id, &name)
+}
+
+#[command]
+pub fn merge_face_clusters(state: State<'_, faces::FaceState>, target_id: i64, source_id: i64) -> Result<(), String> {
+    faces::merge_clusters(&state, target_id, source_id)
+}
+
+#[command]
+pub fn split_face_cluster(state: State<'_, faces::FaceState>, cluster_id: i64, face_ids: Vec<String>) -> Result<i64, String> {
+    faces::split_cluster(&state, cluster_id, face_ids)
+}
+
+#[command]
+pub fn memories_by_face_cluster(cluster_id: i64) -> Result<Vec<Memory>, String> {
+    faces::memories_by_cluster(cluster_id)
+}
+
+// ----------- People ------------
+
+#[command]
+pub fn create_person(input: people::PersonInput) -> Result<people::Person, String> {
+    people::create_person(input)
+}
+
+#[command]
+pub fn update_person(id: String, input: people::PersonInput) -> Result<people::Person, String> {
+    people::update_person(&id, input)
+}
+
+#[command]
+pub fn delete_person(id: String) -> Result<(), String> {
+    people::delete_person(&id)
+}
+
+#[command]
+pub fn list_people() -> Result<Vec<people::Person>, String> {
+    people::list_people()
+}
+
+#[command]
+pub fn tag_person(memory_id: String, person_id: String, region: Option<people::Region>) -> Result<(), String> {
+    people::tag_person(&memory_id, &person_id, region)
+}
+
+#[command]
+pub fn untag_person(memory_id: String, person_id: String) -> Result<(), String> {
+    people::untag_person(&memory_id, &person_id)
+}
+
+#[command]
+pub fn people_in_memory(memory_id: String) -> Result<Vec<people::TaggedPerson>, String> {
+    people::people_in_memory(&memory_id)
+}
+
+#[command]
+pub fn memories_with_people(person_ids: Vec<String>) -> Result<Vec<Memory>, String> {
+    people::memories_with_people(person_ids)
+}
+
+// ----------- Transcripts ------------
+
+#[command]
+pub fn get_transcript_segments(memory_id: String) -> Result<Vec<transcripts::StoredSegment>, String> {
+    transcripts::load_segments(&memory_id)
+}
+
+#[command]
+pub fn search_transcripts(query: String) -> Result<Vec<transcripts::SegmentMatch>, String> {
+    transcripts::search_segments(&query)
+}
+
+// ----------- Background jobs ------------
+
+#[command]
+pub fn list_jobs(status: Option<String>) -> Result<Vec<jobs::Job>, String> {
+    jobs::list_jobs(status)
+}
+
+#[command]
+pub fn queue_transcription(queue: State<'_, jobs::JobQueue>, memory_id: String) -> Result<String, String> {
+    queue.enqueue(jobs::JobKind::Transcribe, &memory_id)
+}
+
+#[command]
+pub fn retry_job(queue: State<'_, jobs::JobQueue>, job_id: String) -> Result<(), String> {
+    queue.retry(&job_id)
+}
+
+#[command]
+pub fn edit_transcript_segment(memory_id: String, segment_index: i64, text: String) -> Result<transcripts::StoredSegment, String> {
+    transcripts::edit_segment(&memory_id, segment_index, &text)
+}
+
+#[command]
+pub fn undo_transcript_edit(memory_id: String, segment_index: i64) -> Result<Option<String>, String> {
+    transcripts::undo_segment_edit(&memory_id, segment_index)
+}
+
+#[command]
+pub fn transcript_segment_history(memory_id: String, segment_index: i64) -> Result<Vec<transcripts::SegmentEdit>, String> {
+    transcripts::segment_history(&memory_id, segment_index)
+}
+
+#[command]
+pub fn export_transcript(memory_id: String, format: SubtitleFormat) -> Result<String, String> {
+    transcripts::export(&memory_id, format)
+}
+
+/// Write the exported transcript to `path`, adding the format's extension
+/// when missing.
+#[command]
+pub fn export_transcript_to_file(memory_id: String, format: SubtitleFormat, path: String) -> Result<String, String> {
+    let content = transcripts::export(&memory_id, format)?;
+    let mut path = std::path::PathBuf::from(path);
+    if path.extension().is_none() {
+        path.set_extension(format.extension());
+    }
+    std::fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+// ----------- Transcription languages ------------
+
+#[command]
+pub fn supported_languages(transcriber: State<'_, TranscriberState>) -> Vec<&'static str> {
+    transcriber.current().supported_languages()
+}
+
+#[command]
+pub fn set_memory_language(memory_id: String, language: Option<String>) -> Result<(), String> {
+    transcripts::set_memory_language(&memory_id, language.as_deref())
+}
+
+#[command]
+pub fn get_memory_translation(memory_id: String) -> Result<Option<String>, String> {
+    transcripts::load_translation(&memory_id)
+}
+
+#[command]
+pub fn get_language_settings() -> Result<LanguageSettings, String> {
+    LanguageSettings::load()
+}
+
+#[command]
+pub fn set_language_settings(transcriber: State<'_, TranscriberState>, mut settings: LanguageSettings) -> Result<(), String> {
+    if let Some(language) = &settings.default_language {
+        settings.default_language = Some(whisper::validate_language(transcriber.current().as_ref(), language)?);
+    }
+    settings.save()
+}
+
+// ----------- Peer-to-peer devices ------------
+
+#[command]
+pub async fn start_p2p(app: AppHandle, p2p: State<'_, P2pState>) -> Result<p2p::NodeInfo, String> {
+    p2p.start(app).await
+}
+
+#[command]
+pub fn stop_p2p(p2p: State<'_, P2pState>) -> Result<(), String> {
+    p2p.stop()
+}
+
+#[command]
+pub fn p2p_status(p2p: State<'_, P2pState>) -> Option<p2p::NodeInfo> {
+    p2p.info()
+}
+
+#[command]
+pub fn list_discovered_devices(p2p: State<'_, P2pState>) -> Result<Vec<p2p::discovery::DiscoveredDevice>, String> {
+    p2p.discovered_devices()
+}
+
+#[command]
+pub fn list_paired_devices() -> Result<Vec<p2p::devices::Device>, String> {
+    p2p::devices::list_devices()
+}
+
+#[command]
+pub async fn pair_device(app: AppHandle, p2p: State<'_, P2pState>, device_id: String) -> Result<p2p::pairing::PairingSession, String> {
+    p2p.pair(app, &device_id).await
+}
+
+/// Accept or reject a pairing once the user has compared the codes.
+#[command]
+pub fn confirm_pairing(p2p: State<'_, P2pState>, session_id: String, accept: bool) -> Result<(), String> {
+    p2p.confirm_pairing(&session_id, accept)
+}
+
+#[command]
+pub fn pairing_qr(p2p: State<'_, P2pState>) -> Result<p2p::pairing::PairingQr, String> {
+    p2p.pairing_qr()
+}
+
+#[command]
+pub async fn pair_with_qr(p2p: State<'_, P2pState>, payload: String) -> Result<p2p::devices::Device, String> {
+    p2p.pair_with_qr(&payload).await
+}
+
+#[command]
+pub fn unpair_device(device_id: String) -> Result<(), String> {
+    p2p::devices::remove_device(&device_id)
+}
+
+/// Pull and push missing memories with a paired device on the LAN.
+#[command]
+pub async fn sync_with_device(p2p: State<'_, P2pState>, device_id: String) -> Result<p2p::replication::ReplicationReport, String> {
+    p2p.sync_with(&device_id).await
+}
+
+#[command]
+pub fn set_device_role(device_id: String, role: String) -> Result<(), String> {
+    p2p::devices::set_role(&device_id, &role)
+}
+
+// ----------- Metadata history ------------
+
+#[command]
+pub fn set_memory_title(memory_id: String, title: String) -> Result<oplog::Op, String> {
+    oplog::set_title(&memory_id, &title)
+}
+
+#[command]
+pub fn add_memory_tag(memory_id: String, tag: String) -> Result<oplog::Op, String> {
+    oplog::add_tag(&memory_id, &tag)
+}
+
+#[command]
+pub fn remove_memory_tag(memory_id: String, tag: String) -> Result<oplog::Op, String> {
+    oplog::remove_tag(&memory_id, &tag)
+}
+
+#[command]
+pub fn add_memory_to_collection(memory_id: String, collection: String) -> Result<oplog::Op, String> {
+    oplog::add_to_collection(&memory_id, &collection)
+}
+
+#[command]
+pub fn remove_memory_from_collection(memory_id: String, collection: String) -> Result<oplog::Op, String> {
+    oplog::remove_from_collection(&memory_id, &collection)
+}
+
+/// Hide a memory everywhere. The tombstone replicates, so it cannot be
+/// resurrected by a device that still has the old row.
+#[command]
+pub fn delete_memory(jobs: State<'_, jobs::JobQueue>, memory_id: String) -> Result<oplog::Op, String> {
+    let op = oplog::delete_memory(&memory_id)?;
+    if ipfs::get_pin(&memory_id)?.is_some() {
+        jobs.enqueue(jobs::JobKind::Unpin, &memory_id)?;
+    }
+    Ok(op)
+}
+
+/// Every metadata operation on a memory, oldest first.
+#[command]
+pub fn memory_history(memory_id: String) -> Result<Vec<oplog::Op>, String> {
+    oplog::history(&memory_id)
+}
+
+// ----------- IPFS ------------
+
+#[command]
+pub fn get_ipfs_settings() -> Result<IpfsSettings, String> {
+    IpfsSettings::load()
+}
+
+#[command]
+pub fn set_ipfs_settings(settings: IpfsSettings) -> Result<(), String> {
+    settings.save()
+}
+
+/// Version of the configured Kubo node, or an error when it is unreachable.
+#[command]
+pub async fn ipfs_node_version() -> Result<String, String> {
+    IpfsSettings::load()?.client().version().await
+}
+
+#[command]
+pub async fn pin_memory(memory_id: String) -> Result<ipfs::Pin, String> {
+    ipfs::pin_memory(&memory_id).await
+}
+
+#[command]
+pub async fn unpin_memory(memory_id: String) -> Result<(), String> {
+    ipfs::unpin_memory(&memory_id).await
+}
+
+#[command]
+pub fn list_ipfs_pins() -> Result<Vec<ipfs::Pin>, String> {
+    ipfs::list_pins()
+}
+
+#[command]
+pub fn export_ipfs_manifest(path: String) -> Result<ipfs::Manifest, String> {
+    ipfs::export_manifest(std::path::Path::new(&path))
+}
+
+#[command]
+pub async fn restore_from_ipfs_manifest(path: String) -> Result<ipfs::RestoreReport, String> {
+    ipfs::restore_from_manifest(std::path::Path::new(&path)).await
+}
+
+// ----------- Syncthing ------------
+
+#[command]
+pub fn get_syncthing_settings() -> Result<syncthing::SyncthingSettingsView, String> {
+    Ok(SyncthingSettings::load()?.redacted())
+}
+
+#[command]
+pub fn set_syncthing_settings(settings: SyncthingSettings) -> Result<(), String> {
+    settings.update()
+}
+
+/// Share the vault folder through the local Syncthing instance.
+#[command]
+pub async fn register_syncthing_vault() -> Result<(), String> {
+    syncthing::register_vault().await
+}
+
+#[command]
+pub async fn syncthing_completion(
+    app: AppHandle,
+    runner: State<'_, SyncRunner>,
+) -> Result<Vec<syncthing::DeviceCompletion>, String> {
+    let completions = syncthing::completion().await?;
+    runner.update_syncthing(&app, vault::MEDIA_DIR, &completions);
+    Ok(completions)
+}
+
+// ----------- Sync profiles ------------
+
+#[command]
+pub fn list_sync_profiles() -> Result<Vec<SyncProfile>, String> {
+    profiles::list_profiles()
+}
+
+#[command]
+pub fn create_sync_profile(input: ProfileInput) -> Result<SyncProfile, String> {
+    profiles::create_profile(input)
+}
+
+/// Save new settings, restarting the profile if it is running.
+#[command(async)]
+pub fn update_sync_profile(app: AppHandle, runner: State<'_, SyncRunner>, id: String, input: ProfileInput) -> Result<SyncProfile, String> {
+    let profile = profiles::update_profile(&id, input)?;
+    if runner.is_running(&id) {
+        runner.stop(&id)?;
+        runner.start(app, &id)?;
+    }
+    Ok(profile)
+}
+
+#[command(async)]
+pub fn delete_sync_profile(runner: State<'_, SyncRunner>, id: String) -> Result<(), String> {
+    if runner.is_running(&id) {
+        runner.stop(&id)?;
+    }
+    profiles::delete_profile(&id)
+}
+
+#[command(async)]
+pub fn start_sync_profile(app: AppHandle, runner: State<'_, SyncRunner>, id: String) -> Result<(), String> {
+    runner.start(app, &id)
+}
+
+#[command(async)]
+pub fn stop_sync_profile(runner: State<'_, SyncRunner>, id: String) -> Result<(), String> {
+    runner.stop(&id)
+}
+
+// ----------- Storage policy ------------
+
+#[command]
+pub fn get_storage_policy() -> Result<StoragePolicy, String> {
+    StoragePolicy::load()
+}
+
+#[command]
+pub fn set_storage_policy(policy: StoragePolicy) -> Result<(), String> {
+    policy.save()
+}
+
+/// Evict local originals the policy does not keep.
+#[command(async)]
+pub fn apply_storage_policy() -> Result<storage::EvictionReport, String> {
+    storage::apply_policy()
+}
+
+/// Base64 of a memory's decrypted original, fetched from a peer or IPFS
+/// first when it was evicted.
+#[command]
+pub async fn open_memory_media(p2p: State<'_, P2pState>, memory_id: String) -> Result<String, String> {
+    let bytes = storage::open_original(&p2p, &memory_id).await?;
+    Ok(STANDARD.encode(bytes))
+}
+
+/// Base64 JPEG thumbnail of an image memory.
+#[command]
+pub fn get_memory_thumbnail(memory_id: String) -> Result<String, String> {
+    Ok(STANDARD.encode(storage::thumbnail(&memory_id)?))
+}
+
+// ----------- Backup ------------
+
+/// Write the vault to a single archive; with `base`, only blobs changed
+/// since that earlier backup are stored. The database is sealed with
+/// `passphrase`.
+#[command(async)]
+pub fn export_backup(path: String, base: Option<String>, passphrase: String) -> Result<backup::Manifest, String> {
+    backup::export_backup(
+        std::path::Path::new(&path),
+        base.as_deref().map(std::path::Path::new),
+        &passphrase,
+    )
+}
+
+#[command]
+pub fn backup_manifest(path: String) -> Result<backup::Manifest, String> {
+    backup::read_manifest(std::path::Path::new(&path))
+}
+
+/// Verify a backup, and the backups it builds on, then restore it.
+#[command(async)]
+pub fn restore_backup(
+    path: String,
+    bases: Option<Vec<String>>,
+    mode: RestoreMode,
+    passphrase: String,
+) -> Result<backup::RestoreReport, String> {
+    let bases: Vec<std::path::PathBuf> = bases.unwrap_or_default().into_iter().map(Into::into).collect();
+    backup::restore_backup(std::path::Path::new(&path), &bases, mode, &passphrase)
+}
+
+// ----------- Vault integrity ------------
+
+/// Check the database against the blob store; `deep` also decrypts every
+/// blob.
+#[command(async)]
+pub fn verify_vault(deep: bool) -> Result<fsck::VaultReport, String> {
+    fsck::verify_vault(deep)
+}
+
+/// Apply repairs chosen from a `verify_vault` report.
+#[command]
+pub async fn repair_vault(p2p: State<'_, P2pState>, repairs: Vec<fsck::Repair>) -> Result<fsck::RepairReport, String> {
+    Ok(fsck::repair(&p2p, repairs).await)
+}
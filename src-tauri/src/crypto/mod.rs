@@ -3,7 +3,7 @@ use aes_gcm::{Aes256Gcm, Nonce}; // Or `Aes128Gcm`
 use aes_gcm::aead::rand_core::RngCore;
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::path::Path;
 use base64::{engine::general_purpose, Engine};
 use aes::Aes256;
@@ -13,6 +13,8 @@ use sha2::{Digest, Sha256};
 use rand::{RngCore, rngs::OsRng};
 use base64::{encode as b64encode, decode as b64decode};
 
+use crate::vault;
+
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
 const NONCE_SIZE: usize = 12;
 
@@ -40,8 +42,7 @@ pub fn encrypt_file(file_path: &str, key: &[u8; 32]) -> Result<String, String> {
 
     // Write to new file
     let encrypted_path = format!("{}.enc", file_path);
-    let mut encrypted_file = File::create(&encrypted_path).map_err(|e| e.to_string())?;
-    encrypted_file.write_all(&encrypted_data).map_err(|e| e.to_string())?;
+    vault::write_atomic(Path::new(&encrypted_path), &encrypted_data).map_err(|e| e.to_string())?;
 
     Ok(encrypted_path)
 }
@@ -68,18 +69,65 @@ pub fn decrypt_file(encrypted_path: &str, key: &[u8; 32]) -> Result<String, Stri
 
     // Write to new file
     let decrypted_path = encrypted_path.trim_end_matches(".enc").to_string() + ".dec";
-    let mut decrypted_file = File::create(&decrypted_path).map_err(|e| e.to_string())?;
-    decrypted_file.write_all(&plaintext).map_err(|e| e.to_string())?;
+    vault::write_atomic(Path::new(&decrypted_path), &plaintext).map_err(|e| e.to_string())?;
 
     Ok(decrypted_path)
 }
 
+/// Encrypts a buffer in memory using AES-256-GCM.
+/// Output layout matches `encrypt_file`: nonce followed by ciphertext.
+pub fn encrypt_bytes(plaintext: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|e| e.to_string())?;
+
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher.encrypt(nonce, plaintext).map_err(|e| e.to_string())?;
+
+    let mut encrypted_data = nonce_bytes.to_vec();
+    encrypted_data.extend(ciphertext);
+    Ok(encrypted_data)
+}
+
+/// Decrypts a buffer produced by `encrypt_bytes` (or read from an `.enc` file).
+pub fn decrypt_bytes(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|e| e.to_string())?;
+
+    if encrypted_data.len() < NONCE_SIZE {
+        return Err("Invalid encrypted data".into());
+    }
+
+    let nonce = Nonce::from_slice(&encrypted_data[..NONCE_SIZE]);
+    cipher
+        .decrypt(nonce, &encrypted_data[NONCE_SIZE..])
+        .map_err(|e| e.to_string())
+}
+
+/// Decode a base64 key as produced by `generate_key_base64`.
+pub fn decode_key(key_b64: &str) -> Result<[u8; 32], String> {
+    let bytes = general_purpose::STANDARD
+        .decode(key_b64)
+        .map_err(|e| format!("Base64 decode failed: {:?}", e))?;
+    bytes
+        .try_into()
+        .map_err(|_| "Key must be 32 bytes".to_string())
+}
+
 /// Generate a random 256-bit key for AES-GCM encryption (for testing/demo)
 pub fn generate_key_base64() -> String {
     let mut key = [0u8; 32];
     OsRng.fill_bytes(&mut key);
     general_purpose::STANDARD.encode(key)
 }
+
+/// Derive a 256-bit key from a passphrase with PBKDF2-HMAC-SHA256.
+pub fn derive_passphrase_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
 fn derive_key(key: &str) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(key.as_bytes());
@@ -1,5 +1,8 @@
 use rusqlite::{params, Connection, Result as SqlResult};
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::crypto;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Memory {
@@ -15,6 +18,13 @@ pub struct Memory {
     Connection::open("aethersync.db")
 }
 
+/// Make `dir` the working directory. The database, media store and other
+/// relative paths all resolve against it, so this switches the whole vault.
+pub fn use_data_dir(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    std::env::set_current_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
 pub fn init_db() -> Result<(), String> {
     let conn = get_connection().map_err(|e| e.to_string())?;
     conn.execute(
@@ -29,12 +39,368 @@ pub fn init_db() -> Result<(), String> {
         [],
     )
     .map_err(|e| e.to_string())?;
+    add_column_if_missing(&conn, "memories", "key_encrypted", "TEXT")?;
+    add_column_if_missing(&conn, "memories", "transcription", "TEXT")?;
+    add_column_if_missing(&conn, "memories", "captured_at", "TEXT")?;
+    add_column_if_missing(&conn, "memories", "language", "TEXT")?;
+    add_column_if_missing(&conn, "memories", "translation", "TEXT")?;
+    add_column_if_missing(&conn, "memories", "versions", "TEXT")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_memories_captured_at ON memories (captured_at)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Memory map: one location per memory, indexed by an R*Tree for bbox queries
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS memory_locations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            memory_id TEXT NOT NULL UNIQUE,
+            latitude REAL NOT NULL,
+            longitude REAL NOT NULL,
+            source TEXT NOT NULL,
+            place_name TEXT,
+            region TEXT,
+            country TEXT
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS memory_locations_rtree USING rtree(
+            id,
+            min_lat, max_lat,
+            min_lon, max_lon
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Face detection: clusters group faces of the same person; embeddings are
+    // encrypted with the key of the memory they were found in.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS face_clusters (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS faces (
+            id TEXT PRIMARY KEY,
+            memory_id TEXT NOT NULL,
+            cluster_id INTEGER,
+            bbox_x REAL NOT NULL,
+            bbox_y REAL NOT NULL,
+            bbox_width REAL NOT NULL,
+            bbox_height REAL NOT NULL,
+            score REAL NOT NULL,
+            embedding BLOB NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_faces_cluster ON faces (cluster_id)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_faces_memory ON faces (memory_id)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // People tagging, independent of face detection
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS people (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            relationship TEXT,
+            birth_date TEXT,
+            avatar_memory_id TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    add_column_if_missing(&conn, "people", "default_language", "TEXT")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS memory_people (
+            memory_id TEXT NOT NULL,
+            person_id TEXT NOT NULL,
+            region_x REAL,
+            region_y REAL,
+            region_width REAL,
+            region_height REAL,
+            PRIMARY KEY (memory_id, person_id)
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_memory_people_person ON memory_people (person_id)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Application settings, stored as JSON sections keyed by name
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Timed transcript segments; text and word timings are encrypted per memory
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS transcript_segments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            memory_id TEXT NOT NULL,
+            segment_index INTEGER NOT NULL,
+            start_ms INTEGER NOT NULL,
+            end_ms INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            confidence REAL,
+            speaker TEXT,
+            words TEXT NOT NULL,
+            UNIQUE (memory_id, segment_index)
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Persistent background jobs (transcription), retried with backoff
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS jobs (
+            id TEXT PRIMARY KEY,
+            kind TEXT NOT NULL,
+            memory_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            progress REAL NOT NULL DEFAULT 0,
+            last_error TEXT,
+            next_run_at TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_jobs_status ON jobs (status, next_run_at)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // History of manual transcript corrections, encrypted like the segments
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS transcript_edits (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            memory_id TEXT NOT NULL,
+            segment_index INTEGER NOT NULL,
+            previous_text TEXT NOT NULL,
+            new_text TEXT NOT NULL,
+            edited_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Last synced state of every file and directory in a sync folder pair
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_index (
+            pair_id TEXT NOT NULL,
+            path TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            hash TEXT NOT NULL,
+            source_mtime INTEGER NOT NULL,
+            target_mtime INTEGER NOT NULL,
+            version INTEGER NOT NULL DEFAULT 1,
+            PRIMARY KEY (pair_id, path)
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    add_column_if_missing(&conn, "sync_index", "kind", "TEXT NOT NULL DEFAULT 'file'")?;
+
+    // Concurrent edits kept side by side instead of overwritten
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_conflicts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            pair_id TEXT NOT NULL,
+            path TEXT NOT NULL,
+            conflict_path TEXT NOT NULL,
+            detected_at TEXT NOT NULL,
+            resolved INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Devices paired for peer-to-peer sync, identified by their long-term key
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS devices (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            public_key TEXT NOT NULL UNIQUE,
+            last_address TEXT,
+            paired_at TEXT NOT NULL,
+            last_seen_at TEXT
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    // 'member' devices get memory keys; 'relay' devices only store ciphertext
+    add_column_if_missing(&conn, "devices", "role", "TEXT NOT NULL DEFAULT 'member'")?;
+
+    // Hash of each memory's encrypted blob, reused while the file is unchanged
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS replica_blobs (
+            memory_id TEXT PRIMARY KEY,
+            hash TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            mtime INTEGER NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Metadata edits as CRDT operations, folded into the memories row
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS memory_ops (
+            id TEXT PRIMARY KEY,
+            memory_id TEXT NOT NULL,
+            device_id TEXT NOT NULL,
+            lamport INTEGER NOT NULL,
+            payload TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_memory_ops_memory ON memory_ops (memory_id)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    add_column_if_missing(&conn, "memories", "collections", "TEXT")?;
+    add_column_if_missing(&conn, "memories", "deleted_at", "TEXT")?;
+
+    // CIDs of encrypted blobs added to the IPFS node
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ipfs_pins (
+            memory_id TEXT PRIMARY KEY,
+            cid TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            pinned_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    add_column_if_missing(&conn, "ipfs_pins", "hash", "TEXT")?;
+
+    // Sync problems and batch summaries for the settings screen
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            profile_id TEXT,
+            level TEXT NOT NULL,
+            message TEXT NOT NULL,
+            path TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Named folder pairs, each synced by its own worker
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_profiles (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            source TEXT NOT NULL,
+            target TEXT NOT NULL,
+            direction TEXT NOT NULL DEFAULT 'two_way',
+            schedule_minutes INTEGER,
+            include TEXT NOT NULL DEFAULT '[]',
+            exclude TEXT NOT NULL DEFAULT '[]',
+            bandwidth_limit INTEGER,
+            enabled INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    add_column_if_missing(&conn, "sync_log", "profile_id", "TEXT")?;
+
+    // Devices known to hold each encrypted blob, so originals can be evicted
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS blob_replicas (
+            memory_id TEXT NOT NULL,
+            device_id TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            seen_at TEXT NOT NULL,
+            PRIMARY KEY (memory_id, device_id)
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    add_column_if_missing(&conn, "memories", "evicted_at", "TEXT")?;
+    Ok(())
+}
+
+/// Add a column to an existing table, for databases created by older versions.
+pub fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])
+            .map_err(|e| e.to_string())?;
+    }
     Ok(())
 }
 
+/// Return the per-memory AES key, generating and storing one on first use.
+pub fn get_memory_key(memory_id: &str) -> Result<[u8; 32], String> {
+    let conn = get_connection().map_err(|e| e.to_string())?;
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT key_encrypted FROM memories WHERE id = ?1",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    match stored {
+        Some(key) if !key.is_empty() => crypto::decode_key(&key),
+        _ => {
+            let key = crypto::generate_key_base64();
+            conn.execute(
+                "UPDATE memories SET key_encrypted = ?1 WHERE id = ?2",
+                params![key, memory_id],
+            )
+            .map_err(|e| e.to_string())?;
+            crypto::decode_key(&key)
+        }
+    }
+}
+
 pub fn get_all_memories() -> Result<Vec<Memory>, String> {
     let conn = get_connection().map_err(|e| e.to_string())?;
-    let mut stmt = conn.prepare("SELECT id, title, tags, created_at, media_type, filename FROM memories")
+    let mut stmt = conn.prepare("SELECT id, title, tags, created_at, media_type, filename FROM memories WHERE deleted_at IS NULL")
         .map_err(|e| e.to_string())?;
 
     let rows = stmt
@@ -97,3 +463,49 @@ pub fn add_memory(memory: Memory) -> Result<(), String> {
     .map_err(|e| e.to_string())?;
     Ok(())
 }
+
+/// Helpers for tests that need a database and media store on disk.
+#[cfg(test)]
+pub mod test_support {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Mutex, MutexGuard};
+
+    /// The database and media store live in the working directory, so
+    /// tests using them take turns.
+    static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+    /// An empty, initialized vault in a fresh temp directory that is the
+    /// working directory until dropped.
+    pub struct TempVault {
+        dir: PathBuf,
+        previous: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl TempVault {
+        pub fn new() -> Self {
+            let lock = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let dir = std::env::temp_dir().join(format!("aethersync-test-{}", uuid::Uuid::new_v4()));
+            let previous = std::env::current_dir().unwrap();
+            super::use_data_dir(&dir).unwrap();
+            std::fs::create_dir_all(crate::vault::MEDIA_DIR).unwrap();
+            super::init_db().unwrap();
+            Self {
+                dir,
+                previous,
+                _lock: lock,
+            }
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.dir
+        }
+    }
+
+    impl Drop for TempVault {
+        fn drop(&mut self) {
+            let _ = std::env::set_current_dir(&self.previous);
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
@@ -0,0 +1,661 @@
+use chrono::Utc;
+use image::imageops::FilterType;
+use image::RgbImage;
+use ndarray::Array4;
+use ort::{GraphOptimizationLevel, Session};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::crypto;
+use crate::db;
+use crate::media;
+use crate::vault::MEDIA_DIR;
+
+const DETECTOR_FILE: &str = "detector.onnx";
+const EMBEDDER_FILE: &str = "embedder.onnx";
+
+/// Where the models live and how aggressively faces are detected and grouped.
+/// The detector is expected to follow the UltraFace layout (`scores` [1, N, 2]
+/// and normalized corner `boxes` [1, N, 4]); the embedder maps an aligned
+/// face crop to a single embedding vector (FaceNet style).
+#[derive(Debug, Clone)]
+pub struct FaceConfig {
+    pub model_dir: PathBuf,
+    pub detector_input: (u32, u32), // (width, height)
+    pub embedder_input: (u32, u32),
+    pub detection_threshold: f32,
+    pub nms_iou_threshold: f32,
+    /// Maximum cosine distance for two faces to be considered neighbours.
+    pub cluster_eps: f32,
+    /// Faces needed before an unassigned group becomes a new cluster.
+    pub min_cluster_size: usize,
+}
+
+impl FaceConfig {
+    pub fn from_dir(model_dir: impl AsRef<Path>) -> Self {
+        Self {
+            model_dir: model_dir.as_ref().to_path_buf(),
+            detector_input: (320, 240),
+            embedder_input: (160, 160),
+            detection_threshold: 0.7,
+            nms_iou_threshold: 0.3,
+            cluster_eps: 0.45,
+            min_cluster_size: 2,
+        }
+    }
+}
+
+/// Bounding box in pixel coordinates of the original image.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FaceBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Face {
+    pub id: String,
+    pub memory_id: String,
+    pub cluster_id: Option<i64>,
+    pub bbox: FaceBox,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FaceCluster {
+    pub id: i64,
+    pub name: Option<String>,
+    pub face_count: i64,
+    pub cover_memory_id: Option<String>,
+}
+
+struct IndexedFace {
+    id: String,
+    memory_id: String,
+    cluster_id: Option<i64>,
+    embedding: Vec<f32>,
+}
+
+/// Loaded ONNX sessions plus an in-memory copy of the decrypted embeddings
+/// used for incremental clustering.
+pub struct FaceEngine {
+    config: FaceConfig,
+    detector: Session,
+    embedder: Session,
+    index: Option<Vec<IndexedFace>>,
+}
+
+/// Managed Tauri state; the engine is loaded on first use.
+pub struct FaceState {
+    pub config: Mutex<FaceConfig>,
+    pub engine: Mutex<Option<FaceEngine>>,
+}
+
+impl FaceState {
+    pub fn new(config: FaceConfig) -> Self {
+        Self {
+            config: Mutex::new(config),
+            engine: Mutex::new(None),
+        }
+    }
+
+    /// Point the engine at a different model directory; models are reloaded lazily.
+    /// Never holds `config` while taking `engine`: `scan_memory` takes them
+    /// the other way round.
+    pub fn set_model_dir(&self, model_dir: &str) {
+        self.config.lock().unwrap().model_dir = PathBuf::from(model_dir);
+        *self.engine.lock().unwrap() = None;
+    }
+
+    /// Detect, embed and cluster every face in a stored image memory.
+    pub fn scan_memory(&self, memory_id: &str) -> Result<Vec<Face>, String> {
+        let mut guard = self.engine.lock().unwrap();
+        if guard.is_none() {
+            let config = self.config.lock().unwrap().clone();
+            *guard = Some(FaceEngine::load(config)?);
+        }
+        guard.as_mut().unwrap().scan_memory(memory_id)
+    }
+
+    /// Drop cached cluster assignments after manual edits.
+    fn invalidate_index(&self) {
+        if let Some(engine) = self.engine.lock().unwrap().as_mut() {
+            engine.index = None;
+        }
+    }
+}
+
+impl FaceEngine {
+    pub fn load(config: FaceConfig) -> Result<Self, String> {
+        let detector = load_session(&config.model_dir.join(DETECTOR_FILE))?;
+        let embedder = load_session(&config.model_dir.join(EMBEDDER_FILE))?;
+        Ok(Self {
+            config,
+            detector,
+            embedder,
+            index: None,
+        })
+    }
+
+    pub fn scan_memory(&mut self, memory_id: &str) -> Result<Vec<Face>, String> {
+        let memory = db::get_memory_by_id(memory_id.to_string())?;
+        if !memory.media_type.starts_with("image/") {
+            return Err("Face detection only supports images".into());
+        }
+
+        let key = db::get_memory_key(memory_id)?;
+        let bytes = media::load_media_bytes(Path::new(MEDIA_DIR), &memory.filename, &key)?;
+        let image = image::load_from_memory(&bytes)
+            .map_err(|e| e.to_string())?
+            .to_rgb8();
+
+        let conn = db::get_connection().map_err(|e| e.to_string())?;
+        self.ensure_index(&conn)?;
+        self.remove_memory_faces(&conn, memory_id)?;
+
+        let mut faces = Vec::new();
+        for (bbox, score) in self.detect(&image)? {
+            let embedding = self.embed(&image, &bbox)?;
+            let face = self.store_face(&conn, memory_id, &key, bbox, score, embedding)?;
+            faces.push(face);
+        }
+        Ok(faces)
+    }
+
+    fn detect(&self, image: &RgbImage) -> Result<Vec<(FaceBox, f32)>, String> {
+        let (width, height) = self.config.detector_input;
+        let input = to_tensor(image, width, height, 127.0, 128.0);
+
+        let outputs = self
+            .detector
+            .run(ort::inputs![input].map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
+        let scores = outputs[0].try_extract_tensor::<f32>().map_err(|e| e.to_string())?;
+        let boxes = outputs[1].try_extract_tensor::<f32>().map_err(|e| e.to_string())?;
+
+        let scores = scores.as_slice().ok_or("Non-contiguous detector scores")?;
+        let boxes = boxes.as_slice().ok_or("Non-contiguous detector boxes")?;
+
+        let (img_w, img_h) = (image.width() as f32, image.height() as f32);
+        let mut candidates: Vec<(FaceBox, f32)> = scores
+            .chunks(2)
+            .zip(boxes.chunks(4))
+            .filter(|(score, _)| score[1] >= self.config.detection_threshold)
+            .map(|(score, b)| {
+                let x1 = b[0].clamp(0.0, 1.0) * img_w;
+                let y1 = b[1].clamp(0.0, 1.0) * img_h;
+                let x2 = b[2].clamp(0.0, 1.0) * img_w;
+                let y2 = b[3].clamp(0.0, 1.0) * img_h;
+                (
+                    FaceBox {
+                        x: x1,
+                        y: y1,
+                        width: (x2 - x1).max(1.0),
+                        height: (y2 - y1).max(1.0),
+                    },
+                    score[1],
+                )
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        let mut kept: Vec<(FaceBox, f32)> = Vec::new();
+        for candidate in candidates {
+            if kept
+                .iter()
+                .all(|(k, _)| iou(k, &candidate.0) < self.config.nms_iou_threshold)
+            {
+                kept.push(candidate);
+            }
+        }
+        Ok(kept)
+    }
+
+    fn embed(&self, image: &RgbImage, bbox: &FaceBox) -> Result<Vec<f32>, String> {
+        let crop = image::imageops::crop_imm(
+            image,
+            bbox.x as u32,
+            bbox.y as u32,
+            bbox.width as u32,
+            bbox.height as u32,
+        )
+        .to_image();
+
+        let (width, height) = self.config.embedder_input;
+        let input = to_tensor(&crop, width, height, 127.5, 128.0);
+
+        let outputs = self
+            .embedder
+            .run(ort::inputs![input].map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
+        let embedding = outputs[0].try_extract_tensor::<f32>().map_err(|e| e.to_string())?;
+
+        Ok(normalize(embedding.iter().copied().collect()))
+    }
+
+    fn ensure_index(&mut self, conn: &Connection) -> Result<(), String> {
+        if self.index.is_some() {
+            return Ok(());
+        }
+
+        let mut stmt = conn
+            .prepare("SELECT id, memory_id, cluster_id, embedding FROM faces")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut index = Vec::new();
+        for row in rows {
+            let (id, memory_id, cluster_id, encrypted) = row.map_err(|e| e.to_string())?;
+            let key = db::get_memory_key(&memory_id)?;
+            let embedding = bytes_to_embedding(&crypto::decrypt_bytes(&encrypted, &key)?);
+            index.push(IndexedFace {
+                id,
+                memory_id,
+                cluster_id,
+                embedding,
+            });
+        }
+        self.index = Some(index);
+        Ok(())
+    }
+
+    fn remove_memory_faces(&mut self, conn: &Connection, memory_id: &str) -> Result<(), String> {
+        conn.execute("DELETE FROM faces WHERE memory_id = ?1", params![memory_id])
+            .map_err(|e| e.to_string())?;
+        if let Some(index) = self.index.as_mut() {
+            index.retain(|f| f.memory_id != memory_id);
+        }
+        Ok(())
+    }
+
+    /// Insert a face and assign it incrementally, DBSCAN style: join the
+    /// cluster most represented among its neighbours, pulling unassigned
+    /// neighbours along; otherwise start a new cluster once enough
+    /// unassigned neighbours exist; otherwise leave it as noise.
+    fn store_face(
+        &mut self,
+        conn: &Connection,
+        memory_id: &str,
+        key: &[u8; 32],
+        bbox: FaceBox,
+        score: f32,
+        embedding: Vec<f32>,
+    ) -> Result<Face, String> {
+        let eps = self.config.cluster_eps;
+        let min_size = self.config.min_cluster_size;
+        let index = self.index.as_mut().ok_or("Face index not loaded")?;
+
+        let neighbours: Vec<(usize, f32)> = index
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (i, cosine_distance(&f.embedding, &embedding)))
+            .filter(|(_, distance)| *distance <= eps)
+            .collect();
+
+        let mut votes: Vec<(i64, usize, f32)> = Vec::new(); // (cluster, count, best distance)
+        for (i, distance) in &neighbours {
+            if let Some(cluster_id) = index[*i].cluster_id {
+                match votes.iter_mut().find(|v| v.0 == cluster_id) {
+                    Some(v) => {
+                        v.1 += 1;
+                        v.2 = v.2.min(*distance);
+                    }
+                    None => votes.push((cluster_id, 1, *distance)),
+                }
+            }
+        }
+        votes.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then(a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal))
+        });
+
+        let cluster_id = if let Some((cluster_id, _, _)) = votes.first() {
+            Some(*cluster_id)
+        } else if neighbours.len() + 1 >= min_size {
+            conn.execute(
+                "INSERT INTO face_clusters (name, created_at) VALUES (NULL, ?1)",
+                params![Utc::now().to_rfc3339()],
+            )
+            .map_err(|e| e.to_string())?;
+            Some(conn.last_insert_rowid())
+        } else {
+            None
+        };
+
+        if let Some(cluster_id) = cluster_id {
+            for (i, _) in &neighbours {
+                if index[*i].cluster_id.is_none() {
+                    index[*i].cluster_id = Some(cluster_id);
+                    conn.execute(
+                        "UPDATE faces SET cluster_id = ?1 WHERE id = ?2",
+                        params![cluster_id, index[*i].id],
+                    )
+                    .map_err(|e| e.to_string())?;
+                }
+            }
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let encrypted = crypto::encrypt_bytes(&embedding_to_bytes(&embedding), key)?;
+        conn.execute(
+            "INSERT INTO faces (id, memory_id, cluster_id, bbox_x, bbox_y, bbox_width, bbox_height, score, embedding, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                id,
+                memory_id,
+                cluster_id,
+                bbox.x,
+                bbox.y,
+                bbox.width,
+                bbox.height,
+                score,
+                encrypted,
+                Utc::now().to_rfc3339()
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+
+        index.push(IndexedFace {
+            id: id.clone(),
+            memory_id: memory_id.to_string(),
+            cluster_id,
+            embedding,
+        });
+
+        Ok(Face {
+            id,
+            memory_id: memory_id.to_string(),
+            cluster_id,
+            bbox,
+            score,
+        })
+    }
+}
+
+fn load_session(path: &Path) -> Result<Session, String> {
+    if !path.exists() {
+        return Err(format!("Model '{}' not found", path.display()));
+    }
+    Session::builder()
+        .and_then(|b| b.with_optimization_level(GraphOptimizationLevel::Level3))
+        .and_then(|b| b.commit_from_file(path))
+        .map_err(|e| format!("Failed to load model '{}': {}", path.display(), e))
+}
+
+/// Resize to the model input and convert to a normalized NCHW tensor.
+fn to_tensor(image: &RgbImage, width: u32, height: u32, mean: f32, scale: f32) -> Array4<f32> {
+    let resized = image::imageops::resize(image, width, height, FilterType::Triangle);
+    let mut tensor = Array4::<f32>::zeros((1, 3, height as usize, width as usize));
+    for (x, y, pixel) in resized.enumerate_pixels() {
+        for c in 0..3 {
+            tensor[[0, c, y as usize, x as usize]] = (pixel[c] as f32 - mean) / scale;
+        }
+    }
+    tensor
+}
+
+fn iou(a: &FaceBox, b: &FaceBox) -> f32 {
+    let x1 = a.x.max(b.x);
+    let y1 = a.y.max(b.y);
+    let x2 = (a.x + a.width).min(b.x + b.width);
+    let y2 = (a.y + a.height).min(b.y + b.height);
+    let intersection = (x2 - x1).max(0.0) * (y2 - y1).max(0.0);
+    let union = a.width * a.height + b.width * b.height - intersection;
+    if union <= 0.0 {
+        0.0
+    } else {
+        intersection / union
+    }
+}
+
+fn normalize(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+/// Both vectors are L2-normalized, so cosine distance is 1 - dot product.
+fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>()
+}
+
+fn embedding_to_bytes(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn bytes_to_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+// ----------- Cluster management ------------
+
+pub fn list_clusters() -> Result<Vec<FaceCluster>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT c.id, c.name, COUNT(f.id),
+                    (SELECT memory_id FROM faces WHERE cluster_id = c.id ORDER BY score DESC LIMIT 1)
+             FROM face_clusters c
+             LEFT JOIN faces f ON f.cluster_id = c.id
+             GROUP BY c.id
+             ORDER BY COUNT(f.id) DESC",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let clusters = stmt
+        .query_map([], |row| {
+            Ok(FaceCluster {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                face_count: row.get(2)?,
+                cover_memory_id: row.get(3)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(clusters)
+}
+
+pub fn name_cluster(cluster_id: i64, name: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let updated = conn
+        .execute(
+            "UPDATE face_clusters SET name = ?1 WHERE id = ?2",
+            params![name, cluster_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err("Cluster not found".into());
+    }
+    Ok(())
+}
+
+/// Move every face of `source_id` into `target_id` and delete the source.
+/// The target keeps its name, or inherits the source's if it had none.
+pub fn merge_clusters(state: &FaceState, target_id: i64, source_id: i64) -> Result<(), String> {
+    if target_id == source_id {
+        return Err("Cannot merge a cluster into itself".into());
+    }
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE face_clusters SET name = COALESCE(name, (SELECT name FROM face_clusters WHERE id = ?2))
+         WHERE id = ?1",
+        params![target_id, source_id],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE faces SET cluster_id = ?1 WHERE cluster_id = ?2",
+        params![target_id, source_id],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM face_clusters WHERE id = ?1", params![source_id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    state.invalidate_index();
+    Ok(())
+}
+
+/// Move the given faces out of their cluster into a new, unnamed one.
+pub fn split_cluster(state: &FaceState, cluster_id: i64, face_ids: Vec<String>) -> Result<i64, String> {
+    if face_ids.is_empty() {
+        return Err("No faces selected".into());
+    }
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT INTO face_clusters (name, created_at) VALUES (NULL, ?1)",
+        params![Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| e.to_string())?;
+    let new_id = tx.last_insert_rowid();
+
+    for face_id in &face_ids {
+        let moved = tx
+            .execute(
+                "UPDATE faces SET cluster_id = ?1 WHERE id = ?2 AND cluster_id = ?3",
+                params![new_id, face_id, cluster_id],
+            )
+            .map_err(|e| e.to_string())?;
+        if moved == 0 {
+            return Err(format!("Face '{}' is not in cluster {}", face_id, cluster_id));
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    state.invalidate_index();
+    Ok(new_id)
+}
+
+pub fn list_cluster_faces(cluster_id: i64) -> Result<Vec<Face>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, memory_id, cluster_id, bbox_x, bbox_y, bbox_width, bbox_height, score
+             FROM faces WHERE cluster_id = ?1",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let faces = stmt
+        .query_map(params![cluster_id], |row| {
+            Ok(Face {
+                id: row.get(0)?,
+                memory_id: row.get(1)?,
+                cluster_id: row.get(2)?,
+                bbox: FaceBox {
+                    x: row.get(3)?,
+                    y: row.get(4)?,
+                    width: row.get(5)?,
+                    height: row.get(6)?,
+                },
+                score: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(faces)
+}
+
+/// Memories containing at least one face from the given cluster.
+pub fn memories_by_cluster(cluster_id: i64) -> Result<Vec<db::Memory>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT DISTINCT m.id, m.title, m.tags, m.created_at, m.media_type, m.filename
+             FROM memories m
+             JOIN faces f ON f.memory_id = m.id
+             WHERE f.cluster_id = ?1 AND m.deleted_at IS NULL
+             ORDER BY COALESCE(m.captured_at, m.created_at)",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let memories = stmt
+        .query_map(params![cluster_id], |row| {
+            Ok(db::Memory {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                tags: row.get(2)?,
+                created_at: row.get(3)?,
+                media_type: row.get(4)?,
+                filename: row.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(memories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tiny models from `tests/fixtures/faces`; see `make_fixtures.py` there.
+    fn fixture_config() -> FaceConfig {
+        FaceConfig::from_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/faces"))
+    }
+
+    #[test]
+    fn loads_models_from_the_configured_dir() {
+        assert!(FaceEngine::load(fixture_config()).is_ok());
+
+        let missing = FaceEngine::load(FaceConfig::from_dir("no-such-model-dir")).err().unwrap();
+        assert!(missing.contains("not found"), "{}", missing);
+    }
+
+    #[test]
+    fn detects_and_embeds_with_fixture_models() {
+        let engine = FaceEngine::load(fixture_config()).unwrap();
+        let image = RgbImage::from_pixel(200, 100, image::Rgb([255, 0, 0]));
+
+        // The fixture detector reports a centred face and one below threshold
+        let faces = engine.detect(&image).unwrap();
+        assert_eq!(faces.len(), 1);
+        let (bbox, score) = faces[0];
+        assert!((score - 0.9).abs() < 1e-6);
+        assert_eq!((bbox.x, bbox.y, bbox.width, bbox.height), (50.0, 25.0, 100.0, 50.0));
+
+        // The fixture embedder averages each channel: red up, green and blue down
+        let embedding = engine.embed(&image, &bbox).unwrap();
+        let expected = 1.0 / 3f32.sqrt();
+        assert_eq!(embedding.len(), 3);
+        assert!((embedding[0] - expected).abs() < 1e-4);
+        assert!((embedding[1] + expected).abs() < 1e-4);
+        assert!((embedding[2] + expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn set_model_dir_drops_the_loaded_engine() {
+        let state = FaceState::new(fixture_config());
+        *state.engine.lock().unwrap() = Some(FaceEngine::load(fixture_config()).unwrap());
+
+        state.set_model_dir("elsewhere");
+        assert!(state.engine.lock().unwrap().is_none());
+        assert_eq!(state.config.lock().unwrap().model_dir, PathBuf::from("elsewhere"));
+    }
+}
@@ -0,0 +1,565 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::p2p::P2pState;
+use crate::syncthing::{self, SyncthingSettings};
+use crate::{crypto, db, oplog, storage, vault};
+use crate::vault::MEDIA_DIR;
+
+const PARTIAL_DIR: &str = ".partial";
+/// Where repairs move suspect files, next to the database rather than in
+/// `media_store` so nothing syncs them.
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// Tables whose rows belong to a memory and mean nothing without it.
+/// Ops are left out: they may arrive before the memory itself.
+const MEMORY_TABLES: &[&str] = &[
+    "memory_locations",
+    "faces",
+    "memory_people",
+    "transcript_segments",
+    "transcript_edits",
+    "jobs",
+    "replica_blobs",
+    "ipfs_pins",
+    "blob_replicas",
+];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// The original is neither on disk nor evicted.
+    MissingBlob { memory_id: String, filename: String },
+    /// Evicted, but no other copy is known to fetch it back from.
+    LostOriginal { memory_id: String, filename: String },
+    /// Sealed media whose memory has no usable key. A row synced through
+    /// a relay gets its key from the next member device it syncs with.
+    MissingKey { memory_id: String },
+    /// The ciphertext failed authentication.
+    Corrupt { memory_id: String, file: String, error: String },
+    /// The blob's bytes changed although its size and modification time
+    /// did not.
+    HashMismatch {
+        memory_id: String,
+        file: String,
+        expected: String,
+        actual: String,
+    },
+    /// A file no memory refers to.
+    Orphaned { file: String, size: u64 },
+    /// A temp file or partial download nothing is going to finish.
+    Leftover { file: String, size: u64 },
+    /// Rows for memories that no longer exist.
+    DanglingRows { table: String, count: usize },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Repair {
+    /// Fetch the original again from a paired device or IPFS, moving a
+    /// damaged copy to quarantine first.
+    Refetch { memory_id: String },
+    /// Move a file (relative to `media_store`) to the quarantine folder.
+    Quarantine { file: String },
+    /// Delete a memory whose media cannot be recovered.
+    DeleteMemory { memory_id: String },
+    RemoveDanglingRows { table: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub problem: Problem,
+    /// Repairs that resolve the problem, preferred first.
+    pub repairs: Vec<Repair>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VaultReport {
+    pub deep: bool,
+    pub memories_checked: usize,
+    pub files_checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RepairReport {
+    pub applied: usize,
+    pub errors: Vec<String>,
+}
+
+/// Cross-check the memories table against `media_store`: originals that
+/// are missing or lost, files nothing refers to, stale temp files and
+/// partial downloads, and rows left behind by deleted memories. A `deep`
+/// check also decrypts every blob to verify its authentication tag and
+/// compares it with the hash recorded when it was last seen. Nothing is
+/// changed; see `repair`.
+pub fn verify_vault(deep: bool) -> Result<VaultReport, String> {
+    let media_dir = Path::new(MEDIA_DIR);
+    let files = list_files(media_dir)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut report = VaultReport {
+        deep,
+        files_checked: files.len(),
+        ..Default::default()
+    };
+
+    let mut stmt = conn
+        .prepare("SELECT id, filename, evicted_at, deleted_at, key_encrypted FROM memories")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut claimed = HashSet::new();
+    // Hashes of blobs still to be fetched, whose partial downloads resume
+    let mut wanted = HashSet::new();
+    for (memory_id, filename, evicted_at, deleted_at, key) in rows {
+        report.memories_checked += 1;
+        if filename.is_empty() {
+            continue;
+        }
+        let sealed = format!("{}.enc", filename);
+        let thumbnail = format!("{}.thumb.enc", filename);
+        let has_sealed = files.contains_key(&sealed);
+        let has_plain = files.contains_key(&filename);
+        let has_thumbnail = files.contains_key(&thumbnail);
+        claimed.extend([filename.clone(), sealed.clone(), thumbnail.clone()]);
+        // Deleted memories keep their files until a purge; nothing to check
+        if deleted_at.is_some() {
+            continue;
+        }
+
+        if !has_sealed && !has_plain {
+            let known = storage::known_hash(&memory_id)?;
+            let delete = Repair::DeleteMemory {
+                memory_id: memory_id.clone(),
+            };
+            match (&known, evicted_at.is_some()) {
+                (Some(_), true) => {}
+                (Some(_), false) => report.findings.push(Finding {
+                    problem: Problem::MissingBlob {
+                        memory_id: memory_id.clone(),
+                        filename: filename.clone(),
+                    },
+                    repairs: vec![
+                        Repair::Refetch {
+                            memory_id: memory_id.clone(),
+                        },
+                        delete,
+                    ],
+                }),
+                (None, evicted) => report.findings.push(Finding {
+                    problem: if evicted {
+                        Problem::LostOriginal {
+                            memory_id: memory_id.clone(),
+                            filename: filename.clone(),
+                        }
+                    } else {
+                        Problem::MissingBlob {
+                            memory_id: memory_id.clone(),
+                            filename: filename.clone(),
+                        }
+                    },
+                    repairs: vec![delete],
+                }),
+            }
+            wanted.extend(known);
+        }
+
+        if !has_sealed && !has_thumbnail {
+            continue;
+        }
+        let key = match key.filter(|k| !k.is_empty()).map(|k| crypto::decode_key(&k)) {
+            Some(Ok(key)) => key,
+            _ => {
+                report.findings.push(Finding {
+                    problem: Problem::MissingKey { memory_id },
+                    repairs: Vec::new(),
+                });
+                continue;
+            }
+        };
+        if !deep {
+            continue;
+        }
+        if has_sealed {
+            let cached = cached_hash(&conn, &memory_id, &files[&sealed])?;
+            report
+                .findings
+                .extend(check_blob(&memory_id, &sealed, &key, cached.as_deref(), true)?);
+        }
+        if has_thumbnail {
+            report
+                .findings
+                .extend(check_blob(&memory_id, &thumbnail, &key, None, false)?);
+        }
+    }
+
+    let stale_before = SystemTime::now() - vault::TEMP_GRACE;
+    for (file, meta) in &files {
+        let path = media_dir.join(file);
+        let leftover = if let Some(hash) = file.strip_prefix(&format!("{}/", PARTIAL_DIR)) {
+            !wanted.contains(hash)
+        } else if vault::is_temp_file(&path) {
+            meta.modified().map_or(true, |at| at < stale_before)
+        } else {
+            false
+        };
+        // Other hidden files belong to other tools, like Syncthing's .stignore
+        let problem = if leftover {
+            Problem::Leftover {
+                file: file.clone(),
+                size: meta.len(),
+            }
+        } else if !claimed.contains(file) && !file.starts_with('.') {
+            Problem::Orphaned {
+                file: file.clone(),
+                size: meta.len(),
+            }
+        } else {
+            continue;
+        };
+        report.findings.push(Finding {
+            problem,
+            repairs: vec![Repair::Quarantine { file: file.clone() }],
+        });
+    }
+
+    for table in MEMORY_TABLES {
+        let count: i64 = conn
+            .query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM {} WHERE memory_id NOT IN (SELECT id FROM memories)",
+                    table
+                ),
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if count > 0 {
+            report.findings.push(Finding {
+                problem: Problem::DanglingRows {
+                    table: table.to_string(),
+                    count: count as usize,
+                },
+                repairs: vec![Repair::RemoveDanglingRows {
+                    table: table.to_string(),
+                }],
+            });
+        }
+    }
+
+    // Syncthing would carry a quarantine move to every shared device as a
+    // delete. A blob it brought in may also just be ahead of its memory row.
+    if SyncthingSettings::load()?.shares_vault() {
+        for finding in &mut report.findings {
+            finding
+                .repairs
+                .retain(|repair| !matches!(repair, Repair::Quarantine { file } if syncthing::is_synced(file)));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Apply repairs offered by `verify_vault`. Each is tried on its own, so
+/// one failure does not stop the rest.
+pub async fn repair(p2p: &P2pState, repairs: Vec<Repair>) -> RepairReport {
+    let mut report = RepairReport::default();
+    for repair in repairs {
+        let result = match &repair {
+            Repair::Refetch { memory_id } => refetch(p2p, memory_id).await,
+            Repair::Quarantine { file } => quarantine_unshared(file),
+            // Through the op log, so other devices agree and replication
+            // does not bring the memory back
+            Repair::DeleteMemory { memory_id } => oplog::delete_memory(memory_id).map(|_| ()),
+            Repair::RemoveDanglingRows { table } => remove_dangling_rows(table),
+        };
+        match result {
+            Ok(()) => report.applied += 1,
+            Err(e) => report.errors.push(format!("{:?}: {}", repair, e)),
+        }
+    }
+    report
+}
+
+/// Compare a blob's hash with `expected`, if given, then decrypt it to
+/// check its tag. The hash is streamed from disk; AES-GCM authenticates a
+/// blob as a whole, so only a blob that passed is read into memory.
+fn check_blob(
+    memory_id: &str,
+    file: &str,
+    key: &[u8; 32],
+    expected: Option<&str>,
+    original: bool,
+) -> Result<Option<Finding>, String> {
+    let path = Path::new(MEDIA_DIR).join(file);
+    let mut repairs = Vec::new();
+    if original && storage::known_hash(memory_id)?.is_some() {
+        repairs.push(Repair::Refetch {
+            memory_id: memory_id.to_string(),
+        });
+    }
+    repairs.push(Repair::Quarantine { file: file.to_string() });
+
+    if let Some(expected) = expected {
+        let actual = vault::hash_file(&path).map_err(|e| e.to_string())?;
+        if expected != actual {
+            return Ok(Some(Finding {
+                problem: Problem::HashMismatch {
+                    memory_id: memory_id.to_string(),
+                    file: file.to_string(),
+                    expected: expected.to_string(),
+                    actual,
+                },
+                repairs,
+            }));
+        }
+    }
+    let data = fs::read(&path).map_err(|e| e.to_string())?;
+    match crypto::decrypt_bytes(&data, key) {
+        Ok(_) => Ok(None),
+        Err(error) => Ok(Some(Finding {
+            problem: Problem::Corrupt {
+                memory_id: memory_id.to_string(),
+                file: file.to_string(),
+                error,
+            },
+            repairs,
+        })),
+    }
+}
+
+/// Hash recorded for the blob, if the file still has the size and
+/// modification time it had then.
+fn cached_hash(conn: &Connection, memory_id: &str, meta: &Metadata) -> Result<Option<String>, String> {
+    let cached: Option<(String, i64, i64)> = conn
+        .query_row(
+            "SELECT hash, size, mtime FROM replica_blobs WHERE memory_id = ?1",
+            params![memory_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    Ok(cached
+        .filter(|(_, size, at)| *size == meta.len() as i64 && *at == mtime)
+        .map(|(hash, _, _)| hash))
+}
+
+/// Replace a damaged original with a verified copy from a peer or IPFS.
+/// The damaged blob only moves to quarantine once the copy is on disk.
+async fn refetch(p2p: &P2pState, memory_id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let filename: String = conn
+        .query_row("SELECT filename FROM memories WHERE id = ?1", params![memory_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    drop(conn);
+    let blob = Path::new(MEDIA_DIR).join(format!("{}.enc", filename));
+    if !blob.exists() {
+        return storage::ensure_original(p2p, memory_id).await;
+    }
+
+    let fetched = Path::new(MEDIA_DIR).join(format!(
+        ".{}.enc.{}{}",
+        filename,
+        Uuid::new_v4().simple(),
+        vault::TEMP_SUFFIX
+    ));
+    if let Err(e) = storage::fetch_original(p2p, memory_id, &fetched).await {
+        let _ = fs::remove_file(&fetched);
+        return Err(e);
+    }
+    quarantine(&blob)?;
+    fs::rename(&fetched, &blob).map_err(|e| e.to_string())
+}
+
+fn remove_dangling_rows(table: &str) -> Result<(), String> {
+    if !MEMORY_TABLES.contains(&table) {
+        return Err(format!("Unknown table {}", table));
+    }
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    if table == "memory_locations" {
+        conn.execute(
+            "DELETE FROM memory_locations_rtree WHERE id IN
+                (SELECT id FROM memory_locations WHERE memory_id NOT IN (SELECT id FROM memories))",
+            [],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    conn.execute(
+        &format!("DELETE FROM {} WHERE memory_id NOT IN (SELECT id FROM memories)", table),
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Quarantine a file `verify_vault` found, unless Syncthing shares it.
+fn quarantine_unshared(file: &str) -> Result<(), String> {
+    if syncthing::is_synced(file) && SyncthingSettings::load()?.shares_vault() {
+        return Err("Syncthing shares this file; moving it would delete it on every shared device".into());
+    }
+    media_path(file).and_then(|path| quarantine(&path))
+}
+
+/// Move a file out of the vault, keeping it for inspection.
+fn quarantine(path: &Path) -> Result<(), String> {
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+    fs::create_dir_all(QUARANTINE_DIR).map_err(|e| e.to_string())?;
+    let name = path.file_name().ok_or("Invalid file name")?.to_string_lossy();
+    let destination = Path::new(QUARANTINE_DIR).join(format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%S"), name));
+    fs::rename(path, destination).map_err(|e| e.to_string())
+}
+
+/// Resolve a file name from a report, refusing anything outside
+/// `media_store`.
+fn media_path(file: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(file);
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(format!("Invalid file name: {}", file));
+    }
+    Ok(Path::new(MEDIA_DIR).join(relative))
+}
+
+/// Files in `media_store` and its partial-download folder, by path
+/// relative to it. Other folders are not ours to judge.
+fn list_files(media_dir: &Path) -> Result<HashMap<String, Metadata>, String> {
+    let mut files = HashMap::new();
+    let dirs = [
+        (media_dir.to_path_buf(), String::new()),
+        (media_dir.join(PARTIAL_DIR), format!("{}/", PARTIAL_DIR)),
+    ];
+    for (dir, prefix) in dirs {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let meta = entry.metadata().map_err(|e| e.to_string())?;
+            if meta.is_file() {
+                files.insert(format!("{}{}", prefix, entry.file_name().to_string_lossy()), meta);
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+    use sha2::{Digest, Sha256};
+
+    /// A memory whose original is sealed in `media_store`.
+    fn add_sealed(id: &str, filename: &str) -> Vec<u8> {
+        db::add_memory(db::Memory {
+            id: id.into(),
+            title: "Photo".into(),
+            tags: String::new(),
+            created_at: Utc::now().to_rfc3339(),
+            media_type: "image/jpeg".into(),
+            filename: filename.into(),
+        })
+        .unwrap();
+        let key = crypto::generate_key_base64();
+        let conn = db::get_connection().unwrap();
+        conn.execute("UPDATE memories SET key_encrypted = ?1 WHERE id = ?2", params![key, id])
+            .unwrap();
+        let sealed = crypto::encrypt_bytes(b"jpeg bytes", &crypto::decode_key(&key).unwrap()).unwrap();
+        fs::write(Path::new(MEDIA_DIR).join(format!("{}.enc", filename)), &sealed).unwrap();
+        sealed
+    }
+
+    #[test]
+    fn a_clean_vault_has_no_findings() {
+        let _vault = TempVault::new();
+        add_sealed("m1", "photo.jpg");
+        let report = verify_vault(true).unwrap();
+        assert_eq!(report.memories_checked, 1);
+        assert!(report.findings.is_empty(), "{:?}", report.findings);
+    }
+
+    #[test]
+    fn deep_check_finds_a_truncated_blob() {
+        let _vault = TempVault::new();
+        let sealed = add_sealed("m1", "photo.jpg");
+        fs::write(Path::new(MEDIA_DIR).join("photo.jpg.enc"), &sealed[..sealed.len() - 4]).unwrap();
+
+        assert!(verify_vault(false).unwrap().findings.is_empty());
+        let report = verify_vault(true).unwrap();
+        assert_eq!(report.findings.len(), 1);
+        let finding = &report.findings[0];
+        assert!(matches!(&finding.problem, Problem::Corrupt { file, .. } if file == "photo.jpg.enc"));
+        assert!(matches!(&finding.repairs[..], [Repair::Quarantine { file }] if file == "photo.jpg.enc"));
+    }
+
+    #[test]
+    fn finds_orphaned_files_and_dangling_rows() {
+        let _vault = TempVault::new();
+        add_sealed("m1", "photo.jpg");
+        fs::write(Path::new(MEDIA_DIR).join("stray.jpg.enc"), b"nobody's").unwrap();
+        fs::write(Path::new(MEDIA_DIR).join(".stignore"), b"!*.enc").unwrap();
+        let conn = db::get_connection().unwrap();
+        conn.execute(
+            "INSERT INTO memory_people (memory_id, person_id) VALUES ('gone', 'p1')",
+            [],
+        )
+        .unwrap();
+
+        let report = verify_vault(false).unwrap();
+        assert_eq!(report.findings.len(), 2, "{:?}", report.findings);
+        assert!(report.findings.iter().any(|f| matches!(
+            (&f.problem, &f.repairs[..]),
+            (Problem::Orphaned { file, .. }, [Repair::Quarantine { .. }]) if file == "stray.jpg.enc"
+        )));
+        assert!(report.findings.iter().any(|f| matches!(
+            &f.problem,
+            Problem::DanglingRows { table, count: 1 } if table == "memory_people"
+        )));
+
+        remove_dangling_rows("memory_people").unwrap();
+        quarantine_unshared("stray.jpg.enc").unwrap();
+        assert!(verify_vault(false).unwrap().findings.is_empty());
+        assert!(fs::read_dir(QUARANTINE_DIR).unwrap().next().is_some());
+    }
+
+    #[tokio::test]
+    async fn a_failed_refetch_leaves_the_blob_in_place() {
+        let _vault = TempVault::new();
+        let sealed = add_sealed("m1", "photo.jpg");
+        let blob = Path::new(MEDIA_DIR).join("photo.jpg.enc");
+        fs::write(&blob, &sealed[..sealed.len() - 4]).unwrap();
+        storage::record_replica("m1", "laptop", &format!("{:x}", Sha256::digest(&sealed))).unwrap();
+
+        let report = repair(&P2pState::default(), vec![Repair::Refetch { memory_id: "m1".into() }]).await;
+        assert_eq!(report.applied, 0);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert_eq!(fs::read(&blob).unwrap(), &sealed[..sealed.len() - 4]);
+        assert!(!Path::new(QUARANTINE_DIR).exists());
+        assert_eq!(fs::read_dir(MEDIA_DIR).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,451 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::OnceLock;
+
+use crate::db;
+
+/// Bundled place-name dataset used for offline reverse geocoding.
+const PLACES_CSV: &str = include_str!("places.csv");
+
+/// Places further away than this are not used to label a memory.
+const MAX_PLACE_DISTANCE_KM: f64 = 150.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryLocation {
+    pub memory_id: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub source: String, // "exif" or "manual"
+    pub place_name: Option<String>,
+    pub region: Option<String>,
+    pub country: Option<String>,
+}
+
+/// A map marker representing one or more memories close to each other
+/// at the requested zoom level.
+#[derive(Debug, Clone, Serialize)]
+pub struct MapMarker {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub count: usize,
+    pub memory_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Place {
+    pub name: String,
+    pub region: String,
+    pub country: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Read GPS coordinates from the EXIF block of an image, if present.
+/// Returns `(latitude, longitude)` in decimal degrees.
+pub fn extract_gps(media_bytes: &[u8]) -> Option<(f64, f64)> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(media_bytes))
+        .ok()?;
+
+    let lat = gps_coordinate(&exif, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S")?;
+    let lon = gps_coordinate(&exif, exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W")?;
+
+    if valid_coordinates(lat, lon) {
+        Some((lat, lon))
+    } else {
+        None
+    }
+}
+
+fn gps_coordinate(
+    exif: &exif::Exif,
+    value_tag: exif::Tag,
+    ref_tag: exif::Tag,
+    negative_ref: &str,
+) -> Option<f64> {
+    let field = exif.get_field(value_tag, exif::In::PRIMARY)?;
+    let degrees = match field.value {
+        exif::Value::Rational(ref parts) if parts.len() >= 3 => {
+            parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0
+        }
+        _ => return None,
+    };
+
+    let reference = exif
+        .get_field(ref_tag, exif::In::PRIMARY)
+        .map(|f| f.display_value().to_string())
+        .unwrap_or_default();
+
+    if reference.trim().eq_ignore_ascii_case(negative_ref) {
+        Some(-degrees)
+    } else {
+        Some(degrees)
+    }
+}
+
+fn valid_coordinates(latitude: f64, longitude: f64) -> bool {
+    (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)
+}
+
+/// Store (or replace) the location of a memory and index it in the R*Tree.
+pub fn set_location(memory_id: &str, latitude: f64, longitude: f64, source: &str) -> Result<MemoryLocation, String> {
+    if !valid_coordinates(latitude, longitude) {
+        return Err("Coordinates out of range".into());
+    }
+
+    let place = nearest_place(latitude, longitude);
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "INSERT INTO memory_locations (memory_id, latitude, longitude, source, place_name, region, country)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(memory_id) DO UPDATE SET
+            latitude = excluded.latitude,
+            longitude = excluded.longitude,
+            source = excluded.source,
+            place_name = excluded.place_name,
+            region = excluded.region,
+            country = excluded.country",
+        params![
+            memory_id,
+            latitude,
+            longitude,
+            source,
+            place.as_ref().map(|p| &p.name),
+            place.as_ref().map(|p| &p.region),
+            place.as_ref().map(|p| &p.country),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    let row_id: i64 = conn
+        .query_row(
+            "SELECT id FROM memory_locations WHERE memory_id = ?1",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO memory_locations_rtree (id, min_lat, max_lat, min_lon, max_lon)
+         VALUES (?1, ?2, ?2, ?3, ?3)",
+        params![row_id, latitude, longitude],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(MemoryLocation {
+        memory_id: memory_id.to_string(),
+        latitude,
+        longitude,
+        source: source.to_string(),
+        place_name: place.as_ref().map(|p| p.name.clone()),
+        region: place.as_ref().map(|p| p.region.clone()),
+        country: place.map(|p| p.country),
+    })
+}
+
+/// Remove the stored location of a memory.
+pub fn clear_location(memory_id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let row_id: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM memory_locations WHERE memory_id = ?1",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    if let Some(row_id) = row_id {
+        conn.execute("DELETE FROM memory_locations_rtree WHERE id = ?1", params![row_id])
+            .map_err(|e| e.to_string())?;
+        conn.execute("DELETE FROM memory_locations WHERE id = ?1", params![row_id])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Add R-tree entries for locations written without `set_location`, such
+/// as rows merged from a backup. Returns how many were added.
+pub fn index_missing(conn: &Connection) -> Result<usize, String> {
+    conn.execute(
+        "INSERT INTO memory_locations_rtree (id, min_lat, max_lat, min_lon, max_lon)
+         SELECT id, latitude, latitude, longitude, longitude FROM memory_locations
+         WHERE id NOT IN (SELECT id FROM memory_locations_rtree)",
+        [],
+    )
+    .map_err(|e| e.to_string())
+}
+
+pub fn get_location(memory_id: &str) -> Result<Option<MemoryLocation>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT memory_id, latitude, longitude, source, place_name, region, country
+         FROM memory_locations WHERE memory_id = ?1",
+        params![memory_id],
+        row_to_location,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// Return every located memory inside the given bounding box, leaving
+/// out deleted ones.
+/// A box with `min_lon > max_lon` crosses the antimeridian.
+///
+/// The R*Tree stores 32-bit floats rounded outwards, so it only narrows
+/// the search; the stored coordinates decide, keeping points on an edge.
+pub fn query_bbox(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Result<Vec<MemoryLocation>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT l.memory_id, l.latitude, l.longitude, l.source, l.place_name, l.region, l.country
+             FROM memory_locations_rtree r
+             JOIN memory_locations l ON l.id = r.id
+             JOIN memories m ON m.id = l.memory_id
+             WHERE r.max_lat >= ?1 AND r.min_lat <= ?3
+               AND r.max_lon >= ?2 AND r.min_lon <= ?4
+               AND l.latitude BETWEEN ?1 AND ?3
+               AND l.longitude BETWEEN ?2 AND ?4
+               AND m.deleted_at IS NULL",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let lon_ranges = if min_lon > max_lon {
+        vec![(min_lon, 180.0), (-180.0, max_lon)]
+    } else {
+        vec![(min_lon, max_lon)]
+    };
+
+    let mut locations = Vec::new();
+    for (from_lon, to_lon) in lon_ranges {
+        let rows = stmt
+            .query_map(params![min_lat, from_lon, max_lat, to_lon], row_to_location)
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        locations.extend(rows);
+    }
+
+    Ok(locations)
+}
+
+/// Group the memories inside a bounding box into map markers.
+/// Memories falling into the same grid cell at `zoom` share a marker,
+/// positioned at the average of their coordinates.
+pub fn cluster_markers(
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    zoom: u8,
+) -> Result<Vec<MapMarker>, String> {
+    let locations = query_bbox(min_lat, min_lon, max_lat, max_lon)?;
+
+    // Roughly four cells per map tile, matching web-mercator tile widths.
+    let cell_size = 360.0 / f64::from(1u32 << zoom.min(20)) / 4.0;
+
+    let mut cells: HashMap<(i64, i64), MapMarker> = HashMap::new();
+    for location in locations {
+        let key = (
+            ((location.latitude + 90.0) / cell_size).floor() as i64,
+            ((location.longitude + 180.0) / cell_size).floor() as i64,
+        );
+        let marker = cells.entry(key).or_insert_with(|| MapMarker {
+            latitude: 0.0,
+            longitude: 0.0,
+            count: 0,
+            memory_ids: Vec::new(),
+        });
+        marker.latitude += location.latitude;
+        marker.longitude += location.longitude;
+        marker.count += 1;
+        marker.memory_ids.push(location.memory_id);
+    }
+
+    let mut markers: Vec<MapMarker> = cells
+        .into_values()
+        .map(|mut marker| {
+            marker.latitude /= marker.count as f64;
+            marker.longitude /= marker.count as f64;
+            marker
+        })
+        .collect();
+    markers.sort_by(|a, b| b.count.cmp(&a.count));
+
+    Ok(markers)
+}
+
+/// Find memories whose place, region or country matches `query`,
+/// optionally restricted to a year (e.g. "Kerala", 2019).
+pub fn search_by_place(query: &str, year: Option<i32>) -> Result<Vec<MemoryLocation>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let pattern = format!("%{}%", escape_like(query.trim()));
+    let year_prefix = year.map(|y| format!("{:04}%", y)).unwrap_or_else(|| "%".into());
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT l.memory_id, l.latitude, l.longitude, l.source, l.place_name, l.region, l.country
+             FROM memory_locations l
+             JOIN memories m ON m.id = l.memory_id
+             WHERE (l.place_name LIKE ?1 ESCAPE '\\' OR l.region LIKE ?1 ESCAPE '\\' OR l.country LIKE ?1 ESCAPE '\\')
+               AND COALESCE(m.captured_at, m.created_at) LIKE ?2
+               AND m.deleted_at IS NULL
+             ORDER BY COALESCE(m.captured_at, m.created_at)",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let locations = stmt
+        .query_map(params![pattern, year_prefix], row_to_location)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(locations)
+}
+
+/// Escape LIKE wildcards so `%` and `_` match literally; use with
+/// `ESCAPE '\'`.
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Resolve coordinates to the nearest bundled place, without network access.
+pub fn reverse_geocode(latitude: f64, longitude: f64) -> Option<Place> {
+    nearest_place(latitude, longitude)
+}
+
+fn nearest_place(latitude: f64, longitude: f64) -> Option<Place> {
+    places()
+        .iter()
+        .map(|place| {
+            let distance = haversine_km(latitude, longitude, place.latitude, place.longitude);
+            (place, distance)
+        })
+        .filter(|(_, distance)| *distance <= MAX_PLACE_DISTANCE_KM)
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(place, _)| place.clone())
+}
+
+/// The bundled places, parsed on first use.
+fn places() -> &'static [Place] {
+    static PLACES: OnceLock<Vec<Place>> = OnceLock::new();
+    PLACES.get_or_init(parse_places)
+}
+
+fn parse_places() -> Vec<Place> {
+    PLACES_CSV
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').collect();
+            if fields.len() != 5 {
+                return None;
+            }
+            Some(Place {
+                name: fields[0].to_string(),
+                region: fields[1].to_string(),
+                country: fields[2].to_string(),
+                latitude: fields[3].parse().ok()?,
+                longitude: fields[4].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+fn row_to_location(row: &rusqlite::Row) -> rusqlite::Result<MemoryLocation> {
+    Ok(MemoryLocation {
+        memory_id: row.get(0)?,
+        latitude: row.get(1)?,
+        longitude: row.get(2)?,
+        source: row.get(3)?,
+        place_name: row.get(4)?,
+        region: row.get(5)?,
+        country: row.get(6)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+
+    fn add_located(id: &str, captured_at: &str, latitude: f64, longitude: f64) {
+        db::add_memory(db::Memory {
+            id: id.into(),
+            title: id.into(),
+            tags: String::new(),
+            created_at: captured_at.into(),
+            media_type: "image/jpeg".into(),
+            filename: String::new(),
+        })
+        .unwrap();
+        set_location(id, latitude, longitude, "manual").unwrap();
+    }
+
+    fn ids(locations: Vec<MemoryLocation>) -> Vec<String> {
+        let mut ids: Vec<String> = locations.into_iter().map(|l| l.memory_id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn bbox_finds_points_inside_and_on_the_edges() {
+        let _vault = TempVault::new();
+        add_located("kochi", "2019-01-02T00:00:00Z", 9.9312, 76.2673);
+        add_located("paris", "2019-01-02T00:00:00Z", 48.8566, 2.3522);
+
+        assert_eq!(ids(query_bbox(9.0, 76.0, 10.0, 77.0).unwrap()), ["kochi"]);
+        assert!(query_bbox(-10.0, -10.0, 0.0, 0.0).unwrap().is_empty());
+        // Bounds equal to the stored coordinates, which no f32 represents
+        assert_eq!(ids(query_bbox(48.8566, 2.3522, 48.8566, 2.3522).unwrap()), ["paris"]);
+        assert!(query_bbox(48.8567, 2.3522, 49.0, 3.0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bbox_across_the_antimeridian_covers_both_sides() {
+        let _vault = TempVault::new();
+        add_located("fiji", "2019-01-02T00:00:00Z", -17.7134, 178.065);
+        add_located("samoa", "2019-01-02T00:00:00Z", -13.759, -172.1046);
+        add_located("sydney", "2019-01-02T00:00:00Z", -33.8688, 151.2093);
+
+        assert_eq!(ids(query_bbox(-20.0, 170.0, -10.0, -170.0).unwrap()), ["fiji", "samoa"]);
+        assert!(query_bbox(-20.0, -170.0, -10.0, 170.0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn place_search_treats_wildcards_literally_and_filters_by_year() {
+        let _vault = TempVault::new();
+        add_located("kochi", "2019-01-02T00:00:00Z", 9.9312, 76.2673);
+        add_located("kochi-again", "2021-05-06T00:00:00Z", 9.9312, 76.2673);
+        let conn = db::get_connection().unwrap();
+        conn.execute("UPDATE memory_locations SET place_name = 'Cafe_100%' WHERE memory_id = 'kochi'", [])
+            .unwrap();
+
+        assert_eq!(ids(search_by_place("Kerala", None).unwrap()), ["kochi", "kochi-again"]);
+        assert_eq!(ids(search_by_place("kerala", Some(2021)).unwrap()), ["kochi-again"]);
+        assert_eq!(ids(search_by_place("_100%", None).unwrap()), ["kochi"]);
+        assert!(search_by_place("Cafe%1", None).unwrap().is_empty());
+        assert_eq!(search_by_place("Cafe_1", None).unwrap().len(), 1);
+        assert!(search_by_place("CafeX1", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reverse_geocode_uses_the_nearest_bundled_place() {
+        let place = reverse_geocode(9.50, 76.34).unwrap();
+        assert_eq!(place.name, "Alappuzha");
+        assert!(reverse_geocode(0.0, -140.0).is_none());
+    }
+}
@@ -0,0 +1,180 @@
+use reqwest::{Client, Response};
+use serde::Deserialize;
+
+/// Client for the HTTP RPC API of a Kubo (go-ipfs) node. Every RPC call
+/// is a POST, with arguments passed as `arg` query parameters.
+#[derive(Debug, Clone)]
+pub struct KuboClient {
+    api_url: String,
+    client: Client,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AddResponse {
+    hash: String,
+    size: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VersionResponse {
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    message: String,
+}
+
+impl KuboClient {
+    pub fn new(api_url: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            client: Client::new(),
+        }
+    }
+
+    fn endpoint(&self, command: &str) -> String {
+        format!("{}/api/v0/{}", self.api_url, command)
+    }
+
+    /// Node version, used to check the API is reachable.
+    pub async fn version(&self) -> Result<String, String> {
+        let res = self.post("version", &[]).await?;
+        let body: VersionResponse = res.json().await.map_err(|e| e.to_string())?;
+        Ok(body.version)
+    }
+
+    /// Add and pin bytes, returning their CIDv1 and stored size.
+    pub async fn add(&self, bytes: Vec<u8>, name: &str) -> Result<(String, u64), String> {
+        let part = reqwest::multipart::Part::bytes(bytes).file_name(name.to_string());
+        let form = reqwest::multipart::Form::new().part("file", part);
+        let res = self
+            .client
+            .post(self.endpoint("add"))
+            .query(&[("cid-version", "1"), ("pin", "true"), ("quieter", "true")])
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| format!("IPFS request failed: {}", e))?;
+        let res = check(res).await?;
+
+        // One JSON object per line; the last one describes the root.
+        let body = res.text().await.map_err(|e| e.to_string())?;
+        let line = body.lines().filter(|l| !l.trim().is_empty()).last().ok_or("Empty IPFS add response")?;
+        let added: AddResponse = serde_json::from_str(line).map_err(|e| format!("Invalid IPFS add response: {}", e))?;
+        Ok((added.hash, added.size.parse().unwrap_or(0)))
+    }
+
+    pub async fn pin(&self, cid: &str) -> Result<(), String> {
+        self.post("pin/add", &[("arg", cid)]).await.map(|_| ())
+    }
+
+    /// Unpin a CID. Content that is not pinned counts as done.
+    pub async fn unpin(&self, cid: &str) -> Result<(), String> {
+        match self.post("pin/rm", &[("arg", cid)]).await {
+            Err(e) if e.contains("not pinned") => Ok(()),
+            other => other.map(|_| ()),
+        }
+    }
+
+    pub async fn cat(&self, cid: &str) -> Result<Vec<u8>, String> {
+        let res = self.post("cat", &[("arg", cid)]).await?;
+        Ok(res.bytes().await.map_err(|e| e.to_string())?.to_vec())
+    }
+
+    async fn post(&self, command: &str, query: &[(&str, &str)]) -> Result<Response, String> {
+        let res = self
+            .client
+            .post(self.endpoint(command))
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| format!("IPFS request failed: {}", e))?;
+        check(res).await
+    }
+}
+
+/// Turn an RPC error reply into its message.
+async fn check(res: Response) -> Result<Response, String> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status();
+    let text = res.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<ErrorResponse>(&text)
+        .map(|e| e.message)
+        .unwrap_or(text);
+    Err(format!("IPFS API error {}: {}", status, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+
+    #[tokio::test]
+    async fn add_returns_the_root_from_the_last_line() {
+        let mut server = mockito::Server::new_async().await;
+        let add = server
+            .mock("POST", "/api/v0/add")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("cid-version".into(), "1".into()),
+                Matcher::UrlEncoded("pin".into(), "true".into()),
+            ]))
+            .with_body(concat!(
+                "{\"Name\":\"chunk\",\"Hash\":\"bafkchunk\",\"Size\":\"10\"}\n",
+                "{\"Name\":\"memory\",\"Hash\":\"bafyroot\",\"Size\":\"42\"}\n",
+                "\n",
+            ))
+            .create_async()
+            .await;
+
+        let client = KuboClient::new(&format!("{}/", server.url()));
+        let (cid, size) = client.add(b"sealed".to_vec(), "memory").await.unwrap();
+        assert_eq!((cid.as_str(), size), ("bafyroot", 42));
+        add.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn unpin_treats_not_pinned_as_done() {
+        let mut server = mockito::Server::new_async().await;
+        let not_pinned = server
+            .mock("POST", "/api/v0/pin/rm")
+            .match_query(Matcher::UrlEncoded("arg".into(), "bafygone".into()))
+            .with_status(500)
+            .with_body(r#"{"Message":"not pinned or pinned indirectly","Code":0,"Type":"error"}"#)
+            .create_async()
+            .await;
+        let broken = server
+            .mock("POST", "/api/v0/pin/rm")
+            .match_query(Matcher::UrlEncoded("arg".into(), "nonsense".into()))
+            .with_status(500)
+            .with_body(r#"{"Message":"invalid path \"nonsense\"","Code":0,"Type":"error"}"#)
+            .create_async()
+            .await;
+
+        let client = KuboClient::new(&server.url());
+        assert!(client.unpin("bafygone").await.is_ok());
+        let error = client.unpin("nonsense").await.unwrap_err();
+        assert!(error.contains("invalid path"), "{}", error);
+        not_pinned.assert_async().await;
+        broken.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn cat_returns_the_raw_bytes() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/v0/cat")
+            .match_query(Matcher::UrlEncoded("arg".into(), "bafyblob".into()))
+            .with_body([0u8, 159, 146, 150])
+            .create_async()
+            .await;
+
+        let client = KuboClient::new(&server.url());
+        assert_eq!(client.cat("bafyblob").await.unwrap(), vec![0u8, 159, 146, 150]);
+    }
+}
@@ -0,0 +1,365 @@
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::p2p::replication;
+use crate::{db, settings, vault};
+use crate::vault::MEDIA_DIR;
+
+mod kubo;
+
+pub use kubo::KuboClient;
+
+const SETTINGS_KEY: &str = "ipfs";
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpfsSettings {
+    /// Pin new memories automatically.
+    pub enabled: bool,
+    /// Kubo RPC address; the RPC API has no authentication, so keep it local.
+    pub api_url: String,
+}
+
+impl Default for IpfsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            api_url: "http://127.0.0.1:5001".into(),
+        }
+    }
+}
+
+impl IpfsSettings {
+    pub fn load() -> Result<Self, String> {
+        settings::get_json(SETTINGS_KEY)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        settings::set_json(SETTINGS_KEY, self)
+    }
+
+    pub fn client(&self) -> KuboClient {
+        KuboClient::new(&self.api_url)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Pin {
+    pub memory_id: String,
+    pub cid: String,
+    pub size: u64,
+    pub pinned_at: String,
+    /// SHA-256 of the ciphertext that was pinned. Pins recorded before
+    /// this was tracked have none.
+    pub hash: Option<String>,
+}
+
+/// Everything needed to rebuild a vault from IPFS. Memory keys are
+/// included, so the manifest must be kept as safe as the database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub created_at: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub memory_id: String,
+    pub cid: String,
+    /// SHA-256 of the encrypted blob.
+    pub hash: String,
+    pub title: String,
+    pub tags: String,
+    pub created_at: String,
+    pub media_type: String,
+    pub filename: String,
+    pub captured_at: Option<String>,
+    pub key: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RestoreReport {
+    pub memories_restored: usize,
+    pub blobs_restored: usize,
+    pub skipped: usize,
+    pub errors: Vec<String>,
+}
+
+/// Add a memory's encrypted blob to the node and record its CID. Only
+/// ciphertext ever leaves the device.
+pub async fn pin_memory(memory_id: &str) -> Result<Pin, String> {
+    let client = IpfsSettings::load()?.client();
+    let (path, _) = replication::sealed_blob(memory_id)?.ok_or("Memory has no media to pin")?;
+    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+    let hash = format!("{:x}", Sha256::digest(&bytes));
+    let (cid, size) = client.add(bytes, memory_id).await?;
+
+    let pin = Pin {
+        memory_id: memory_id.to_string(),
+        cid,
+        size,
+        pinned_at: Utc::now().to_rfc3339(),
+        hash: Some(hash),
+    };
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO ipfs_pins (memory_id, cid, size, pinned_at, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![pin.memory_id, pin.cid, pin.size as i64, pin.pinned_at, pin.hash],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(pin)
+}
+
+/// Release a memory's blob on the node. The CID stays pinned while
+/// another memory still refers to it.
+pub async fn unpin_memory(memory_id: &str) -> Result<(), String> {
+    let Some(pin) = get_pin(memory_id)? else {
+        return Ok(());
+    };
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let shared: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM ipfs_pins WHERE cid = ?1 AND memory_id != ?2)",
+            params![pin.cid, memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if !shared {
+        IpfsSettings::load()?.client().unpin(&pin.cid).await?;
+    }
+    conn.execute("DELETE FROM ipfs_pins WHERE memory_id = ?1", params![memory_id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn get_pin(memory_id: &str) -> Result<Option<Pin>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT memory_id, cid, size, pinned_at, hash FROM ipfs_pins WHERE memory_id = ?1",
+        params![memory_id],
+        row_to_pin,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn list_pins() -> Result<Vec<Pin>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT memory_id, cid, size, pinned_at, hash FROM ipfs_pins ORDER BY pinned_at")
+        .map_err(|e| e.to_string())?;
+
+    let pins = stmt
+        .query_map([], row_to_pin)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(pins)
+}
+
+/// Write a manifest of every pinned memory to `path`.
+pub fn export_manifest(path: &Path) -> Result<Manifest, String> {
+    let mut entries = Vec::new();
+    for pin in list_pins()? {
+        let Some((_, hash)) = replication::sealed_blob(&pin.memory_id)? else {
+            continue;
+        };
+        let conn = db::get_connection().map_err(|e| e.to_string())?;
+        let entry = conn
+            .query_row(
+                "SELECT title, tags, created_at, media_type, filename, captured_at, key_encrypted
+                 FROM memories WHERE id = ?1 AND deleted_at IS NULL",
+                params![pin.memory_id],
+                |row| {
+                    Ok(ManifestEntry {
+                        memory_id: pin.memory_id.clone(),
+                        cid: pin.cid.clone(),
+                        hash: hash.clone(),
+                        title: row.get(0)?,
+                        tags: row.get(1)?,
+                        created_at: row.get(2)?,
+                        media_type: row.get(3)?,
+                        filename: row.get(4)?,
+                        captured_at: row.get(5)?,
+                        key: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        entries.extend(entry);
+    }
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        created_at: Utc::now().to_rfc3339(),
+        entries,
+    };
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    vault::write_atomic(path, &json).map_err(|e| e.to_string())?;
+    Ok(manifest)
+}
+
+/// Rebuild memories listed in a manifest, fetching any blob missing from
+/// `media_store` by CID. Existing memories and blobs are left alone, so
+/// an interrupted restore can simply be run again.
+pub async fn restore_from_manifest(path: &Path) -> Result<RestoreReport, String> {
+    let manifest: Manifest =
+        serde_json::from_slice(&fs::read(path).map_err(|e| e.to_string())?).map_err(|e| format!("Invalid manifest: {}", e))?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(format!("Unsupported manifest version {}", manifest.version));
+    }
+
+    let client = IpfsSettings::load()?.client();
+    fs::create_dir_all(MEDIA_DIR).map_err(|e| e.to_string())?;
+    let mut report = RestoreReport::default();
+    for entry in manifest.entries {
+        match restore_entry(&client, &entry).await {
+            Ok((row, blob)) => {
+                report.memories_restored += row as usize;
+                report.blobs_restored += blob as usize;
+                if !row && !blob {
+                    report.skipped += 1;
+                }
+            }
+            Err(e) => report.errors.push(format!("{}: {}", entry.memory_id, e)),
+        }
+    }
+    Ok(report)
+}
+
+async fn restore_entry(client: &KuboClient, entry: &ManifestEntry) -> Result<(bool, bool), String> {
+    if entry.filename.contains(['/', '\\']) || entry.filename.starts_with('.') {
+        return Err(format!("Refusing unsafe filename '{}'", entry.filename));
+    }
+
+    let blob = Path::new(MEDIA_DIR).join(format!("{}.enc", entry.filename));
+    let fetched = if blob.exists() {
+        false
+    } else {
+        let bytes = client.cat(&entry.cid).await?;
+        if format!("{:x}", Sha256::digest(&bytes)) != entry.hash {
+            return Err("Blob from IPFS failed verification".into());
+        }
+        vault::write_atomic(&blob, &bytes).map_err(|e| e.to_string())?;
+        client.pin(&entry.cid).await?;
+        true
+    };
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let inserted = conn
+        .execute(
+            "INSERT OR IGNORE INTO memories (id, title, tags, created_at, media_type, filename, key_encrypted,
+                                             transcription, captured_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, '', ?8)",
+            params![
+                entry.memory_id,
+                entry.title,
+                entry.tags,
+                entry.created_at,
+                entry.media_type,
+                entry.filename,
+                entry.key,
+                entry.captured_at
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR IGNORE INTO ipfs_pins (memory_id, cid, size, pinned_at, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            entry.memory_id,
+            entry.cid,
+            fs::metadata(&blob).map(|m| m.len() as i64).unwrap_or(0),
+            Utc::now().to_rfc3339(),
+            entry.hash
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok((inserted > 0, fetched))
+}
+
+fn row_to_pin(row: &rusqlite::Row) -> rusqlite::Result<Pin> {
+    Ok(Pin {
+        memory_id: row.get(0)?,
+        cid: row.get(1)?,
+        size: row.get::<_, i64>(2)? as u64,
+        pinned_at: row.get(3)?,
+        hash: row.get(4)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+    use mockito::Matcher;
+
+    fn entry(memory_id: &str, cid: &str, hash: String) -> ManifestEntry {
+        ManifestEntry {
+            memory_id: memory_id.into(),
+            cid: cid.into(),
+            hash,
+            title: "Restored".into(),
+            tags: String::new(),
+            created_at: "2024-01-01T00:00:00+00:00".into(),
+            media_type: "image/jpeg".into(),
+            filename: format!("{}.jpg", memory_id),
+            captured_at: None,
+            key: crate::crypto::generate_key_base64(),
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_rejects_blobs_that_fail_verification() {
+        let vault = TempVault::new();
+        let mut server = mockito::Server::new_async().await;
+        for cid in ["bafygood", "bafybad"] {
+            server
+                .mock("POST", "/api/v0/cat")
+                .match_query(Matcher::UrlEncoded("arg".into(), cid.into()))
+                .with_body("sealed blob")
+                .create_async()
+                .await;
+        }
+        let pinned = server
+            .mock("POST", "/api/v0/pin/add")
+            .match_query(Matcher::UrlEncoded("arg".into(), "bafygood".into()))
+            .create_async()
+            .await;
+        IpfsSettings {
+            enabled: true,
+            api_url: server.url(),
+        }
+        .save()
+        .unwrap();
+
+        let good = format!("{:x}", Sha256::digest(b"sealed blob"));
+        let bad = format!("{:x}", Sha256::digest(b"something else"));
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            created_at: Utc::now().to_rfc3339(),
+            entries: vec![entry("good", "bafygood", good), entry("bad", "bafybad", bad)],
+        };
+        let path = vault.path().join("manifest.json");
+        fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        let report = restore_from_manifest(&path).await.unwrap();
+        assert_eq!((report.memories_restored, report.blobs_restored), (1, 1));
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].starts_with("bad: "), "{:?}", report.errors);
+        assert!(report.errors[0].contains("failed verification"));
+
+        assert!(Path::new(MEDIA_DIR).join("good.jpg.enc").is_file());
+        assert!(!Path::new(MEDIA_DIR).join("bad.jpg.enc").exists());
+        assert!(get_pin("bad").unwrap().is_none());
+        assert_eq!(get_pin("good").unwrap().unwrap().cid, "bafygood");
+        pinned.assert_async().await;
+    }
+}
@@ -0,0 +1,376 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::db;
+use crate::ipfs;
+use crate::sync::status;
+use crate::transcripts;
+use crate::whisper::{TranscribeOptions, TranscriberState};
+
+/// Event emitted to the frontend whenever a job changes state.
+pub const JOB_EVENT: &str = "job-progress";
+
+const MAX_ATTEMPTS: i64 = 5;
+const BASE_BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+/// Workers re-check the table at least this often, so retries whose
+/// backoff has elapsed are picked up without a new enqueue.
+const IDLE_POLL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    Transcribe,
+    /// Add the memory's encrypted blob to the IPFS node.
+    Pin,
+    Unpin,
+}
+
+impl JobKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Transcribe => "transcribe",
+            JobKind::Pin => "pin",
+            JobKind::Unpin => "unpin",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "transcribe" => Some(JobKind::Transcribe),
+            "pin" => Some(JobKind::Pin),
+            "unpin" => Some(JobKind::Unpin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub kind: String,
+    pub memory_id: String,
+    pub status: String, // queued, running, done, failed
+    pub attempts: i64,
+    pub progress: f64,
+    pub last_error: Option<String>,
+    pub next_run_at: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEvent {
+    pub job_id: String,
+    pub memory_id: String,
+    pub kind: String,
+    pub status: String,
+    pub progress: f64,
+    pub error: Option<String>,
+}
+
+/// Managed Tauri state used to wake idle workers when work is queued.
+#[derive(Default)]
+pub struct JobQueue {
+    notify: Arc<Notify>,
+}
+
+impl JobQueue {
+    /// Persist a job and wake a worker. Queuing the same kind of job twice
+    /// for a memory is a no-op while the first is still pending.
+    pub fn enqueue(&self, kind: JobKind, memory_id: &str) -> Result<String, String> {
+        let conn = db::get_connection().map_err(|e| e.to_string())?;
+        let existing: Option<String> = conn
+            .query_row(
+                "SELECT id FROM jobs WHERE kind = ?1 AND memory_id = ?2 AND status IN ('queued', 'running')",
+                params![kind.as_str(), memory_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+        conn.execute(
+            "INSERT INTO jobs (id, kind, memory_id, status, attempts, progress, next_run_at, created_at, updated_at)
+             VALUES (?1, ?2, ?3, 'queued', 0, 0, ?4, ?4, ?4)",
+            params![id, kind.as_str(), memory_id, now],
+        )
+        .map_err(|e| e.to_string())?;
+
+        self.notify.notify_one();
+        Ok(id)
+    }
+
+    /// Re-queue a failed job immediately, resetting its attempt count.
+    pub fn retry(&self, job_id: &str) -> Result<(), String> {
+        let conn = db::get_connection().map_err(|e| e.to_string())?;
+        let updated = conn
+            .execute(
+                "UPDATE jobs SET status = 'queued', attempts = 0, progress = 0, last_error = NULL,
+                        next_run_at = ?1, updated_at = ?1
+                 WHERE id = ?2 AND status = 'failed'",
+                params![Utc::now().to_rfc3339(), job_id],
+            )
+            .map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Err("Job not found or not failed".into());
+        }
+        self.notify.notify_one();
+        Ok(())
+    }
+}
+
+/// Start `workers` background workers. Jobs left `running` by a previous
+/// session (app closed mid-job) are queued again first.
+pub fn start_workers(app: AppHandle, workers: usize) -> Result<(), String> {
+    requeue_interrupted()?;
+
+    let notify = app.state::<JobQueue>().notify.clone();
+    for _ in 0..workers.max(1) {
+        let app = app.clone();
+        let notify = notify.clone();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                match claim_next() {
+                    Ok(Some(job)) => run_job(&app, job).await,
+                    Ok(None) => {
+                        let _ = tokio::time::timeout(IDLE_POLL, notify.notified()).await;
+                    }
+                    Err(e) => {
+                        status::log_error(None, &format!("Job queue error: {}", e));
+                        tokio::time::sleep(IDLE_POLL).await;
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Queue jobs still marked `running` again; returns how many there were.
+fn requeue_interrupted() -> Result<usize, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE jobs SET status = 'queued', updated_at = ?1 WHERE status = 'running'",
+        params![Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Atomically move the oldest due job from `queued` to `running`.
+fn claim_next() -> Result<Option<Job>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let now = Utc::now().to_rfc3339();
+    conn.query_row(
+        "UPDATE jobs SET status = 'running', attempts = attempts + 1, updated_at = ?1
+         WHERE id = (
+            SELECT id FROM jobs WHERE status = 'queued' AND next_run_at <= ?1
+            ORDER BY created_at LIMIT 1
+         )
+         RETURNING id, kind, memory_id, status, attempts, progress, last_error, next_run_at, created_at, updated_at",
+        params![now],
+        row_to_job,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+async fn run_job(app: &AppHandle, job: Job) {
+    emit(app, &job, "running", 0.1, None);
+
+    let result = match JobKind::parse(&job.kind) {
+        Some(JobKind::Transcribe) => {
+            let transcriber = app.state::<TranscriberState>().current();
+            transcripts::transcribe_memory(transcriber, &job.memory_id, TranscribeOptions::default())
+                .await
+                .map(|_| ())
+        }
+        Some(JobKind::Pin) => ipfs::pin_memory(&job.memory_id).await.map(|_| ()),
+        Some(JobKind::Unpin) => ipfs::unpin_memory(&job.memory_id).await,
+        None => Err(format!("Unknown job kind '{}'", job.kind)),
+    };
+
+    let outcome = match result {
+        Ok(()) => mark_done(&job.id).map(|_| ("done", 1.0, None)),
+        Err(error) => mark_failed(&job, &error).map(|status| (status, 0.0, Some(error))),
+    };
+
+    match outcome {
+        Ok((status, progress, error)) => emit(app, &job, status, progress, error),
+        Err(e) => status::log_error(None, &format!("Failed to record job {} result: {}", job.id, e)),
+    }
+}
+
+fn mark_done(job_id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE jobs SET status = 'done', progress = 1, last_error = NULL, updated_at = ?1 WHERE id = ?2",
+        params![Utc::now().to_rfc3339(), job_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Schedule a retry with exponential backoff, or give up after MAX_ATTEMPTS.
+fn mark_failed(job: &Job, error: &str) -> Result<&'static str, String> {
+    let now = Utc::now();
+    let (status, next_run_at) = if job.attempts >= MAX_ATTEMPTS {
+        ("failed", now)
+    } else {
+        ("queued", now + ChronoDuration::seconds(backoff_secs(job.attempts)))
+    };
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE jobs SET status = ?1, progress = 0, last_error = ?2, next_run_at = ?3, updated_at = ?4 WHERE id = ?5",
+        params![status, error, next_run_at.to_rfc3339(), now.to_rfc3339(), job.id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(status)
+}
+
+/// Delay before retrying a job that has failed `attempts` times: 30s,
+/// doubling per attempt, at most an hour.
+fn backoff_secs(attempts: i64) -> i64 {
+    (BASE_BACKOFF_SECS << (attempts - 1).clamp(0, 16)).min(MAX_BACKOFF_SECS)
+}
+
+fn emit(app: &AppHandle, job: &Job, status: &str, progress: f64, error: Option<String>) {
+    let event = JobEvent {
+        job_id: job.id.clone(),
+        memory_id: job.memory_id.clone(),
+        kind: job.kind.clone(),
+        status: status.to_string(),
+        progress,
+        error,
+    };
+    if let Err(e) = app.emit(JOB_EVENT, event) {
+        status::log_error(None, &format!("Failed to emit job event: {}", e));
+    }
+}
+
+pub fn list_jobs(status: Option<String>) -> Result<Vec<Job>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, kind, memory_id, status, attempts, progress, last_error, next_run_at, created_at, updated_at
+             FROM jobs WHERE ?1 IS NULL OR status = ?1
+             ORDER BY created_at DESC",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let jobs = stmt
+        .query_map(params![status], row_to_job)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(jobs)
+}
+
+fn row_to_job(row: &rusqlite::Row) -> rusqlite::Result<Job> {
+    Ok(Job {
+        id: row.get(0)?,
+        kind: row.get(1)?,
+        memory_id: row.get(2)?,
+        status: row.get(3)?,
+        attempts: row.get(4)?,
+        progress: row.get(5)?,
+        last_error: row.get(6)?,
+        next_run_at: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+
+    fn job(id: &str) -> Job {
+        list_jobs(None).unwrap().into_iter().find(|j| j.id == id).unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_an_hour() {
+        let backoffs: Vec<i64> = (1..=9).map(backoff_secs).collect();
+        assert_eq!(backoffs, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(backoff_secs(0), 30);
+        assert_eq!(backoff_secs(100), MAX_BACKOFF_SECS);
+    }
+
+    #[test]
+    fn claims_due_jobs_oldest_first_and_once() {
+        let _vault = TempVault::new();
+        let queue = JobQueue::default();
+        let first = queue.enqueue(JobKind::Transcribe, "m1").unwrap();
+        assert_eq!(queue.enqueue(JobKind::Transcribe, "m1").unwrap(), first);
+        let second = queue.enqueue(JobKind::Pin, "m1").unwrap();
+        assert_ne!(first, second);
+
+        let claimed = claim_next().unwrap().unwrap();
+        assert_eq!((claimed.id.as_str(), claimed.status.as_str(), claimed.attempts), (first.as_str(), "running", 1));
+        // Still pending while running
+        assert_eq!(queue.enqueue(JobKind::Transcribe, "m1").unwrap(), first);
+        assert_eq!(claim_next().unwrap().unwrap().id, second);
+        assert!(claim_next().unwrap().is_none());
+    }
+
+    #[test]
+    fn failures_back_off_then_give_up() {
+        let _vault = TempVault::new();
+        let queue = JobQueue::default();
+        let id = queue.enqueue(JobKind::Transcribe, "m1").unwrap();
+
+        let claimed = claim_next().unwrap().unwrap();
+        let before = Utc::now();
+        assert_eq!(mark_failed(&claimed, "no model").unwrap(), "queued");
+        let retried = job(&id);
+        assert_eq!(retried.last_error.as_deref(), Some("no model"));
+        let next_run_at = chrono::DateTime::parse_from_rfc3339(&retried.next_run_at).unwrap();
+        assert!(next_run_at >= before + ChronoDuration::seconds(BASE_BACKOFF_SECS));
+        // Not due yet
+        assert!(claim_next().unwrap().is_none());
+
+        let last = Job {
+            attempts: MAX_ATTEMPTS,
+            ..retried
+        };
+        assert_eq!(mark_failed(&last, "no model").unwrap(), "failed");
+        assert_eq!(job(&id).status, "failed");
+        assert!(claim_next().unwrap().is_none());
+
+        queue.retry(&id).unwrap();
+        let reset = job(&id);
+        assert_eq!((reset.status.as_str(), reset.attempts), ("queued", 0));
+        assert!(queue.retry(&id).is_err());
+        assert_eq!(claim_next().unwrap().unwrap().id, id);
+    }
+
+    #[test]
+    fn jobs_interrupted_mid_run_are_queued_again() {
+        let _vault = TempVault::new();
+        let queue = JobQueue::default();
+        let id = queue.enqueue(JobKind::Pin, "m1").unwrap();
+        claim_next().unwrap().unwrap();
+
+        assert_eq!(requeue_interrupted().unwrap(), 1);
+        assert_eq!(job(&id).status, "queued");
+        let reclaimed = claim_next().unwrap().unwrap();
+        assert_eq!((reclaimed.id.as_str(), reclaimed.attempts), (id.as_str(), 2));
+        mark_done(&id).unwrap();
+        assert_eq!(requeue_interrupted().unwrap(), 0);
+    }
+}
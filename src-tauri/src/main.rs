@@ -9,10 +9,9 @@ use std::fs;
 use rusqlite::{params, Connection};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 use chrono::Utc;
-use std::sync::Mutex;
 
 mod commands;
 mod db;
@@ -20,6 +19,21 @@ mod crypto;
 mod media;
 mod whisper;
 mod sync;
+mod geo;
+mod timeline;
+mod faces;
+mod people;
+mod settings;
+mod transcripts;
+mod jobs;
+mod vault;
+mod p2p;
+mod oplog;
+mod ipfs;
+mod syncthing;
+mod storage;
+mod backup;
+mod fsck;
 
 // ----------- Memory structs and commands ------------
 
@@ -43,19 +57,37 @@ struct Memory {
 }
 
 #[tauri::command]
-fn add_memory(input: MemoryInput) -> Result<(), String> {
+fn add_memory(input: MemoryInput, jobs: State<'_, jobs::JobQueue>) -> Result<(), String> {
     let id = Uuid::new_v4().to_string();
     let now = Utc::now().to_rfc3339();
-    let media_folder = PathBuf::from("media_store");
+    let media_folder = PathBuf::from(vault::MEDIA_DIR);
     let _ = fs::create_dir_all(&media_folder);
 
     let media_bytes = STANDARD.decode(&input.media_data).map_err(|e| e.to_string())?;
-    let media_path = media_folder.join(&input.filename);
-    fs::write(&media_path, &media_bytes).map_err(|e| e.to_string())?;
+    // The original is sealed with its own key before it touches the disk
+    let key = crypto::generate_key_base64();
+    media::seal_media(&media_folder, &input.filename, &media_bytes, &crypto::decode_key(&key)?)?;
 
     let conn = db::get_conn().map_err(|e| e.to_string())?;
-    let query = "INSERT INTO memories (id, title, tags, created_at, media_type, filename, key_encrypted, transcription) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
-    conn.execute(query, [&id, &input.title, &input.tags, &now, &input.media_type, &input.filename, "", ""]).map_err(|e| e.to_string())?;
+    let captured_at = media::extract_capture_time(&media_bytes).unwrap_or_else(|| now.clone());
+    let query = "INSERT INTO memories (id, title, tags, created_at, media_type, filename, key_encrypted, transcription, captured_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)";
+    conn.execute(query, [&id, &input.title, &input.tags, &now, &input.media_type, &input.filename, &key, "", &captured_at]).map_err(|e| e.to_string())?;
+    p2p::replication::bump_version(&id)?;
+    oplog::seed(&id)?;
+
+    // Place the memory on the map if the image carries GPS coordinates
+    if let Some((latitude, longitude)) = geo::extract_gps(&media_bytes) {
+        geo::set_location(&id, latitude, longitude, "exif")?;
+    }
+
+    // Voice notes and videos are transcribed in the background
+    if input.media_type.starts_with("audio/") || input.media_type.starts_with("video/") {
+        jobs.enqueue(jobs::JobKind::Transcribe, &id)?;
+    }
+
+    if ipfs::IpfsSettings::load()?.enabled {
+        jobs.enqueue(jobs::JobKind::Pin, &id)?;
+    }
 
     Ok(())
 }
@@ -63,7 +95,7 @@ fn add_memory(input: MemoryInput) -> Result<(), String> {
 #[tauri::command]
 fn list_memories() -> Result<Vec<Memory>, String> {
     let conn = db::get_conn().map_err(|e| e.to_string())?;
-    let mut stmt = conn.prepare("SELECT id, title, tags, created_at, media_type, filename FROM memories").map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare("SELECT id, title, tags, created_at, media_type, filename FROM memories WHERE deleted_at IS NULL").map_err(|e| e.to_string())?;
     let memories = stmt.query_map([], |row| {
         Ok(Memory {
             id: row.get(0)?,
@@ -109,67 +141,256 @@ struct TranscriptionInput {
 #[tauri::command]
 async fn transcribe_audio(
     input: TranscriptionInput,
-    whisper_client: State<'_, whisper::WhisperClient>,
+    transcriber: State<'_, whisper::TranscriberState>,
 ) -> Result<String, String> {
-    whisper_client
-        .transcribe_audio(&input.file_path, input.language.as_deref())
-        .await
+    let transcriber = transcriber.current();
+    let language = input
+        .language
+        .map(|language| whisper::validate_language(transcriber.as_ref(), &language))
+        .transpose()?;
+    let options = whisper::TranscribeOptions {
+        language,
+        ..Default::default()
+    };
+    let transcript = transcriber
+        .transcribe(whisper::Audio::File(PathBuf::from(input.file_path)), options)
+        .await?;
+    Ok(transcript.text)
 }
 
-// ----------- Sync manager commands ------------
+/// Transcribe a stored audio/video memory and keep its timed segments.
+#[tauri::command]
+async fn transcribe_memory(
+    memory_id: String,
+    language: Option<String>,
+    transcriber: State<'_, whisper::TranscriberState>,
+) -> Result<whisper::Transcript, String> {
+    let options = whisper::TranscribeOptions {
+        language,
+        ..Default::default()
+    };
+    transcripts::transcribe_memory(transcriber.current(), &memory_id, options).await
+}
 
-struct SyncState(Mutex<Option<sync::SyncManager>>);
+#[tauri::command]
+fn get_transcriber_settings() -> Result<whisper::TranscriberSettingsView, String> {
+    Ok(whisper::TranscriberSettings::load()?.redacted())
+}
 
 #[tauri::command]
-fn start_sync(state: State<'_, SyncState>, watch_path: String, target_path: String) -> Result<(), String> {
-    let mut guard = state.0.lock().unwrap();
+fn set_transcriber_settings(
+    settings: whisper::TranscriberSettings,
+    transcriber: State<'_, whisper::TranscriberState>,
+) -> Result<(), String> {
+    transcriber.apply(settings)
+}
 
-    if guard.is_some() {
-        return Err("Sync already running".into());
-    }
+// ----------- Sync manager commands ------------
 
-    let mut manager = sync::SyncManager::new(watch_path, target_path);
-    manager.start_sync().map_err(|e| e.to_string())?;
-    *guard = Some(manager);
+/// Sync a folder pair two-way, reusing the profile for that pair if there
+/// is one.
+#[tauri::command(async)]
+fn start_sync(app: tauri::AppHandle, runner: State<'_, sync::SyncRunner>, watch_path: String, target_path: String) -> Result<(), String> {
+    let profile = match sync::profiles::find_profile(&watch_path, &target_path)? {
+        Some(profile) => profile,
+        None => {
+            let name = Path::new(&target_path)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| target_path.clone());
+            sync::profiles::create_profile(sync::profiles::ProfileInput {
+                name,
+                source: watch_path,
+                target: target_path,
+                direction: sync::profiles::Direction::TwoWay,
+                schedule_minutes: None,
+                include: Vec::new(),
+                exclude: Vec::new(),
+                bandwidth_limit: None,
+            })?
+        }
+    };
+    runner.start(app, &profile.id)
+}
 
-    Ok(())
+/// Stop every running sync profile.
+#[tauri::command(async)]
+fn stop_sync(runner: State<'_, sync::SyncRunner>) -> Result<(), String> {
+    match runner.stop_all()? {
+        0 => Err("Sync is not running".into()),
+        _ => Ok(()),
+    }
 }
 
+/// Progress of each running sync profile and of the devices Syncthing
+/// shares the vault with.
 #[tauri::command]
-fn stop_sync(state: State<'_, SyncState>) -> Result<(), String> {
-    let mut guard = state.0.lock().unwrap();
-    if let Some(manager) = guard.take() {
-        manager.stop_sync();
-        Ok(())
-    } else {
-        Err("Sync is not running".into())
+async fn sync_status(
+    app: tauri::AppHandle,
+    runner: State<'_, sync::SyncRunner>,
+) -> Result<Vec<sync::status::SyncStatus>, String> {
+    if syncthing::SyncthingSettings::load()?.shares_vault() {
+        match syncthing::completion().await {
+            Ok(completions) => runner.update_syncthing(&app, vault::MEDIA_DIR, &completions),
+            Err(e) => runner.syncthing_failed(&format!("Failed to query Syncthing: {}", e)),
+        }
     }
+    Ok(runner.statuses())
+}
+
+/// Newest entries of the persistent sync log.
+#[tauri::command]
+fn sync_log(profile_id: Option<String>, limit: Option<usize>) -> Result<Vec<sync::status::SyncLogEntry>, String> {
+    sync::status::recent_log(profile_id.as_deref(), limit.unwrap_or(100))
 }
 
 // ----------- main function ------------
 
 #[tokio::main]
 async fn main() {
+    // AETHERSYNC_DATA_DIR holds the vault instead of the working directory,
+    // e.g. to run a second instance on the same machine
+    if let Ok(dir) = std::env::var("AETHERSYNC_DATA_DIR") {
+        db::use_data_dir(Path::new(&dir)).expect("Failed to open data directory");
+    }
+
     // Initialize database
     db::init_db().expect("Failed to initialize DB");
 
-    // Create WhisperClient from environment variable
-    let api_key = std::env::var("OPENAI_API_KEY").expect("Missing OPENAI_API_KEY env var");
-    let whisper_client = whisper::WhisperClient::new(api_key);
+    // Transcription backend comes from settings and defaults to the local model
+    let transcriber = whisper::TranscriberState::from_settings().expect("Failed to load transcriber settings");
 
-    // Initialize SyncState (not running yet)
-    let sync_state = SyncState(Mutex::new(None));
+    // Face models are loaded lazily from AETHERSYNC_MODEL_DIR (default ./models/faces)
+    let model_dir = std::env::var("AETHERSYNC_MODEL_DIR").unwrap_or_else(|_| "models/faces".into());
+    let face_state = faces::FaceState::new(faces::FaceConfig::from_dir(model_dir));
+
+    // Originals kept in the clear by earlier versions are sealed once
+    if let Err(e) = storage::seal_plain_originals() {
+        sync::status::log_error(None, &format!("Failed to seal stored originals: {}", e));
+    }
+
+    // Drop half-written media left by a crash in a previous session
+    if let Err(e) = vault::cleanup_temp_files(Path::new(vault::MEDIA_DIR)) {
+        sync::status::log_error(None, &format!("Failed to clean media_store: {}", e));
+    }
 
     tauri::Builder::default()
-        .manage(whisper_client)
-        .manage(sync_state)
+        .manage(transcriber)
+        .manage(sync::SyncRunner::default())
+        .manage(face_state)
+        .manage(jobs::JobQueue::default())
+        .manage(p2p::P2pState::default())
+        .setup(|app| {
+            jobs::start_workers(app.handle().clone(), 2)?;
+            // Profiles that were running at last quit pick up where they left
+            // off; their first passes run off the main thread
+            let handle = app.handle().clone();
+            std::thread::spawn(move || {
+                if let Err(e) = handle.state::<sync::SyncRunner>().resume_enabled(&handle) {
+                    sync::status::log_error(None, &format!("Failed to resume sync: {}", e));
+                }
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             add_memory,
             list_memories,
             get_memory_by_id,
             transcribe_audio,
+            transcribe_memory,
+            get_transcriber_settings,
+            set_transcriber_settings,
             start_sync,
-            stop_sync
+            stop_sync,
+            sync_status,
+            sync_log,
+            commands::set_memory_location,
+            commands::clear_memory_location,
+            commands::get_memory_location,
+            commands::memories_in_bbox,
+            commands::map_markers,
+            commands::search_memories_by_place,
+            commands::reverse_geocode,
+            commands::timeline_buckets,
+            commands::on_this_day,
+            commands::set_face_model_dir,
+            commands::scan_memory_faces,
+            commands::list_face_clusters,
+            commands::list_cluster_faces,
+            commands::name_face_cluster,
+            commands::merge_face_clusters,
+            commands::split_face_cluster,
+            commands::memories_by_face_cluster,
+            commands::create_person,
+            commands::update_person,
+            commands::delete_person,
+            commands::list_people,
+            commands::tag_person,
+            commands::untag_person,
+            commands::people_in_memory,
+            commands::memories_with_people,
+            commands::get_transcript_segments,
+            commands::search_transcripts,
+            commands::list_jobs,
+            commands::queue_transcription,
+            commands::retry_job,
+            commands::edit_transcript_segment,
+            commands::undo_transcript_edit,
+            commands::transcript_segment_history,
+            commands::export_transcript,
+            commands::export_transcript_to_file,
+            commands::supported_languages,
+            commands::set_memory_language,
+            commands::get_memory_translation,
+            commands::get_language_settings,
+            commands::set_language_settings,
+            commands::start_p2p,
+            commands::stop_p2p,
+            commands::p2p_status,
+            commands::list_discovered_devices,
+            commands::list_paired_devices,
+            commands::pair_device,
+            commands::confirm_pairing,
+            commands::pairing_qr,
+            commands::pair_with_qr,
+            commands::unpair_device,
+            commands::sync_with_device,
+            commands::set_device_role,
+            commands::set_memory_title,
+            commands::add_memory_tag,
+            commands::remove_memory_tag,
+            commands::add_memory_to_collection,
+            commands::remove_memory_from_collection,
+            commands::delete_memory,
+            commands::memory_history,
+            commands::get_ipfs_settings,
+            commands::set_ipfs_settings,
+            commands::ipfs_node_version,
+            commands::pin_memory,
+            commands::unpin_memory,
+            commands::list_ipfs_pins,
+            commands::export_ipfs_manifest,
+            commands::restore_from_ipfs_manifest,
+            commands::get_syncthing_settings,
+            commands::set_syncthing_settings,
+            commands::register_syncthing_vault,
+            commands::syncthing_completion,
+            commands::list_sync_profiles,
+            commands::create_sync_profile,
+            commands::update_sync_profile,
+            commands::delete_sync_profile,
+            commands::start_sync_profile,
+            commands::stop_sync_profile,
+            commands::get_storage_policy,
+            commands::set_storage_policy,
+            commands::apply_storage_policy,
+            commands::open_memory_media,
+            commands::get_memory_thumbnail,
+            commands::export_backup,
+            commands::backup_manifest,
+            commands::restore_backup,
+            commands::verify_vault,
+            commands::repair_vault
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri app");
@@ -0,0 +1,162 @@
+use rubato::{
+    Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction,
+};
+use std::io::Cursor;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Sample rate expected by Whisper models.
+pub const TARGET_SAMPLE_RATE: u32 = 16_000;
+
+/// Frames fed to the resampler per call.
+const RESAMPLE_CHUNK: usize = 4096;
+
+/// A slice of a longer recording, positioned on the original timeline.
+#[derive(Debug, Clone)]
+pub struct AudioChunk {
+    pub offset_secs: f64,
+    pub samples: Vec<f32>,
+}
+
+/// Demux the first audio track from an audio or video container
+/// (MP4/MOV, WebM/MKV, WAV, MP3, OGG, FLAC), decode it and return
+/// 16 kHz mono samples. Everything happens in memory, so decrypted
+/// media never touches the disk.
+pub fn decode_to_mono_16k(media_bytes: Vec<u8>, extension: Option<&str>) -> Result<Vec<f32>, String> {
+    let (samples, sample_rate) = decode_mono(media_bytes, extension)?;
+    resample(&samples, sample_rate, TARGET_SAMPLE_RATE)
+}
+
+fn decode_mono(media_bytes: Vec<u8>, extension: Option<&str>) -> Result<(Vec<f32>, u32), String> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(media_bytes)), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Unsupported media container: {}", e))?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL && t.codec_params.sample_rate.is_some())
+        .ok_or("No audio track found")?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.unwrap_or(TARGET_SAMPLE_RATE);
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported audio codec: {}", e))?;
+
+    let mut mono = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(format!("Failed to read media: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                let channels = spec.channels.count().max(1);
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+                mono.extend(
+                    buffer
+                        .samples()
+                        .chunks(channels)
+                        .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+                );
+            }
+            // A corrupt packet should not abort the whole recording.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        }
+    }
+
+    Ok((mono, sample_rate))
+}
+
+/// Band-limited (windowed sinc) resampling of mono samples.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Result<Vec<f32>, String> {
+    if from_rate == to_rate || samples.is_empty() {
+        return Ok(samples.to_vec());
+    }
+
+    let params = SincInterpolationParameters {
+        sinc_len: 256,
+        f_cutoff: 0.95,
+        interpolation: SincInterpolationType::Linear,
+        oversampling_factor: 256,
+        window: WindowFunction::BlackmanHarris2,
+    };
+    let ratio = to_rate as f64 / from_rate as f64;
+    let mut resampler = SincFixedIn::<f32>::new(ratio, 1.0, params, RESAMPLE_CHUNK, 1)
+        .map_err(|e| e.to_string())?;
+
+    let mut output = Vec::with_capacity((samples.len() as f64 * ratio) as usize + RESAMPLE_CHUNK);
+    for chunk in samples.chunks(RESAMPLE_CHUNK) {
+        let frames = if chunk.len() == RESAMPLE_CHUNK {
+            resampler.process(&[chunk], None)
+        } else {
+            resampler.process_partial(Some(&[chunk]), None)
+        }
+        .map_err(|e| e.to_string())?;
+        output.extend_from_slice(&frames[0]);
+    }
+    // Flush the filter tail, then drop the leading filter delay.
+    let tail = resampler
+        .process_partial::<&[f32]>(None, None)
+        .map_err(|e| e.to_string())?;
+    output.extend_from_slice(&tail[0]);
+
+    let delay = resampler.output_delay().min(output.len());
+    output.drain(..delay);
+    output.truncate((samples.len() as f64 * ratio).ceil() as usize);
+    Ok(output)
+}
+
+/// Split a long 16 kHz recording into windows of `chunk_secs`, each
+/// overlapping the previous one by `overlap_secs` so words on a boundary
+/// are heard whole by at least one chunk.
+pub fn chunk_with_overlap(samples: &[f32], chunk_secs: f64, overlap_secs: f64) -> Vec<AudioChunk> {
+    let rate = TARGET_SAMPLE_RATE as f64;
+    let chunk_len = (chunk_secs * rate) as usize;
+    let overlap = ((overlap_secs * rate) as usize).min(chunk_len / 2);
+    let step = chunk_len - overlap;
+
+    if chunk_len == 0 || samples.len() <= chunk_len {
+        return vec![AudioChunk {
+            offset_secs: 0.0,
+            samples: samples.to_vec(),
+        }];
+    }
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < samples.len() {
+        let end = (start + chunk_len).min(samples.len());
+        chunks.push(AudioChunk {
+            offset_secs: start as f64 / rate,
+            samples: samples[start..end].to_vec(),
+        });
+        if end == samples.len() {
+            break;
+        }
+        start += step;
+    }
+    chunks
+}
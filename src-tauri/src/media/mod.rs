@@ -2,10 +2,11 @@ use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::Write;
+use std::io::Cursor;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod audio;
+
 /// Represents metadata about a saved media file
 #[derive(Debug, Clone)]
 pub struct MediaFile {
@@ -42,8 +43,7 @@ pub fn save_media_file(
     let filepath = media_dir.join(&unique_filename);
 
     // Write bytes to file
-    let mut file = File::create(&filepath)?;
-    file.write_all(media_bytes)?;
+    crate::vault::write_atomic(&filepath, media_bytes)?;
 
     let filesize = fs::metadata(&filepath)?.len();
 
@@ -96,3 +96,47 @@ pub fn load_media_file_metadata(filepath: &Path) -> io::Result<MediaFile> {
         media_type,
     })
 }
+
+/// Read the original capture time from an image's EXIF block.
+/// Returns an RFC 3339 timestamp (EXIF carries no zone, so UTC is assumed).
+pub fn extract_capture_time(media_bytes: &[u8]) -> Option<String> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(media_bytes))
+        .ok()?;
+
+    let field = exif
+        .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
+        .or_else(|| exif.get_field(exif::Tag::DateTime, exif::In::PRIMARY))?;
+
+    let raw = match field.value {
+        exif::Value::Ascii(ref parts) => parts.first()?.clone(),
+        _ => return None,
+    };
+    let raw = String::from_utf8(raw).ok()?;
+
+    // EXIF format: "YYYY:MM:DD HH:MM:SS"
+    chrono::NaiveDateTime::parse_from_str(raw.trim(), "%Y:%m:%d %H:%M:%S")
+        .ok()
+        .map(|dt| dt.and_utc().to_rfc3339())
+}
+
+/// Store an original encrypted with its memory's key as `<filename>.enc`,
+/// the only form originals are kept, backed up and replicated in.
+pub fn seal_media(media_dir: &Path, filename: &str, media_bytes: &[u8], key: &[u8; 32]) -> Result<PathBuf, String> {
+    let sealed = crate::crypto::encrypt_bytes(media_bytes, key)?;
+    let encrypted_path = media_dir.join(format!("{}.enc", filename));
+    crate::vault::write_atomic(&encrypted_path, &sealed).map_err(|e| e.to_string())?;
+    Ok(encrypted_path)
+}
+
+/// Load the bytes of a stored media file into memory.
+/// Encrypted blobs (`<filename>.enc`) are decrypted with `key` without
+/// touching the disk; plain files are read as-is.
+pub fn load_media_bytes(media_dir: &Path, filename: &str, key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let encrypted_path = media_dir.join(format!("{}.enc", filename));
+    if encrypted_path.exists() {
+        let data = fs::read(&encrypted_path).map_err(|e| e.to_string())?;
+        return crate::crypto::decrypt_bytes(&data, key);
+    }
+    fs::read(media_dir.join(filename)).map_err(|e| e.to_string())
+}
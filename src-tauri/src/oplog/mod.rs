@@ -0,0 +1,542 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::db;
+use crate::p2p::identity::Identity;
+
+/// A change to a memory's metadata. Tags, people and collections are
+/// observed-remove sets: a remove only cancels the adds it has seen, so
+/// a concurrent re-add survives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OpKind {
+    SetTitle { title: String },
+    AddTag { tag: String },
+    RemoveTag { tag: String, observed: Vec<String> },
+    AddPerson { person_id: String },
+    RemovePerson { person_id: String, observed: Vec<String> },
+    AddToCollection { collection: String },
+    RemoveFromCollection { collection: String, observed: Vec<String> },
+    /// Tombstone; a deleted memory stays deleted.
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Op {
+    pub id: String,
+    pub memory_id: String,
+    pub device_id: String,
+    /// Lamport timestamp; with `device_id` it orders concurrent title edits.
+    pub lamport: i64,
+    pub op: OpKind,
+    pub created_at: String,
+}
+
+/// Metadata obtained by folding a memory's operations. The result does
+/// not depend on the order the operations arrived in.
+#[derive(Debug, Default, PartialEq)]
+pub struct MemoryState {
+    pub title: Option<String>,
+    pub tags: BTreeSet<String>,
+    pub people: BTreeSet<String>,
+    pub collections: BTreeSet<String>,
+    pub deleted_at: Option<String>,
+}
+
+pub fn set_title(memory_id: &str, title: &str) -> Result<Op, String> {
+    if title.trim().is_empty() {
+        return Err("Title is required".into());
+    }
+    record(memory_id, OpKind::SetTitle { title: title.trim().to_string() })
+}
+
+pub fn add_tag(memory_id: &str, tag: &str) -> Result<Op, String> {
+    let tag = normalize_tag(tag)?;
+    record(memory_id, OpKind::AddTag { tag })
+}
+
+pub fn remove_tag(memory_id: &str, tag: &str) -> Result<Op, String> {
+    let tag = normalize_tag(tag)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let observed = live_adds(&conn, memory_id, |op| matches!(op, OpKind::AddTag { tag: t } if *t == tag))?;
+    record(memory_id, OpKind::RemoveTag { tag, observed })
+}
+
+/// This device's id, for the person ops below. Load it before opening
+/// their transaction: the first call writes the identity to the database.
+pub fn local_device_id() -> Result<String, String> {
+    Ok(Identity::load_or_create()?.device_id)
+}
+
+/// Record on `conn` that a person is tagged, unless they already are, so
+/// the caller can write its own rows in the same transaction.
+pub fn add_person(conn: &Connection, device_id: &str, memory_id: &str, person_id: &str) -> Result<(), String> {
+    seed_on(conn, memory_id)?;
+    if fold(&load_ops(conn, memory_id)?).people.contains(person_id) {
+        return Ok(());
+    }
+    record_on(conn, device_id, memory_id, OpKind::AddPerson { person_id: person_id.to_string() }).map(|_| ())
+}
+
+/// Record on `conn` that a person is no longer tagged; see `add_person`.
+pub fn remove_person(conn: &Connection, device_id: &str, memory_id: &str, person_id: &str) -> Result<(), String> {
+    let observed = live_adds(conn, memory_id, |op| matches!(op, OpKind::AddPerson { person_id: p } if p == person_id))?;
+    if observed.is_empty() {
+        return Ok(());
+    }
+    record_on(
+        conn,
+        device_id,
+        memory_id,
+        OpKind::RemovePerson {
+            person_id: person_id.to_string(),
+            observed,
+        },
+    )
+    .map(|_| ())
+}
+
+pub fn add_to_collection(memory_id: &str, collection: &str) -> Result<Op, String> {
+    let collection = collection.trim().to_string();
+    if collection.is_empty() {
+        return Err("Collection name is required".into());
+    }
+    record(memory_id, OpKind::AddToCollection { collection })
+}
+
+pub fn remove_from_collection(memory_id: &str, collection: &str) -> Result<Op, String> {
+    let collection = collection.trim().to_string();
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let observed = live_adds(&conn, memory_id, |op| {
+        matches!(op, OpKind::AddToCollection { collection: c } if *c == collection)
+    })?;
+    record(memory_id, OpKind::RemoveFromCollection { collection, observed })
+}
+
+pub fn delete_memory(memory_id: &str) -> Result<Op, String> {
+    record(memory_id, OpKind::Delete)
+}
+
+/// Operations on a memory, oldest first.
+pub fn history(memory_id: &str) -> Result<Vec<Op>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    load_ops(&conn, memory_id)
+}
+
+/// Store operations received from another device, ignoring ones we
+/// already have, and refresh the affected memories.
+pub fn apply_remote(ops: Vec<Op>) -> Result<usize, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut touched = BTreeSet::new();
+    let mut inserted = 0;
+    for op in ops {
+        let payload = serde_json::to_string(&op.op).map_err(|e| e.to_string())?;
+        inserted += conn
+            .execute(
+                "INSERT OR IGNORE INTO memory_ops (id, memory_id, device_id, lamport, payload, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![op.id, op.memory_id, op.device_id, op.lamport, payload, op.created_at],
+            )
+            .map_err(|e| e.to_string())?;
+        touched.insert(op.memory_id);
+    }
+    for memory_id in touched {
+        materialize(&conn, &memory_id)?;
+    }
+    Ok(inserted)
+}
+
+/// Describe a memory's current metadata as operations, so memories
+/// created before the log existed (or just now) can be edited and merged.
+/// Does nothing once a memory has operations.
+pub fn seed(memory_id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    seed_on(&conn, memory_id)
+}
+
+fn seed_on(conn: &Connection, memory_id: &str) -> Result<(), String> {
+    let has_ops: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM memory_ops WHERE memory_id = ?1)",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if has_ops {
+        return Ok(());
+    }
+
+    let row: Option<(Option<String>, Option<String>)> = conn
+        .query_row(
+            "SELECT title, tags FROM memories WHERE id = ?1",
+            params![memory_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let (title, tags) = row.ok_or("Memory not found")?;
+
+    // Seeds get ids derived from the content, so devices that seed the
+    // same replicated row independently produce the same operations.
+    if let Some(title) = title.filter(|t| !t.trim().is_empty()) {
+        let name = format!("title:{:x}", Sha256::digest(title.as_bytes()));
+        insert_seed(conn, memory_id, &name, OpKind::SetTitle { title })?;
+    }
+    for tag in split_tags(tags.as_deref().unwrap_or("")) {
+        insert_seed(conn, memory_id, &format!("tag:{}", tag), OpKind::AddTag { tag })?;
+    }
+
+    let mut stmt = conn
+        .prepare("SELECT person_id FROM memory_people WHERE memory_id = ?1")
+        .map_err(|e| e.to_string())?;
+    let people = stmt
+        .query_map(params![memory_id], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    for person_id in people {
+        insert_seed(conn, memory_id, &format!("person:{}", person_id), OpKind::AddPerson { person_id })?;
+    }
+    Ok(())
+}
+
+fn insert_seed(conn: &Connection, memory_id: &str, name: &str, kind: OpKind) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR IGNORE INTO memory_ops (id, memory_id, device_id, lamport, payload, created_at)
+         VALUES (?1, ?2, '', 0, ?3, '')",
+        params![
+            format!("seed:{}:{}", memory_id, name),
+            memory_id,
+            serde_json::to_string(&kind).map_err(|e| e.to_string())?
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Fold operations into metadata. Titles are last-writer-wins by
+/// (lamport, device id, op id); set members are present while at least
+/// one of their adds has not been observed by a remove.
+pub fn fold(ops: &[Op]) -> MemoryState {
+    let removed: HashSet<&str> = ops
+        .iter()
+        .flat_map(|op| match &op.op {
+            OpKind::RemoveTag { observed, .. }
+            | OpKind::RemovePerson { observed, .. }
+            | OpKind::RemoveFromCollection { observed, .. } => observed.iter().map(String::as_str).collect(),
+            _ => Vec::new(),
+        })
+        .collect();
+    let live = |op: &Op| !removed.contains(op.id.as_str());
+
+    let mut state = MemoryState::default();
+    let mut title_order: Option<(i64, &str, &str)> = None;
+    for op in ops {
+        match &op.op {
+            OpKind::SetTitle { title } => {
+                let order = (op.lamport, op.device_id.as_str(), op.id.as_str());
+                if title_order.map_or(true, |current| order > current) {
+                    title_order = Some(order);
+                    state.title = Some(title.clone());
+                }
+            }
+            OpKind::AddTag { tag } if live(op) => {
+                state.tags.insert(tag.clone());
+            }
+            OpKind::AddPerson { person_id } if live(op) => {
+                state.people.insert(person_id.clone());
+            }
+            OpKind::AddToCollection { collection } if live(op) => {
+                state.collections.insert(collection.clone());
+            }
+            OpKind::Delete => {
+                // Earliest tombstone, so every device shows the same time.
+                if state.deleted_at.as_deref().map_or(true, |at| op.created_at.as_str() < at) {
+                    state.deleted_at = Some(op.created_at.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    state
+}
+
+fn record(memory_id: &str, kind: OpKind) -> Result<Op, String> {
+    let device_id = local_device_id()?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    record_on(&conn, &device_id, memory_id, kind)
+}
+
+fn record_on(conn: &Connection, device_id: &str, memory_id: &str, kind: OpKind) -> Result<Op, String> {
+    seed_on(conn, memory_id)?;
+    let op = insert(conn, device_id, memory_id, kind)?;
+    materialize(conn, memory_id)?;
+    Ok(op)
+}
+
+fn insert(conn: &Connection, device_id: &str, memory_id: &str, kind: OpKind) -> Result<Op, String> {
+    // Above every timestamp seen so far, including remote ones.
+    let lamport: i64 = conn
+        .query_row("SELECT COALESCE(MAX(lamport), 0) + 1 FROM memory_ops", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let op = Op {
+        id: uuid::Uuid::new_v4().to_string(),
+        memory_id: memory_id.to_string(),
+        device_id: device_id.to_string(),
+        lamport,
+        op: kind,
+        created_at: Utc::now().to_rfc3339(),
+    };
+    conn.execute(
+        "INSERT INTO memory_ops (id, memory_id, device_id, lamport, payload, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            op.id,
+            op.memory_id,
+            op.device_id,
+            op.lamport,
+            serde_json::to_string(&op.op).map_err(|e| e.to_string())?,
+            op.created_at
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(op)
+}
+
+/// Ids of the adds a remove issued now would cancel.
+fn live_adds(conn: &Connection, memory_id: &str, matches: impl Fn(&OpKind) -> bool) -> Result<Vec<String>, String> {
+    seed_on(conn, memory_id)?;
+    let ops = load_ops(conn, memory_id)?;
+    let removed: HashSet<String> = ops
+        .iter()
+        .flat_map(|op| match &op.op {
+            OpKind::RemoveTag { observed, .. }
+            | OpKind::RemovePerson { observed, .. }
+            | OpKind::RemoveFromCollection { observed, .. } => observed.clone(),
+            _ => Vec::new(),
+        })
+        .collect();
+    Ok(ops
+        .into_iter()
+        .filter(|op| matches(&op.op) && !removed.contains(&op.id))
+        .map(|op| op.id)
+        .collect())
+}
+
+/// Write the folded state back to the memory's row and its people tags.
+/// Memories we have operations for but no row yet are left until the
+/// row arrives.
+pub fn materialize(conn: &Connection, memory_id: &str) -> Result<(), String> {
+    let ops = load_ops(conn, memory_id)?;
+    if ops.is_empty() {
+        return Ok(());
+    }
+    let state = fold(&ops);
+
+    let tags = state.tags.iter().cloned().collect::<Vec<_>>().join(", ");
+    let collections = serde_json::to_string(&state.collections).map_err(|e| e.to_string())?;
+    let updated = conn
+        .execute(
+            "UPDATE memories SET title = COALESCE(?1, title), tags = ?2, collections = ?3, deleted_at = ?4
+             WHERE id = ?5",
+            params![state.title, tags, collections, state.deleted_at, memory_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Ok(());
+    }
+
+    let mut stmt = conn
+        .prepare("SELECT person_id FROM memory_people WHERE memory_id = ?1")
+        .map_err(|e| e.to_string())?;
+    let tagged: BTreeSet<String> = stmt
+        .query_map(params![memory_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+
+    for person_id in tagged.difference(&state.people) {
+        conn.execute(
+            "DELETE FROM memory_people WHERE memory_id = ?1 AND person_id = ?2",
+            params![memory_id, person_id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    for person_id in state.people.difference(&tagged) {
+        // People are local to each vault; skip ones this device doesn't know.
+        conn.execute(
+            "INSERT INTO memory_people (memory_id, person_id)
+             SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM people WHERE id = ?2)",
+            params![memory_id, person_id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn load_ops(conn: &Connection, memory_id: &str) -> Result<Vec<Op>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, memory_id, device_id, lamport, payload, created_at FROM memory_ops
+             WHERE memory_id = ?1
+             ORDER BY lamport, device_id, id",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params![memory_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    rows.into_iter()
+        .map(|(id, memory_id, device_id, lamport, payload, created_at)| {
+            Ok(Op {
+                op: serde_json::from_str(&payload).map_err(|e| e.to_string())?,
+                id,
+                memory_id,
+                device_id,
+                lamport,
+                created_at,
+            })
+        })
+        .collect()
+}
+
+fn normalize_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_string();
+    if tag.is_empty() || tag.contains(',') {
+        return Err("Tags must be non-empty and contain no commas".into());
+    }
+    Ok(tag)
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Operation digests for every memory, keyed by memory id.
+pub fn digests() -> Result<BTreeMap<String, String>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT memory_id, id FROM memory_ops ORDER BY memory_id, id")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?;
+
+    let mut hashers: BTreeMap<String, Sha256> = BTreeMap::new();
+    for row in rows {
+        let (memory_id, id) = row.map_err(|e| e.to_string())?;
+        let hasher = hashers.entry(memory_id).or_default();
+        hasher.update(id.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(hashers
+        .into_iter()
+        .map(|(memory_id, hasher)| (memory_id, format!("{:x}", hasher.finalize())))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Ops per generated history; removes observe ids from the same range.
+    const OPS: usize = 24;
+
+    fn name() -> impl Strategy<Value = String> {
+        prop::sample::select(vec!["a", "b", "c"]).prop_map(String::from)
+    }
+
+    fn observed() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec((0..OPS).prop_map(|i| format!("op{}", i)), 0..4)
+    }
+
+    fn op_kind() -> impl Strategy<Value = OpKind> {
+        prop_oneof![
+            name().prop_map(|title| OpKind::SetTitle { title }),
+            name().prop_map(|tag| OpKind::AddTag { tag }),
+            (name(), observed()).prop_map(|(tag, observed)| OpKind::RemoveTag { tag, observed }),
+            name().prop_map(|person_id| OpKind::AddPerson { person_id }),
+            (name(), observed()).prop_map(|(person_id, observed)| OpKind::RemovePerson { person_id, observed }),
+            name().prop_map(|collection| OpKind::AddToCollection { collection }),
+            (name(), observed())
+                .prop_map(|(collection, observed)| OpKind::RemoveFromCollection { collection, observed }),
+            Just(OpKind::Delete),
+        ]
+    }
+
+    /// A history from two devices, with colliding Lamport timestamps.
+    fn history() -> impl Strategy<Value = Vec<Op>> {
+        let op = (op_kind(), 0i64..4, prop::sample::select(vec!["device-a", "device-b"]), 0u32..6);
+        prop::collection::vec(op, 0..OPS).prop_map(|ops| {
+            ops.into_iter()
+                .enumerate()
+                .map(|(i, (op, lamport, device_id, minute))| Op {
+                    id: format!("op{}", i),
+                    memory_id: "memory".into(),
+                    device_id: device_id.into(),
+                    lamport,
+                    op,
+                    created_at: format!("2024-01-01T00:{:02}:00+00:00", minute),
+                })
+                .collect()
+        })
+    }
+
+    /// A history and the same ops as another device might receive them:
+    /// shuffled, with some delivered more than once.
+    fn history_and_delivery() -> impl Strategy<Value = (Vec<Op>, Vec<Op>)> {
+        history()
+            .prop_flat_map(|ops| {
+                let len = ops.len().max(1);
+                (Just(ops), prop::collection::vec(0..len, 0..8))
+            })
+            .prop_flat_map(|(ops, repeats)| {
+                let mut delivered = ops.clone();
+                delivered.extend(repeats.into_iter().filter_map(|i| ops.get(i).cloned()));
+                (Just(ops), Just(delivered).prop_shuffle())
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn fold_ignores_delivery_order_and_duplicates((ops, delivered) in history_and_delivery()) {
+            prop_assert_eq!(fold(&ops), fold(&delivered));
+        }
+
+        #[test]
+        fn fold_of_merged_histories_matches_either_merge_order(
+            (ours, theirs) in (history(), history()),
+        ) {
+            // Rename one side's ids so both devices contribute distinct ops.
+            let theirs: Vec<Op> = theirs
+                .into_iter()
+                .map(|op| Op { id: format!("{}'", op.id), ..op })
+                .collect();
+            let ours_first: Vec<Op> = ours.iter().chain(&theirs).cloned().collect();
+            let theirs_first: Vec<Op> = theirs.iter().chain(&ours).cloned().collect();
+            prop_assert_eq!(fold(&ours_first), fold(&theirs_first));
+        }
+    }
+}
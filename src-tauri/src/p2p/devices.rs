@@ -0,0 +1,129 @@
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+
+use crate::db;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub public_key: String, // base64 X25519
+    pub role: String,       // member, relay
+    pub last_address: Option<String>,
+    pub paired_at: String,
+    pub last_seen_at: Option<String>,
+}
+
+const DEVICE_COLUMNS: &str = "id, name, public_key, last_address, paired_at, last_seen_at, role";
+
+/// Store a newly paired device, replacing any earlier pairing with it.
+pub fn save_device(id: &str, name: &str, public_key: &str, address: Option<&str>) -> Result<Device, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "DELETE FROM devices WHERE public_key = ?1 AND id != ?2",
+        params![public_key, id],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO devices (id, name, public_key, last_address, paired_at, last_seen_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+         ON CONFLICT(id) DO UPDATE SET
+            name = excluded.name,
+            public_key = excluded.public_key,
+            last_address = excluded.last_address,
+            paired_at = excluded.paired_at,
+            last_seen_at = excluded.last_seen_at",
+        params![id, name, public_key, address, now],
+    )
+    .map_err(|e| e.to_string())?;
+    get_device(id)?.ok_or_else(|| "Device not found".to_string())
+}
+
+/// The paired device holding `public_key`, if any.
+pub fn find_by_public_key(public_key: &str) -> Result<Option<Device>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        &format!("SELECT {} FROM devices WHERE public_key = ?1", DEVICE_COLUMNS),
+        params![public_key],
+        row_to_device,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// Relays store and forward ciphertext but are never sent memory keys.
+pub fn set_role(id: &str, role: &str) -> Result<(), String> {
+    if role != "member" && role != "relay" {
+        return Err(format!("Unknown device role '{}'", role));
+    }
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let updated = conn
+        .execute("UPDATE devices SET role = ?1 WHERE id = ?2", params![role, id])
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err("Device not found".into());
+    }
+    Ok(())
+}
+
+pub fn get_device(id: &str) -> Result<Option<Device>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        &format!("SELECT {} FROM devices WHERE id = ?1", DEVICE_COLUMNS),
+        params![id],
+        row_to_device,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn list_devices() -> Result<Vec<Device>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM devices ORDER BY name", DEVICE_COLUMNS))
+        .map_err(|e| e.to_string())?;
+
+    let devices = stmt
+        .query_map([], row_to_device)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(devices)
+}
+
+/// Record where a paired device was last seen on the network.
+pub fn touch_device(id: &str, address: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE devices SET last_address = ?1, last_seen_at = ?2 WHERE id = ?3",
+        params![address, Utc::now().to_rfc3339(), id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn remove_device(id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let deleted = conn
+        .execute("DELETE FROM devices WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    if deleted == 0 {
+        return Err("Device not found".into());
+    }
+    Ok(())
+}
+
+fn row_to_device(row: &rusqlite::Row) -> rusqlite::Result<Device> {
+    Ok(Device {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        public_key: row.get(2)?,
+        last_address: row.get(3)?,
+        paired_at: row.get(4)?,
+        last_seen_at: row.get(5)?,
+        role: row.get(6)?,
+    })
+}
@@ -0,0 +1,109 @@
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::devices;
+use super::identity::Identity;
+
+/// DNS-SD service type every vault advertises.
+pub const SERVICE_TYPE: &str = "_aethersync._tcp.local.";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredDevice {
+    pub device_id: String,
+    pub name: String,
+    pub public_key: String,
+    pub addresses: Vec<SocketAddr>,
+}
+
+/// Advertises this device over mDNS and keeps the set of other vaults
+/// currently visible on the local network.
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    fullname: String,
+    /// Keyed by DNS-SD full name, so removals can be matched.
+    peers: Arc<Mutex<HashMap<String, DiscoveredDevice>>>,
+}
+
+impl Discovery {
+    pub fn start(identity: &Identity, port: u16) -> Result<Self, String> {
+        let daemon = ServiceDaemon::new().map_err(|e| e.to_string())?;
+
+        let public_key = identity.public_key_b64();
+        let properties = [
+            ("id", identity.device_id.as_str()),
+            ("name", identity.device_name.as_str()),
+            ("pk", public_key.as_str()),
+        ];
+        let host = format!("aethersync-{}.local.", &identity.device_id[..8]);
+        let info = ServiceInfo::new(SERVICE_TYPE, &identity.device_id, &host, "", port, &properties[..])
+            .map_err(|e| e.to_string())?
+            .enable_addr_auto();
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info).map_err(|e| e.to_string())?;
+
+        let peers: Arc<Mutex<HashMap<String, DiscoveredDevice>>> = Arc::default();
+        let receiver = daemon.browse(SERVICE_TYPE).map_err(|e| e.to_string())?;
+        let own_id = identity.device_id.clone();
+        let found = peers.clone();
+        thread::spawn(move || {
+            while let Ok(event) = receiver.recv() {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let device_id = match info.get_property_val_str("id") {
+                            Some(id) if id != own_id => id.to_string(),
+                            _ => continue,
+                        };
+                        let port = info.get_port();
+                        let device = DiscoveredDevice {
+                            device_id,
+                            name: info.get_property_val_str("name").unwrap_or_default().to_string(),
+                            public_key: info.get_property_val_str("pk").unwrap_or_default().to_string(),
+                            addresses: info
+                                .get_addresses()
+                                .iter()
+                                .map(|ip| SocketAddr::new(*ip, port))
+                                .collect(),
+                        };
+                        if let Some(addr) = device.addresses.first() {
+                            // No-op unless this is a device we paired with.
+                            let _ = devices::touch_device(&device.device_id, &addr.ip().to_string());
+                        }
+                        found.lock().unwrap().insert(info.get_fullname().to_string(), device);
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        found.lock().unwrap().remove(&fullname);
+                    }
+                    ServiceEvent::SearchStopped(_) => break,
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self { daemon, fullname, peers })
+    }
+
+    pub fn devices(&self) -> Vec<DiscoveredDevice> {
+        let mut devices: Vec<_> = self.peers.lock().unwrap().values().cloned().collect();
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+        devices
+    }
+
+    pub fn find(&self, device_id: &str) -> Option<DiscoveredDevice> {
+        self.peers
+            .lock()
+            .unwrap()
+            .values()
+            .find(|d| d.device_id == device_id)
+            .cloned()
+    }
+
+    /// Withdraw the advertisement and stop browsing.
+    pub fn stop(&self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
@@ -0,0 +1,98 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::settings;
+
+const IDENTITY_KEY: &str = "device_identity";
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoredIdentity {
+    device_id: String,
+    device_name: String,
+    secret_key: String, // base64
+}
+
+/// This device's long-term identity: a stable id and an X25519 keypair
+/// generated on first start. Paired devices know it by its public key.
+#[derive(Clone)]
+pub struct Identity {
+    pub device_id: String,
+    pub device_name: String,
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub fn load_or_create() -> Result<Self, String> {
+        let stored: StoredIdentity = settings::get_json(IDENTITY_KEY)?;
+        if !stored.device_id.is_empty() {
+            let bytes: [u8; 32] = STANDARD
+                .decode(&stored.secret_key)
+                .map_err(|e| e.to_string())?
+                .try_into()
+                .map_err(|_| "Stored device key has the wrong length".to_string())?;
+            return Ok(Self {
+                device_id: stored.device_id,
+                device_name: stored.device_name,
+                secret: StaticSecret::from(bytes),
+            });
+        }
+
+        let identity = Self::generate(default_device_name());
+        settings::set_json(
+            IDENTITY_KEY,
+            &StoredIdentity {
+                device_id: identity.device_id.clone(),
+                device_name: identity.device_name.clone(),
+                secret_key: STANDARD.encode(identity.secret.to_bytes()),
+            },
+        )?;
+        Ok(identity)
+    }
+
+    /// A fresh identity with a new id and keypair, not yet stored.
+    pub fn generate(device_name: String) -> Self {
+        Self {
+            device_id: uuid::Uuid::new_v4().to_string(),
+            device_name,
+            secret: StaticSecret::random_from_rng(rand::rngs::OsRng),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.secret)
+    }
+
+    pub fn public_key_b64(&self) -> String {
+        STANDARD.encode(self.public_key().as_bytes())
+    }
+
+    /// Raw private key, for the Noise handshake.
+    pub fn secret_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    /// X25519 shared secret with a peer's public key.
+    pub fn agree(&self, peer: &PublicKey) -> [u8; 32] {
+        self.secret.diffie_hellman(peer).to_bytes()
+    }
+}
+
+pub fn decode_public_key(value: &str) -> Result<PublicKey, String> {
+    let bytes: [u8; 32] = STANDARD
+        .decode(value)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|_| "Public key must be 32 bytes".to_string())?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// `AETHERSYNC_DEVICE_NAME`, else the host name, so two instances on one
+/// machine can be told apart.
+fn default_device_name() -> String {
+    std::env::var("AETHERSYNC_DEVICE_NAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .unwrap_or_else(|_| "AetherSync device".to_string())
+}
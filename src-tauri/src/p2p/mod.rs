@@ -0,0 +1,238 @@
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tauri::async_runtime::JoinHandle;
+use tauri::AppHandle;
+use tokio::net::TcpListener;
+
+pub mod devices;
+pub mod discovery;
+pub mod identity;
+pub mod pairing;
+pub mod replication;
+mod secure;
+mod wire;
+
+use devices::Device;
+use discovery::{DiscoveredDevice, Discovery};
+use identity::Identity;
+use pairing::{PairMessage, PairingQr, PairingSession, Pairings};
+use replication::ReplicationReport;
+use secure::{SecureConn, PROTOCOL_MAGIC};
+use wire::Conn;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeInfo {
+    pub device_id: String,
+    pub device_name: String,
+    pub public_key: String,
+    pub port: u16,
+}
+
+/// A running LAN presence: the listener other devices connect to and
+/// the mDNS advertisement pointing at it.
+struct Node {
+    identity: Identity,
+    port: u16,
+    discovery: Discovery,
+    pairings: Arc<Pairings>,
+    listener: JoinHandle<()>,
+}
+
+/// Managed Tauri state; `None` while peer-to-peer is switched off.
+#[derive(Default)]
+pub struct P2pState {
+    node: Mutex<Option<Arc<Node>>>,
+}
+
+impl P2pState {
+    /// Listen on `AETHERSYNC_P2P_PORT` (any free port by default) and
+    /// advertise the vault on the local network.
+    pub async fn start(&self, app: AppHandle) -> Result<NodeInfo, String> {
+        if let Some(node) = self.node.lock().unwrap().as_ref() {
+            return Ok(node.info());
+        }
+
+        let identity = Identity::load_or_create()?;
+        let port = std::env::var("AETHERSYNC_P2P_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(0u16);
+        let listener = TcpListener::bind(("0.0.0.0", port)).await.map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+
+        let discovery = Discovery::start(&identity, port)?;
+        let pairings = Arc::new(Pairings::default());
+        let listener = tauri::async_runtime::spawn(accept_loop(app, listener, identity.clone(), pairings.clone()));
+
+        let node = Arc::new(Node {
+            identity,
+            port,
+            discovery,
+            pairings,
+            listener,
+        });
+        let info = node.info();
+
+        let mut guard = self.node.lock().unwrap();
+        if let Some(existing) = guard.as_ref() {
+            // Started concurrently; keep the first one.
+            node.shutdown();
+            return Ok(existing.info());
+        }
+        *guard = Some(node);
+        Ok(info)
+    }
+
+    pub fn stop(&self) -> Result<(), String> {
+        match self.node.lock().unwrap().take() {
+            Some(node) => {
+                node.shutdown();
+                Ok(())
+            }
+            None => Err("Peer-to-peer is not running".into()),
+        }
+    }
+
+    pub fn info(&self) -> Option<NodeInfo> {
+        self.node.lock().unwrap().as_ref().map(|node| node.info())
+    }
+
+    pub fn discovered_devices(&self) -> Result<Vec<DiscoveredDevice>, String> {
+        Ok(self.running()?.discovery.devices())
+    }
+
+    /// Begin pairing with a discovered device by comparing short codes.
+    pub async fn pair(&self, app: AppHandle, device_id: &str) -> Result<PairingSession, String> {
+        let node = self.running()?;
+        let device = node
+            .discovery
+            .find(device_id)
+            .ok_or("Device is no longer visible on the network")?;
+        pairing::pair_with(app, node.identity.clone(), node.pairings.clone(), &device.addresses).await
+    }
+
+    pub fn confirm_pairing(&self, session_id: &str, accept: bool) -> Result<(), String> {
+        self.running()?.pairings.decide(session_id, accept)
+    }
+
+    pub fn pairing_qr(&self) -> Result<PairingQr, String> {
+        let node = self.running()?;
+        pairing::pairing_qr(&node.identity, &node.pairings, node.port)
+    }
+
+    pub async fn pair_with_qr(&self, payload: &str) -> Result<Device, String> {
+        let identity = self.running()?.identity.clone();
+        pairing::pair_with_qr(&identity, payload).await
+    }
+
+    /// Exchange missing memories and blobs with a paired device in both
+    /// directions. Safe to repeat after a dropped connection: finished
+    /// items are not sent again and partial blobs resume.
+    pub async fn sync_with(&self, device_id: &str) -> Result<ReplicationReport, String> {
+        let node = self.running()?;
+        let device = devices::get_device(device_id)?.ok_or("Device is not paired")?;
+        let found = node
+            .discovery
+            .find(device_id)
+            .ok_or("Device is not visible on the network")?;
+        let mut conn = SecureConn::connect(&node.identity, &device, &found.addresses).await?;
+        replication::run_initiator(&mut conn).await
+    }
+
+    /// Download one memory's blob to `destination` from the first paired
+    /// device on the network that has it.
+    pub async fn fetch_blob(&self, memory_id: &str, hash: &str, destination: &Path) -> Result<(), String> {
+        let node = self.running()?;
+        let mut last_error = "No paired device is visible on the network".to_string();
+        for device in devices::list_devices()? {
+            let Some(found) = node.discovery.find(&device.id) else {
+                continue;
+            };
+            let result = match SecureConn::connect(&node.identity, &device, &found.addresses).await {
+                Ok(mut conn) => replication::run_fetch(&mut conn, memory_id, hash, destination).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) => last_error = format!("{}: {}", device.name, e),
+            }
+        }
+        Err(last_error)
+    }
+
+    fn running(&self) -> Result<Arc<Node>, String> {
+        self.node
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| "Peer-to-peer is not running".to_string())
+    }
+}
+
+impl Node {
+    fn info(&self) -> NodeInfo {
+        NodeInfo {
+            device_id: self.identity.device_id.clone(),
+            device_name: self.identity.device_name.clone(),
+            public_key: self.identity.public_key_b64(),
+            port: self.port,
+        }
+    }
+
+    fn shutdown(&self) {
+        self.discovery.stop();
+        self.listener.abort();
+    }
+}
+
+async fn accept_loop(app: AppHandle, listener: TcpListener, identity: Identity, pairings: Arc<Pairings>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("P2P accept error: {}", e);
+                continue;
+            }
+        };
+        let app = app.clone();
+        let identity = identity.clone();
+        let pairings = pairings.clone();
+        tauri::async_runtime::spawn(async move {
+            // Pairing starts with a JSON line, replication with a marker.
+            let mut first = [0u8; 1];
+            let pairing = matches!(stream.peek(&mut first).await, Ok(1) if first[0] == b'{');
+            let mut conn = match Conn::new(stream) {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+            let peer_addr = conn.peer_addr;
+
+            if !pairing {
+                if let Err(e) = replicate_incoming(&identity, conn).await {
+                    eprintln!("Replication with {} failed: {}", peer_addr, e);
+                }
+                return;
+            }
+            match conn.recv::<PairMessage>().await {
+                Ok(hello @ PairMessage::Hello { .. }) => pairing::respond(app, identity, pairings, conn, hello).await,
+                Ok(_) => eprintln!("Unexpected first message from {}", peer_addr),
+                Err(e) => eprintln!("P2P connection from {} failed: {}", peer_addr, e),
+            }
+        });
+    }
+}
+
+async fn replicate_incoming(identity: &Identity, mut conn: Conn) -> Result<(), String> {
+    if conn.recv_raw(PROTOCOL_MAGIC.len()).await? != PROTOCOL_MAGIC {
+        return Err("Unknown protocol".into());
+    }
+    let mut conn = SecureConn::accept(identity, conn).await?;
+    let report = replication::run_responder(&mut conn).await?;
+    devices::touch_device(&conn.peer.id, &conn.peer_addr().ip().to_string())?;
+    println!(
+        "Replicated from {}: {} memories, {} blobs",
+        conn.peer.name, report.memories_received, report.blobs_received
+    );
+    Ok(())
+}
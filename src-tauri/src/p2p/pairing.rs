@@ -0,0 +1,553 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+
+use super::devices::{self, Device};
+use super::identity::{decode_public_key, Identity};
+use super::wire::Conn;
+
+/// Emitted when another device asks to pair; carries a `PairingSession`.
+pub const PAIRING_REQUEST_EVENT: &str = "pairing-request";
+/// Emitted when a pairing finishes either way; carries a `PairingResult`.
+pub const PAIRING_RESULT_EVENT: &str = "pairing-result";
+
+/// How long both people have to compare the code.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+/// How long a displayed QR code stays valid.
+const QR_CODE_TTL: Duration = Duration::from_secs(300);
+/// Wrong QR codes tolerated before every code on screen is withdrawn,
+/// so the eight digits cannot be guessed while one is displayed.
+const MAX_CODE_FAILURES: u32 = 5;
+
+/// The initiator commits to its nonce before seeing the responder's and
+/// reveals it last, so nobody in between can pick nonces until the short
+/// codes on both screens happen to match.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PairMessage {
+    Hello {
+        device_id: String,
+        name: String,
+        public_key: String,
+        /// `commitment(nonce, public_key)` of the initiator's nonce.
+        commitment: String,
+        /// One-time code read from the responder's QR code, if any.
+        code: Option<String>,
+    },
+    Reply {
+        device_id: String,
+        name: String,
+        public_key: String,
+        nonce: String,
+    },
+    Reveal {
+        nonce: String,
+    },
+    Confirm {
+        accepted: bool,
+        proof: String,
+    },
+}
+
+/// A pairing waiting for the user to compare codes.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingSession {
+    pub id: String,
+    pub peer_id: String,
+    pub peer_name: String,
+    /// Six digits shown on both screens; they match only if nobody sits
+    /// between the two devices.
+    pub sas: String,
+    pub incoming: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingResult {
+    pub session_id: String,
+    pub device: Option<Device>,
+    pub error: Option<String>,
+}
+
+/// Contents of the pairing QR code.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QrPayload {
+    pub v: u8,
+    pub id: String,
+    pub name: String,
+    pub pk: String,
+    pub addrs: Vec<SocketAddr>,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingQr {
+    pub payload: String,
+    pub svg: String,
+}
+
+/// The other side of a completed hello exchange.
+struct Peer {
+    id: String,
+    name: String,
+    public_key: String,
+    shared: [u8; 32],
+    transcript: [u8; 32],
+}
+
+/// Pairings awaiting a decision and QR codes currently on screen.
+#[derive(Default)]
+pub struct Pairings {
+    pending: Mutex<HashMap<String, oneshot::Sender<bool>>>,
+    codes: Mutex<QrCodes>,
+}
+
+#[derive(Default)]
+struct QrCodes {
+    issued: HashMap<String, Instant>,
+    /// Wrong codes presented since the current ones were issued.
+    failures: u32,
+}
+
+impl Pairings {
+    /// Deliver the user's decision for a pairing started on either side.
+    pub fn decide(&self, session_id: &str, accept: bool) -> Result<(), String> {
+        let sender = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(session_id)
+            .ok_or("Pairing not found or expired")?;
+        sender.send(accept).map_err(|_| "Pairing already ended".to_string())
+    }
+
+    fn wait_for(&self, session_id: &str) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(session_id.to_string(), tx);
+        rx
+    }
+
+    fn forget(&self, session_id: &str) {
+        self.pending.lock().unwrap().remove(session_id);
+    }
+
+    pub fn new_code(&self) -> String {
+        let code = format!("{:08}", rand::rngs::OsRng.next_u32() % 100_000_000);
+        let mut codes = self.codes.lock().unwrap();
+        codes.issued.retain(|_, issued| issued.elapsed() < QR_CODE_TTL);
+        if codes.issued.is_empty() {
+            codes.failures = 0;
+        }
+        codes.issued.insert(code.clone(), Instant::now());
+        code
+    }
+
+    /// Codes are single use, and too many wrong ones withdraw them all.
+    fn take_code(&self, code: &str) -> bool {
+        let mut codes = self.codes.lock().unwrap();
+        match codes.issued.remove(code) {
+            Some(issued) => issued.elapsed() < QR_CODE_TTL,
+            None => {
+                codes.failures += 1;
+                if codes.failures >= MAX_CODE_FAILURES {
+                    codes.issued.clear();
+                }
+                false
+            }
+        }
+    }
+}
+
+/// QR code another device can scan to pair without comparing digits:
+/// it carries our public key (authenticating us) and a one-time code
+/// (proving the scanner saw our screen).
+pub fn pairing_qr(identity: &Identity, pairings: &Pairings, port: u16) -> Result<PairingQr, String> {
+    let addrs = if_addrs::get_if_addrs()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|iface| iface.ip().is_ipv4())
+        .map(|iface| SocketAddr::new(iface.ip(), port))
+        .collect();
+    let payload = QrPayload {
+        v: 1,
+        id: identity.device_id.clone(),
+        name: identity.device_name.clone(),
+        pk: identity.public_key_b64(),
+        addrs,
+        code: pairings.new_code(),
+    };
+    let payload = serde_json::to_string(&payload).map_err(|e| e.to_string())?;
+    let svg = qrcode::QrCode::new(payload.as_bytes())
+        .map_err(|e| e.to_string())?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(240, 240)
+        .build();
+    Ok(PairingQr { payload, svg })
+}
+
+/// Start pairing with a discovered device. Returns once both sides have
+/// derived the short code; the pairing completes in the background after
+/// `Pairings::decide` and reports through `PAIRING_RESULT_EVENT`.
+pub async fn pair_with(
+    app: AppHandle,
+    identity: Identity,
+    pairings: std::sync::Arc<Pairings>,
+    addrs: &[SocketAddr],
+) -> Result<PairingSession, String> {
+    let mut conn = Conn::connect(addrs).await?;
+    let peer = handshake(&mut conn, &identity, None).await?;
+
+    let session = PairingSession {
+        id: uuid::Uuid::new_v4().to_string(),
+        peer_id: peer.id.clone(),
+        peer_name: peer.name.clone(),
+        sas: short_code(&peer),
+        incoming: false,
+    };
+    let decision = pairings.wait_for(&session.id);
+    let session_id = session.id.clone();
+    tauri::async_runtime::spawn(async move {
+        let result = match tokio::time::timeout(CONFIRM_TIMEOUT, decision).await {
+            Ok(Ok(accepted)) => finish(&mut conn, &identity, &peer, accepted).await,
+            _ => Err("Pairing timed out".to_string()),
+        };
+        pairings.forget(&session_id);
+        report(&app, &session_id, result);
+    });
+    Ok(session)
+}
+
+/// Pair using the contents of another device's QR code.
+pub async fn pair_with_qr(identity: &Identity, payload: &str) -> Result<Device, String> {
+    let qr: QrPayload = serde_json::from_str(payload).map_err(|_| "Not an AetherSync pairing code".to_string())?;
+    let mut conn = Conn::connect(&qr.addrs).await?;
+    let peer = handshake(&mut conn, identity, Some(qr.code.clone())).await?;
+    if peer.public_key != qr.pk || peer.id != qr.id {
+        return Err("Device does not match the scanned code".into());
+    }
+    finish(&mut conn, identity, &peer, true).await
+}
+
+/// Handle a pairing request arriving on our listener. Requests carrying
+/// a valid QR code are accepted directly; others wait for the user.
+pub async fn respond(app: AppHandle, identity: Identity, pairings: std::sync::Arc<Pairings>, mut conn: Conn, hello: PairMessage) {
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let result = respond_with(&mut conn, &identity, &pairings, hello, &session_id, |session| {
+        if let Err(e) = app.emit(PAIRING_REQUEST_EVENT, session) {
+            eprintln!("Failed to emit pairing request: {}", e);
+        }
+    })
+    .await;
+    report(&app, &session_id, result);
+}
+
+/// The responder's side of `respond`, asking the user through `ask`.
+async fn respond_with(
+    conn: &mut Conn,
+    identity: &Identity,
+    pairings: &Pairings,
+    hello: PairMessage,
+    session_id: &str,
+    ask: impl FnOnce(PairingSession),
+) -> Result<Device, String> {
+    let code = match &hello {
+        PairMessage::Hello { code, .. } => code.clone(),
+        _ => return Err("Expected hello".to_string()),
+    };
+    let peer = answer_hello(conn, identity, hello).await?;
+
+    let accepted = match code {
+        Some(code) => pairings.take_code(&code),
+        None => {
+            let decision = pairings.wait_for(session_id);
+            ask(PairingSession {
+                id: session_id.to_string(),
+                peer_id: peer.id.clone(),
+                peer_name: peer.name.clone(),
+                sas: short_code(&peer),
+                incoming: true,
+            });
+            let decision = tokio::time::timeout(CONFIRM_TIMEOUT, decision).await;
+            pairings.forget(session_id);
+            matches!(decision, Ok(Ok(true)))
+        }
+    };
+    finish(conn, identity, &peer, accepted).await
+}
+
+/// The initiator's side: commit to a nonce, learn the responder's, then
+/// reveal ours.
+async fn handshake(conn: &mut Conn, identity: &Identity, code: Option<String>) -> Result<Peer, String> {
+    let nonce = random_nonce();
+    let public_key = identity.public_key_b64();
+    conn.send(&PairMessage::Hello {
+        device_id: identity.device_id.clone(),
+        name: identity.device_name.clone(),
+        commitment: commitment(&nonce, &public_key),
+        public_key,
+        code,
+    })
+    .await?;
+    let (id, name, public_key, their_nonce) = match conn.recv().await? {
+        PairMessage::Reply { device_id, name, public_key, nonce } => (device_id, name, public_key, nonce),
+        _ => return Err("Expected reply".into()),
+    };
+    conn.send(&PairMessage::Reveal { nonce: nonce.clone() }).await?;
+    peer_from(identity, id, name, public_key, &nonce, &their_nonce, true)
+}
+
+/// The responder's side: answer with our nonce and check the initiator's
+/// against its commitment.
+async fn answer_hello(conn: &mut Conn, identity: &Identity, hello: PairMessage) -> Result<Peer, String> {
+    let (id, name, public_key, committed) = match hello {
+        PairMessage::Hello { device_id, name, public_key, commitment, .. } => (device_id, name, public_key, commitment),
+        _ => return Err("Expected hello".into()),
+    };
+    let nonce = random_nonce();
+    conn.send(&PairMessage::Reply {
+        device_id: identity.device_id.clone(),
+        name: identity.device_name.clone(),
+        public_key: identity.public_key_b64(),
+        nonce: nonce.clone(),
+    })
+    .await?;
+    let their_nonce = match conn.recv().await? {
+        PairMessage::Reveal { nonce } => nonce,
+        _ => return Err("Expected nonce".into()),
+    };
+    if commitment(&their_nonce, &public_key) != committed {
+        return Err("Device changed its pairing nonce".into());
+    }
+    peer_from(identity, id, name, public_key, &their_nonce, &nonce, false)
+}
+
+fn peer_from(
+    identity: &Identity,
+    id: String,
+    name: String,
+    public_key: String,
+    initiator_nonce: &str,
+    responder_nonce: &str,
+    initiator: bool,
+) -> Result<Peer, String> {
+    if id == identity.device_id {
+        return Err("Cannot pair a device with itself".into());
+    }
+    let shared = identity.agree(&decode_public_key(&public_key)?);
+
+    // Both sides hash the exchange in the same (initiator, responder) order.
+    let ours = identity.public_key_b64();
+    let (first, second) = if initiator { (&ours, &public_key) } else { (&public_key, &ours) };
+    let mut hasher = Sha256::new();
+    hasher.update(b"aethersync-pair-v2");
+    for part in [first.as_str(), second.as_str(), initiator_nonce, responder_nonce] {
+        hasher.update(part.as_bytes());
+    }
+    let transcript: [u8; 32] = hasher.finalize().into();
+
+    Ok(Peer {
+        id,
+        name,
+        public_key,
+        shared,
+        transcript,
+    })
+}
+
+/// Exchange decisions and proofs that each side derived the same secret,
+/// then remember the peer.
+async fn finish(conn: &mut Conn, identity: &Identity, peer: &Peer, accepted: bool) -> Result<Device, String> {
+    conn.send(&PairMessage::Confirm {
+        accepted,
+        proof: proof(peer, &identity.public_key_b64()),
+    })
+    .await?;
+    if !accepted {
+        return Err("Pairing declined".into());
+    }
+
+    match conn.recv_within(CONFIRM_TIMEOUT).await? {
+        PairMessage::Confirm { accepted: false, .. } => Err("Pairing declined on the other device".into()),
+        PairMessage::Confirm { proof: theirs, .. } if theirs == proof(peer, &peer.public_key) => {
+            let address = conn.peer_addr.ip().to_string();
+            devices::save_device(&peer.id, &peer.name, &peer.public_key, Some(&address))
+        }
+        PairMessage::Confirm { .. } => Err("Device failed to prove its key".into()),
+        _ => Err("Expected confirmation".into()),
+    }
+}
+
+fn short_code(peer: &Peer) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"aethersync-sas")
+        .chain_update(peer.shared)
+        .chain_update(peer.transcript)
+        .finalize();
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 1_000_000;
+    format!("{:03} {:03}", value / 1000, value % 1000)
+}
+
+fn proof(peer: &Peer, sender_public_key: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"aethersync-confirm")
+        .chain_update(peer.shared)
+        .chain_update(peer.transcript)
+        .chain_update(sender_public_key.as_bytes())
+        .finalize();
+    STANDARD.encode(digest)
+}
+
+fn commitment(nonce: &str, public_key: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"aethersync-commit")
+        .chain_update(nonce.as_bytes())
+        .chain_update(public_key.as_bytes())
+        .finalize();
+    STANDARD.encode(digest)
+}
+
+fn random_nonce() -> String {
+    let mut nonce = [0u8; 16];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+    STANDARD.encode(nonce)
+}
+
+fn report(app: &AppHandle, session_id: &str, result: Result<Device, String>) {
+    let event = match result {
+        Ok(device) => PairingResult {
+            session_id: session_id.to_string(),
+            device: Some(device),
+            error: None,
+        },
+        Err(error) => PairingResult {
+            session_id: session_id.to_string(),
+            device: None,
+            error: Some(error),
+        },
+    };
+    if let Err(e) = app.emit(PAIRING_RESULT_EVENT, event) {
+        eprintln!("Failed to emit pairing result: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::task::JoinHandle;
+
+    /// A node on localhost answering one pairing request, passing on what
+    /// it would ask the user. Both nodes share the test vault's database.
+    async fn listen(
+        identity: Identity,
+        pairings: Arc<Pairings>,
+    ) -> (SocketAddr, mpsc::UnboundedReceiver<PairingSession>, JoinHandle<Result<Device, String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (asked, asks) = mpsc::unbounded_channel();
+        let node = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = Conn::new(stream)?;
+            let hello = conn.recv().await?;
+            respond_with(&mut conn, &identity, &pairings, hello, "session", |session| {
+                asked.send(session).unwrap();
+            })
+            .await
+        });
+        (addr, asks, node)
+    }
+
+    #[tokio::test]
+    async fn pairs_two_nodes_by_comparing_codes() {
+        let _vault = TempVault::new();
+        let alice = Identity::generate("Alice".into());
+        let bob = Identity::generate("Bob".into());
+        let pairings = Arc::new(Pairings::default());
+        let (addr, mut asks, bob_node) = listen(bob.clone(), pairings.clone()).await;
+
+        let mut conn = Conn::connect(&[addr]).await.unwrap();
+        let peer = handshake(&mut conn, &alice, None).await.unwrap();
+        let session = asks.recv().await.unwrap();
+        assert_eq!(session.peer_id, alice.device_id);
+        assert_eq!(session.sas, short_code(&peer));
+        pairings.decide(&session.id, true).unwrap();
+
+        let paired = finish(&mut conn, &alice, &peer, true).await.unwrap();
+        assert_eq!(paired.id, bob.device_id);
+        assert_eq!(paired.public_key, bob.public_key_b64());
+        let paired = bob_node.await.unwrap().unwrap();
+        assert_eq!(paired.id, alice.device_id);
+        assert_eq!(paired.public_key, alice.public_key_b64());
+    }
+
+    #[tokio::test]
+    async fn pairs_two_nodes_with_a_qr_code() {
+        let _vault = TempVault::new();
+        let alice = Identity::generate("Alice".into());
+        let bob = Identity::generate("Bob".into());
+        let pairings = Arc::new(Pairings::default());
+        let (addr, mut asks, bob_node) = listen(bob.clone(), pairings.clone()).await;
+
+        let payload = serde_json::to_string(&QrPayload {
+            v: 1,
+            id: bob.device_id.clone(),
+            name: bob.device_name.clone(),
+            pk: bob.public_key_b64(),
+            addrs: vec![addr],
+            code: pairings.new_code(),
+        })
+        .unwrap();
+        let paired = pair_with_qr(&alice, &payload).await.unwrap();
+        assert_eq!(paired.id, bob.device_id);
+        assert_eq!(bob_node.await.unwrap().unwrap().id, alice.device_id);
+        assert!(asks.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_nonce_that_does_not_match_the_commitment() {
+        let _vault = TempVault::new();
+        let alice = Identity::generate("Alice".into());
+        let pairings = Arc::new(Pairings::default());
+        let (addr, mut asks, bob_node) = listen(Identity::generate("Bob".into()), pairings).await;
+
+        let mut conn = Conn::connect(&[addr]).await.unwrap();
+        conn.send(&PairMessage::Hello {
+            device_id: alice.device_id.clone(),
+            name: alice.device_name.clone(),
+            public_key: alice.public_key_b64(),
+            commitment: commitment(&random_nonce(), &alice.public_key_b64()),
+            code: None,
+        })
+        .await
+        .unwrap();
+        let _reply: PairMessage = conn.recv().await.unwrap();
+        conn.send(&PairMessage::Reveal { nonce: random_nonce() }).await.unwrap();
+
+        assert_eq!(bob_node.await.unwrap().unwrap_err(), "Device changed its pairing nonce");
+        assert!(asks.try_recv().is_err());
+    }
+
+    #[test]
+    fn wrong_qr_codes_withdraw_the_displayed_one() {
+        let pairings = Pairings::default();
+        let code = pairings.new_code();
+        for _ in 0..MAX_CODE_FAILURES {
+            assert!(!pairings.take_code("guess"));
+        }
+        assert!(!pairings.take_code(&code));
+
+        let code = pairings.new_code();
+        assert!(pairings.take_code(&code));
+        assert!(!pairings.take_code(&code));
+    }
+}
@@ -0,0 +1,799 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::identity::Identity;
+use super::secure::SecureConn;
+use crate::oplog::{self, Op};
+use crate::{db, storage, vault};
+use crate::vault::MEDIA_DIR;
+
+/// Interrupted downloads, named by blob hash so they resume across sessions.
+const PARTIAL_DIR: &str = ".partial";
+/// Raw bytes per blob message.
+const BLOB_CHUNK: usize = 48 * 1024;
+const BUCKETS: usize = 256;
+
+/// Per-memory edit counters by device id. A row is newer than another
+/// when its vector dominates.
+pub type VersionVector = BTreeMap<String, u64>;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    GetRoot,
+    Root { root: String, buckets: Vec<String> },
+    GetBucket { bucket: usize },
+    Bucket { entries: Vec<Entry> },
+    GetMemory { memory_id: String },
+    Memory { row: MemoryRow },
+    GetOps { memory_id: String },
+    Ops { ops: Vec<Op> },
+    GetBlob { memory_id: String, offset: u64 },
+    BlobChunk { data: String, last: bool },
+    /// The sender has nothing more to ask; roles swap.
+    Done,
+    Error { message: String },
+}
+
+/// One leaf of the summary tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    memory_id: String,
+    versions: VersionVector,
+    blob_hash: Option<String>,
+    /// Digest of the memory's metadata operations.
+    #[serde(default)]
+    ops: Option<String>,
+}
+
+/// Replicated columns of a memory. `key` is only sent to member devices.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MemoryRow {
+    id: String,
+    title: String,
+    tags: String,
+    created_at: String,
+    media_type: String,
+    filename: String,
+    captured_at: Option<String>,
+    versions: VersionVector,
+    key: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ReplicationReport {
+    pub memories_received: usize,
+    pub ops_received: usize,
+    pub blobs_received: usize,
+    pub bytes_received: u64,
+}
+
+/// Full session as the connecting side: pull what we lack, then serve
+/// the peer while it does the same.
+pub async fn run_initiator(conn: &mut SecureConn) -> Result<ReplicationReport, String> {
+    let report = pull(conn).await?;
+    serve(conn).await?;
+    Ok(report)
+}
+
+/// Full session as the listening side.
+pub async fn run_responder(conn: &mut SecureConn) -> Result<ReplicationReport, String> {
+    serve(conn).await?;
+    pull(conn).await
+}
+
+/// Record a local change to a memory's replicated fields.
+pub fn bump_version(memory_id: &str) -> Result<(), String> {
+    let device_id = Identity::load_or_create()?.device_id;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut versions = load_versions(&conn, memory_id)?;
+    *versions.entry(device_id).or_insert(0) += 1;
+    conn.execute(
+        "UPDATE memories SET versions = ?1 WHERE id = ?2",
+        params![serde_json::to_string(&versions).map_err(|e| e.to_string())?, memory_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Compare summary trees top-down and fetch rows and blobs only for the
+/// buckets that differ.
+async fn pull(conn: &mut SecureConn) -> Result<ReplicationReport, String> {
+    let mut report = ReplicationReport::default();
+    let local = local_entries()?;
+    let (local_root, local_buckets) = summarize(&local);
+
+    let peer_id = conn.peer.id.clone();
+    conn.send(&Message::GetRoot).await?;
+    let remote_buckets = match conn.recv().await? {
+        Message::Root { root, .. } if root == local_root => {
+            // The peer has every blob we have.
+            note_replicas(&peer_id, local.iter())?;
+            conn.send(&Message::Done).await?;
+            return Ok(report);
+        }
+        Message::Root { buckets, .. } if buckets.len() == BUCKETS => buckets,
+        other => return Err(unexpected(other)),
+    };
+
+    let evicted = storage::evicted_ids()?;
+    let local: HashMap<String, Entry> = local.into_iter().map(|e| (e.memory_id.clone(), e)).collect();
+    for bucket in 0..BUCKETS {
+        if remote_buckets[bucket] == local_buckets[bucket] {
+            note_replicas(&peer_id, local.values().filter(|e| bucket_of(&e.memory_id) == bucket))?;
+            continue;
+        }
+        conn.send(&Message::GetBucket { bucket }).await?;
+        let entries = match conn.recv().await? {
+            Message::Bucket { entries } => entries,
+            other => return Err(unexpected(other)),
+        };
+        let listed: HashSet<String> = entries.iter().map(|e| e.memory_id.clone()).collect();
+
+        for entry in entries {
+            let ours = local.get(&entry.memory_id);
+            let behind = ours.map_or(true, |ours| !dominates(&ours.versions, &entry.versions));
+            if behind {
+                conn.send(&Message::GetMemory {
+                    memory_id: entry.memory_id.clone(),
+                })
+                .await?;
+                match conn.recv().await? {
+                    Message::Memory { row } => merge_row(row)?,
+                    other => return Err(unexpected(other)),
+                }
+                report.memories_received += 1;
+            }
+
+            if entry.ops.is_some() && entry.ops != ours.and_then(|ours| ours.ops.clone()) {
+                conn.send(&Message::GetOps {
+                    memory_id: entry.memory_id.clone(),
+                })
+                .await?;
+                match conn.recv().await? {
+                    Message::Ops { ops } => report.ops_received += oplog::apply_remote(ops)?,
+                    other => return Err(unexpected(other)),
+                }
+            }
+
+            note_replicas(&peer_id, std::iter::once(&entry))?;
+            // Originals evicted on purpose are only fetched when opened.
+            let have_blob = ours.map_or(false, |ours| ours.blob_hash.is_some()) || evicted.contains(&entry.memory_id);
+            if let (Some(hash), false) = (&entry.blob_hash, have_blob) {
+                let destination = blob_path(&entry.memory_id)?
+                    .ok_or_else(|| format!("Unknown memory {}", entry.memory_id))?;
+                report.bytes_received += fetch_blob(conn, &entry.memory_id, hash, &destination).await?;
+                report.blobs_received += 1;
+            }
+        }
+        // Memories the peer does not have at all are not replicated there.
+        for memory_id in local.keys().filter(|id| bucket_of(id) == bucket && !listed.contains(*id)) {
+            storage::forget_replica(memory_id, &peer_id)?;
+        }
+    }
+
+    conn.send(&Message::Done).await?;
+    Ok(report)
+}
+
+/// Fetch one blob the peer holds to `destination`, e.g. an evicted
+/// original being opened, then answer the peer's half of the session.
+pub async fn run_fetch(conn: &mut SecureConn, memory_id: &str, hash: &str, destination: &Path) -> Result<u64, String> {
+    let fetched = fetch_blob(conn, memory_id, hash, destination).await;
+    conn.send(&Message::Done).await?;
+    serve(conn).await?;
+    fetched
+}
+
+/// Remember which of these blobs the peer holds, so local originals can
+/// be evicted safely, and forget the ones it no longer has.
+fn note_replicas<'a>(peer_id: &str, entries: impl Iterator<Item = &'a Entry>) -> Result<(), String> {
+    for entry in entries {
+        match &entry.blob_hash {
+            Some(hash) => storage::record_replica(&entry.memory_id, peer_id, hash)?,
+            None => storage::forget_replica(&entry.memory_id, peer_id)?,
+        }
+    }
+    Ok(())
+}
+
+/// Answer the peer's requests until it is done.
+async fn serve(conn: &mut SecureConn) -> Result<(), String> {
+    let send_keys = conn.peer.role == "member";
+    loop {
+        let reply = match conn.recv().await? {
+            Message::Done => return Ok(()),
+            Message::Error { message } => return Err(message),
+            Message::GetRoot => {
+                let (root, buckets) = summarize(&local_entries()?);
+                Message::Root { root, buckets }
+            }
+            Message::GetBucket { bucket } => Message::Bucket {
+                entries: local_entries()?
+                    .into_iter()
+                    .filter(|e| bucket_of(&e.memory_id) == bucket)
+                    .collect(),
+            },
+            Message::GetMemory { memory_id } => match load_row(&memory_id, send_keys)? {
+                Some(row) => Message::Memory { row },
+                None => Message::Error {
+                    message: format!("Unknown memory {}", memory_id),
+                },
+            },
+            Message::GetOps { memory_id } => Message::Ops {
+                ops: oplog::history(&memory_id)?,
+            },
+            Message::GetBlob { memory_id, offset } => {
+                send_blob(conn, &memory_id, offset).await?;
+                continue;
+            }
+            other => return Err(unexpected(other)),
+        };
+        conn.send(&reply).await?;
+    }
+}
+
+async fn send_blob(conn: &mut SecureConn, memory_id: &str, offset: u64) -> Result<(), String> {
+    let path = match blob_path(memory_id)? {
+        Some(path) if path.exists() => path,
+        _ => {
+            return conn
+                .send(&Message::Error {
+                    message: format!("No blob for {}", memory_id),
+                })
+                .await
+        }
+    };
+
+    let mut file = File::open(&path).map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    let mut buf = vec![0u8; BLOB_CHUNK];
+    loop {
+        let n = read_full(&mut file, &mut buf).map_err(|e| e.to_string())?;
+        let last = n < BLOB_CHUNK;
+        conn.send(&Message::BlobChunk {
+            data: STANDARD.encode(&buf[..n]),
+            last,
+        })
+        .await?;
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+/// Download a blob, appending to any partial copy from an earlier
+/// session, and move it to `destination` once its hash checks out.
+async fn fetch_blob(conn: &mut SecureConn, memory_id: &str, hash: &str, destination: &Path) -> Result<u64, String> {
+    vault::check_hash(hash)?;
+    let partial_dir = Path::new(MEDIA_DIR).join(PARTIAL_DIR);
+    fs::create_dir_all(&partial_dir).map_err(|e| e.to_string())?;
+    let partial = partial_dir.join(hash);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&partial)
+        .map_err(|e| e.to_string())?;
+    let offset = file.metadata().map_err(|e| e.to_string())?.len();
+
+    conn.send(&Message::GetBlob {
+        memory_id: memory_id.to_string(),
+        offset,
+    })
+    .await?;
+    let mut received = 0u64;
+    loop {
+        match conn.recv().await? {
+            Message::BlobChunk { data, last } => {
+                let bytes = STANDARD.decode(data).map_err(|e| e.to_string())?;
+                file.write_all(&bytes).map_err(|e| e.to_string())?;
+                received += bytes.len() as u64;
+                if last {
+                    break;
+                }
+            }
+            Message::Error { message } => return Err(message),
+            other => return Err(unexpected(other)),
+        }
+    }
+    file.sync_all().map_err(|e| e.to_string())?;
+    drop(file);
+
+    if vault::hash_file(&partial).map_err(|e| e.to_string())? != hash {
+        let _ = fs::remove_file(&partial);
+        return Err(format!("Blob for {} failed verification", memory_id));
+    }
+    fs::rename(&partial, destination).map_err(|e| e.to_string())?;
+    Ok(received)
+}
+
+fn local_entries() -> Result<Vec<Entry>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT id, filename, versions FROM memories")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let mut ops = oplog::digests()?;
+
+    rows.into_iter()
+        .map(|(memory_id, filename, versions)| {
+            Ok(Entry {
+                blob_hash: blob_hash(&memory_id, &filename)?,
+                versions: parse_versions(versions.as_deref()),
+                ops: ops.remove(&memory_id),
+                memory_id,
+            })
+        })
+        .collect()
+}
+
+/// Root hash plus one hash per bucket of memory ids.
+fn summarize(entries: &[Entry]) -> (String, Vec<String>) {
+    let mut buckets: Vec<Vec<&Entry>> = vec![Vec::new(); BUCKETS];
+    for entry in entries {
+        buckets[bucket_of(&entry.memory_id)].push(entry);
+    }
+
+    let bucket_hashes: Vec<String> = buckets
+        .into_iter()
+        .map(|mut bucket| {
+            bucket.sort_by(|a, b| a.memory_id.cmp(&b.memory_id));
+            let mut hasher = Sha256::new();
+            for entry in bucket {
+                hasher.update(serde_json::to_vec(entry).unwrap_or_default());
+            }
+            format!("{:x}", hasher.finalize())
+        })
+        .collect();
+
+    let mut root = Sha256::new();
+    for hash in &bucket_hashes {
+        root.update(hash.as_bytes());
+    }
+    (format!("{:x}", root.finalize()), bucket_hashes)
+}
+
+fn bucket_of(memory_id: &str) -> usize {
+    Sha256::digest(memory_id.as_bytes())[0] as usize
+}
+
+/// `a` has seen every change `b` has.
+fn dominates(a: &VersionVector, b: &VersionVector) -> bool {
+    b.iter().all(|(device, count)| a.get(device).copied().unwrap_or(0) >= *count)
+}
+
+/// Insert a memory we did not have, or take the peer's fields when its
+/// version is strictly newer. Concurrent edits keep our fields and merge
+/// the counters.
+fn merge_row(row: MemoryRow) -> Result<(), String> {
+    if row.filename.contains(['/', '\\']) || row.filename.starts_with('.') {
+        return Err(format!("Refusing unsafe filename '{}'", row.filename));
+    }
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let exists: Option<String> = conn
+        .query_row("SELECT versions FROM memories WHERE id = ?1", params![row.id], |r| r.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .map(|v: Option<String>| v.unwrap_or_default());
+
+    let versions = serde_json::to_string(&row.versions).map_err(|e| e.to_string())?;
+    let key = row.key.unwrap_or_default();
+    match exists {
+        None => {
+            conn.execute(
+                "INSERT INTO memories (id, title, tags, created_at, media_type, filename, key_encrypted,
+                                       transcription, captured_at, versions)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, '', ?8, ?9)",
+                params![
+                    row.id,
+                    row.title,
+                    row.tags,
+                    row.created_at,
+                    row.media_type,
+                    row.filename,
+                    key,
+                    row.captured_at,
+                    versions
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        Some(local) => {
+            let local = parse_versions(Some(&local));
+            if dominates(&row.versions, &local) {
+                conn.execute(
+                    "UPDATE memories SET title = ?1, tags = ?2, media_type = ?3, captured_at = ?4, versions = ?5
+                     WHERE id = ?6",
+                    params![row.title, row.tags, row.media_type, row.captured_at, versions, row.id],
+                )
+                .map_err(|e| e.to_string())?;
+            } else {
+                let mut merged = local;
+                for (device, count) in row.versions {
+                    let ours = merged.entry(device).or_insert(0);
+                    *ours = (*ours).max(count);
+                }
+                conn.execute(
+                    "UPDATE memories SET versions = ?1 WHERE id = ?2",
+                    params![serde_json::to_string(&merged).map_err(|e| e.to_string())?, row.id],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            // A relay may have given us the row before a member sent the key.
+            if !key.is_empty() {
+                conn.execute(
+                    "UPDATE memories SET key_encrypted = ?1
+                     WHERE id = ?2 AND (key_encrypted IS NULL OR key_encrypted = '')",
+                    params![key, row.id],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    // Title and tags follow the operation log wherever one exists.
+    oplog::materialize(&conn, &row.id)
+}
+
+fn load_row(memory_id: &str, with_key: bool) -> Result<Option<MemoryRow>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT id, title, tags, created_at, media_type, filename, captured_at, versions, key_encrypted
+         FROM memories WHERE id = ?1",
+        params![memory_id],
+        |row| {
+            let key: Option<String> = row.get(8)?;
+            Ok(MemoryRow {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                tags: row.get(2)?,
+                created_at: row.get(3)?,
+                media_type: row.get(4)?,
+                filename: row.get(5)?,
+                captured_at: row.get(6)?,
+                versions: parse_versions(row.get::<_, Option<String>>(7)?.as_deref()),
+                key: key.filter(|k| with_key && !k.is_empty()),
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn load_versions(conn: &rusqlite::Connection, memory_id: &str) -> Result<VersionVector, String> {
+    let versions: Option<String> = conn
+        .query_row("SELECT versions FROM memories WHERE id = ?1", params![memory_id], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    Ok(parse_versions(versions.as_deref()))
+}
+
+fn parse_versions(value: Option<&str>) -> VersionVector {
+    value.and_then(|v| serde_json::from_str(v).ok()).unwrap_or_default()
+}
+
+fn blob_path(memory_id: &str) -> Result<Option<PathBuf>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let filename: Option<String> = conn
+        .query_row("SELECT filename FROM memories WHERE id = ?1", params![memory_id], |r| r.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    Ok(filename.map(|f| Path::new(MEDIA_DIR).join(format!("{}.enc", f))))
+}
+
+/// Path and hash of the memory's encrypted blob, or `None` when the
+/// memory has no sealed original on this device.
+pub fn sealed_blob(memory_id: &str) -> Result<Option<(PathBuf, String)>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let filename: Option<String> = conn
+        .query_row("SELECT filename FROM memories WHERE id = ?1", params![memory_id], |r| r.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let filename = filename.ok_or("Memory not found")?;
+    Ok(blob_hash(memory_id, &filename)?
+        .map(|hash| (Path::new(MEDIA_DIR).join(format!("{}.enc", filename)), hash)))
+}
+
+/// Hash of the memory's encrypted blob as it travels between devices.
+/// Only sealed originals are offered; hashes are cached by size and mtime.
+fn blob_hash(memory_id: &str, filename: &str) -> Result<Option<String>, String> {
+    let encrypted = Path::new(MEDIA_DIR).join(format!("{}.enc", filename));
+    if filename.is_empty() || !encrypted.is_file() {
+        return Ok(None);
+    }
+
+    let meta = fs::metadata(&encrypted).map_err(|e| e.to_string())?;
+    let size = meta.len() as i64;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let cached: Option<String> = conn
+        .query_row(
+            "SELECT hash FROM replica_blobs WHERE memory_id = ?1 AND size = ?2 AND mtime = ?3",
+            params![memory_id, size, mtime],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(hash) = cached {
+        return Ok(Some(hash));
+    }
+
+    let hash = vault::hash_file(&encrypted).map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO replica_blobs (memory_id, hash, size, mtime) VALUES (?1, ?2, ?3, ?4)",
+        params![memory_id, hash, size, mtime],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(Some(hash))
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = file.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+fn unexpected(message: Message) -> String {
+    match message {
+        Message::Error { message } => message,
+        _ => "Unexpected message from peer".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+    use crate::db::test_support::TempVault;
+    use crate::media;
+    use crate::p2p::devices;
+    use crate::p2p::secure::PROTOCOL_MAGIC;
+    use crate::p2p::wire::Conn;
+    use std::future::Future;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// A session with a paired device on localhost that runs `peer` on
+    /// its end. Both ends share the test vault's database.
+    async fn session<F, Fut>(peer: F) -> (SecureConn, JoinHandle<Result<(), String>>)
+    where
+        F: FnOnce(SecureConn) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), String>> + Send,
+    {
+        let alice = Identity::generate("Alice".into());
+        let bob = Identity::generate("Bob".into());
+        devices::save_device(&alice.device_id, "Alice", &alice.public_key_b64(), None).unwrap();
+        let bob_device = devices::save_device(&bob.device_id, "Bob", &bob.public_key_b64(), None).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = Conn::new(stream)?;
+            assert_eq!(conn.recv_raw(PROTOCOL_MAGIC.len()).await?, PROTOCOL_MAGIC);
+            peer(SecureConn::accept(&bob, conn).await?).await
+        });
+        let conn = SecureConn::connect(&alice, &bob_device, &[addr]).await.unwrap();
+        (conn, node)
+    }
+
+    /// A memory row with a fresh key, returning the key.
+    fn insert_memory(memory_id: &str, filename: &str) -> [u8; 32] {
+        let key = crypto::generate_key_base64();
+        db::get_connection()
+            .unwrap()
+            .execute(
+                "INSERT INTO memories (id, title, tags, created_at, media_type, filename, key_encrypted, transcription)
+                 VALUES (?1, 'Beach', '', '2024-07-01T10:00:00Z', 'image/jpeg', ?2, ?3, '')",
+                params![memory_id, filename, key],
+            )
+            .unwrap();
+        crypto::decode_key(&key).unwrap()
+    }
+
+    fn add_sealed(memory_id: &str, filename: &str, bytes: &[u8]) -> String {
+        let key = insert_memory(memory_id, filename);
+        let path = media::seal_media(Path::new(MEDIA_DIR), filename, bytes, &key).unwrap();
+        vault::hash_file(&path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn root_and_buckets_show_where_two_vaults_differ() {
+        let _vault = TempVault::new();
+        let hash = add_sealed("m1", "beach.jpg", b"waves");
+        add_sealed("m2", "hike.jpg", b"trail");
+        // Plain originals are not offered, and looking does not seal them
+        insert_memory("m3", "plain.jpg");
+        fs::write(Path::new(MEDIA_DIR).join("plain.jpg"), b"clear").unwrap();
+
+        let (mut conn, node) = session(|mut conn| async move { serve(&mut conn).await }).await;
+        let local = local_entries().unwrap();
+        let (root, buckets) = summarize(&local);
+        conn.send(&Message::GetRoot).await.unwrap();
+        match conn.recv().await.unwrap() {
+            Message::Root { root: theirs, buckets: their_buckets } => {
+                assert_eq!(theirs, root);
+                assert_eq!(their_buckets, buckets);
+            }
+            _ => panic!("expected the root"),
+        }
+
+        // A device lacking m1 differs in m1's bucket only
+        let without: Vec<Entry> = local.iter().filter(|e| e.memory_id != "m1").cloned().collect();
+        let (other_root, other_buckets) = summarize(&without);
+        assert_ne!(other_root, root);
+        let differing: Vec<usize> = (0..BUCKETS).filter(|&b| buckets[b] != other_buckets[b]).collect();
+        assert_eq!(differing, vec![bucket_of("m1")]);
+
+        conn.send(&Message::GetBucket { bucket: differing[0] }).await.unwrap();
+        let entries = match conn.recv().await.unwrap() {
+            Message::Bucket { entries } => entries,
+            _ => panic!("expected a bucket"),
+        };
+        let entry = entries.iter().find(|e| e.memory_id == "m1").unwrap();
+        assert_eq!(entry.blob_hash.as_deref(), Some(hash.as_str()));
+        assert!(entries.iter().all(|e| bucket_of(&e.memory_id) == differing[0]));
+
+        let plain = local.iter().find(|e| e.memory_id == "m3").unwrap();
+        assert_eq!(plain.blob_hash, None);
+        assert!(Path::new(MEDIA_DIR).join("plain.jpg").is_file());
+        assert!(!Path::new(MEDIA_DIR).join("plain.jpg.enc").exists());
+
+        conn.send(&Message::Done).await.unwrap();
+        node.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn pull_fetches_the_rows_and_blobs_the_peer_has() {
+        let _vault = TempVault::new();
+        let key = crypto::generate_key_base64();
+        let sealed = crypto::encrypt_bytes(b"waves", &crypto::decode_key(&key).unwrap()).unwrap();
+        let hash = format!("{:x}", Sha256::digest(&sealed));
+        let row = MemoryRow {
+            id: "m1".into(),
+            title: "Beach".into(),
+            tags: "summer".into(),
+            created_at: "2024-07-01T10:00:00Z".into(),
+            media_type: "image/jpeg".into(),
+            filename: "beach.jpg".into(),
+            captured_at: None,
+            versions: VersionVector::from([("bob".to_string(), 1)]),
+            key: Some(key.clone()),
+        };
+        let entry = Entry {
+            memory_id: "m1".into(),
+            versions: row.versions.clone(),
+            blob_hash: Some(hash.clone()),
+            ops: None,
+        };
+
+        let blob = sealed.clone();
+        let (mut conn, node) = session(move |mut conn| async move {
+            // Our vault is empty, so only m1's bucket differs from the peer's
+            let (_, mut buckets) = summarize(&[]);
+            buckets[bucket_of("m1")] = "changed".into();
+            assert!(matches!(conn.recv().await?, Message::GetRoot));
+            conn.send(&Message::Root { root: "peer".into(), buckets }).await?;
+            match conn.recv().await? {
+                Message::GetBucket { bucket } => assert_eq!(bucket, bucket_of("m1")),
+                _ => panic!("expected a bucket request"),
+            }
+            conn.send(&Message::Bucket { entries: vec![entry] }).await?;
+            assert!(matches!(conn.recv().await?, Message::GetMemory { memory_id } if memory_id == "m1"));
+            conn.send(&Message::Memory { row }).await?;
+            assert!(matches!(conn.recv().await?, Message::GetBlob { offset: 0, .. }));
+            conn.send(&Message::BlobChunk {
+                data: STANDARD.encode(&blob),
+                last: true,
+            })
+            .await?;
+            assert!(matches!(conn.recv().await?, Message::Done));
+            Ok(())
+        })
+        .await;
+
+        let report = pull(&mut conn).await.unwrap();
+        node.await.unwrap().unwrap();
+        assert_eq!(report.memories_received, 1);
+        assert_eq!(report.blobs_received, 1);
+        assert_eq!(report.bytes_received, sealed.len() as u64);
+
+        let stored = load_row("m1", true).unwrap().unwrap();
+        assert_eq!(stored.title, "Beach");
+        assert_eq!(stored.key.as_deref(), Some(key.as_str()));
+        assert_eq!(fs::read(Path::new(MEDIA_DIR).join("beach.jpg.enc")).unwrap(), sealed);
+        let key = db::get_memory_key("m1").unwrap();
+        assert_eq!(media::load_media_bytes(Path::new(MEDIA_DIR), "beach.jpg", &key).unwrap(), b"waves");
+        assert_eq!(local_entries().unwrap()[0].blob_hash.as_deref(), Some(hash.as_str()));
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_resume_from_the_partial_file() {
+        let _vault = TempVault::new();
+        let key = insert_memory("m1", "beach.jpg");
+        let sealed = crypto::encrypt_bytes(&[7u8; 1000], &key).unwrap();
+        let hash = format!("{:x}", Sha256::digest(&sealed));
+        let partial_dir = Path::new(MEDIA_DIR).join(PARTIAL_DIR);
+        fs::create_dir_all(&partial_dir).unwrap();
+        fs::write(partial_dir.join(&hash), &sealed[..400]).unwrap();
+
+        let rest = sealed[400..].to_vec();
+        let (mut conn, node) = session(move |mut conn| async move {
+            assert!(matches!(conn.recv().await?, Message::GetBlob { offset: 400, .. }));
+            conn.send(&Message::BlobChunk {
+                data: STANDARD.encode(&rest),
+                last: true,
+            })
+            .await?;
+            assert!(matches!(conn.recv().await?, Message::Done));
+            conn.send(&Message::Done).await
+        })
+        .await;
+
+        let received = run_fetch(&mut conn, "m1", &hash, &Path::new(MEDIA_DIR).join("beach.jpg.enc")).await.unwrap();
+        node.await.unwrap().unwrap();
+        assert_eq!(received, (sealed.len() - 400) as u64);
+        assert_eq!(fs::read(Path::new(MEDIA_DIR).join("beach.jpg.enc")).unwrap(), sealed);
+        assert!(!partial_dir.join(&hash).exists());
+    }
+
+    #[tokio::test]
+    async fn a_partial_file_that_fails_verification_is_dropped() {
+        let _vault = TempVault::new();
+        let key = insert_memory("m1", "beach.jpg");
+        let sealed = crypto::encrypt_bytes(&[7u8; 1000], &key).unwrap();
+        let hash = format!("{:x}", Sha256::digest(&sealed));
+        let partial_dir = Path::new(MEDIA_DIR).join(PARTIAL_DIR);
+        fs::create_dir_all(&partial_dir).unwrap();
+        fs::write(partial_dir.join(&hash), vec![0u8; 400]).unwrap();
+
+        let rest = sealed[400..].to_vec();
+        let (mut conn, node) = session(move |mut conn| async move {
+            assert!(matches!(conn.recv().await?, Message::GetBlob { offset: 400, .. }));
+            conn.send(&Message::BlobChunk {
+                data: STANDARD.encode(&rest),
+                last: true,
+            })
+            .await?;
+            assert!(matches!(conn.recv().await?, Message::Done));
+            conn.send(&Message::Done).await
+        })
+        .await;
+
+        let error = run_fetch(&mut conn, "m1", &hash, &Path::new(MEDIA_DIR).join("beach.jpg.enc")).await.unwrap_err();
+        node.await.unwrap().unwrap();
+        assert!(error.contains("failed verification"));
+        assert!(!partial_dir.join(&hash).exists());
+        assert!(!Path::new(MEDIA_DIR).join("beach.jpg.enc").exists());
+    }
+}
@@ -0,0 +1,144 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use snow::params::NoiseParams;
+use snow::{Builder, HandshakeState, TransportState};
+use std::net::SocketAddr;
+
+use super::devices::{self, Device};
+use super::identity::{decode_public_key, Identity};
+use super::wire::Conn;
+
+/// Sent in the clear before the handshake so the listener can tell a
+/// replication session from a pairing request.
+pub const PROTOCOL_MAGIC: &[u8; 4] = b"ASR1";
+
+/// Both sides already know each other's static key from pairing; the
+/// responder learns which device is calling from the first message.
+const NOISE_PARAMS: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+const MAX_FRAME: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_PLAINTEXT: usize = MAX_FRAME - TAG_LEN;
+/// Largest message accepted from a peer once decrypted.
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// A mutually authenticated, encrypted channel to a paired device.
+/// Messages are JSON, split over as many Noise frames as needed.
+pub struct SecureConn {
+    conn: Conn,
+    noise: TransportState,
+    pub peer: Device,
+}
+
+impl SecureConn {
+    pub async fn connect(identity: &Identity, device: &Device, addrs: &[SocketAddr]) -> Result<Self, String> {
+        let peer_key = decode_public_key(&device.public_key)?;
+        let secret = identity.secret_bytes();
+        let mut handshake = Builder::new(noise_params()?)
+            .local_private_key(&secret)
+            .remote_public_key(peer_key.as_bytes())
+            .build_initiator()
+            .map_err(|e| e.to_string())?;
+
+        let mut conn = Conn::connect(addrs).await?;
+        conn.send_raw(PROTOCOL_MAGIC).await?;
+        let mut buf = vec![0u8; MAX_FRAME];
+        let len = handshake.write_message(&[], &mut buf).map_err(|e| e.to_string())?;
+        conn.send_frame(&buf[..len]).await?;
+        let reply = conn.recv_frame().await?;
+        handshake
+            .read_message(&reply, &mut buf)
+            .map_err(|_| "Device failed to authenticate".to_string())?;
+
+        Ok(Self {
+            noise: handshake.into_transport_mode().map_err(|e| e.to_string())?,
+            conn,
+            peer: device.clone(),
+        })
+    }
+
+    /// Complete a handshake on an incoming connection whose protocol marker
+    /// has been read. Devices we have not paired with are turned away.
+    pub async fn accept(identity: &Identity, mut conn: Conn) -> Result<Self, String> {
+        let secret = identity.secret_bytes();
+        let mut handshake = Builder::new(noise_params()?)
+            .local_private_key(&secret)
+            .build_responder()
+            .map_err(|e| e.to_string())?;
+
+        let mut buf = vec![0u8; MAX_FRAME];
+        let first = conn.recv_frame().await?;
+        handshake
+            .read_message(&first, &mut buf)
+            .map_err(|_| "Invalid handshake".to_string())?;
+        let peer = remote_device(&handshake)?;
+
+        let len = handshake.write_message(&[], &mut buf).map_err(|e| e.to_string())?;
+        conn.send_frame(&buf[..len]).await?;
+
+        Ok(Self {
+            noise: handshake.into_transport_mode().map_err(|e| e.to_string())?,
+            conn,
+            peer,
+        })
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.conn.peer_addr
+    }
+
+    pub async fn send<T: Serialize>(&mut self, message: &T) -> Result<(), String> {
+        let bytes = serde_json::to_vec(message).map_err(|e| e.to_string())?;
+        self.send_chunk(&(bytes.len() as u32).to_be_bytes()).await?;
+        for chunk in bytes.chunks(MAX_PLAINTEXT) {
+            self.send_chunk(chunk).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<T, String> {
+        let header = self.recv_chunk().await?;
+        let len = <[u8; 4]>::try_from(header.as_slice())
+            .map(u32::from_be_bytes)
+            .map_err(|_| "Invalid message header".to_string())? as usize;
+        if len > MAX_MESSAGE {
+            return Err("Peer message too large".into());
+        }
+
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            bytes.extend(self.recv_chunk().await?);
+        }
+        serde_json::from_slice(&bytes).map_err(|e| format!("Invalid peer message: {}", e))
+    }
+
+    async fn send_chunk(&mut self, plaintext: &[u8]) -> Result<(), String> {
+        let mut frame = vec![0u8; plaintext.len() + TAG_LEN];
+        let len = self
+            .noise
+            .write_message(plaintext, &mut frame)
+            .map_err(|e| e.to_string())?;
+        self.conn.send_frame(&frame[..len]).await
+    }
+
+    async fn recv_chunk(&mut self) -> Result<Vec<u8>, String> {
+        let frame = self.conn.recv_frame().await?;
+        let mut plaintext = vec![0u8; frame.len()];
+        let len = self
+            .noise
+            .read_message(&frame, &mut plaintext)
+            .map_err(|_| "Message failed to decrypt".to_string())?;
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+}
+
+fn noise_params() -> Result<NoiseParams, String> {
+    NOISE_PARAMS.parse().map_err(|e: snow::Error| e.to_string())
+}
+
+fn remote_device(handshake: &HandshakeState) -> Result<Device, String> {
+    let key = handshake.get_remote_static().ok_or("Peer sent no device key")?;
+    devices::find_by_public_key(&STANDARD.encode(key))?.ok_or_else(|| "Device is not paired".to_string())
+}
@@ -0,0 +1,99 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+/// Largest control message accepted from a peer.
+const MAX_MESSAGE: u64 = 64 * 1024;
+
+/// A peer that stops talking mid-exchange is dropped after this long.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Newline-delimited JSON messages over a TCP connection, or binary
+/// frames with a 2-byte big-endian length once a secure channel is up.
+pub struct Conn {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    pub peer_addr: SocketAddr,
+}
+
+impl Conn {
+    pub fn new(stream: TcpStream) -> Result<Self, String> {
+        let peer_addr = stream.peer_addr().map_err(|e| e.to_string())?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+            peer_addr,
+        })
+    }
+
+    pub async fn connect(addrs: &[SocketAddr]) -> Result<Self, String> {
+        let mut last_error = "No address to connect to".to_string();
+        for addr in addrs {
+            match tokio::time::timeout(READ_TIMEOUT, TcpStream::connect(addr)).await {
+                Ok(Ok(stream)) => return Self::new(stream),
+                Ok(Err(e)) => last_error = format!("{}: {}", addr, e),
+                Err(_) => last_error = format!("{}: timed out", addr),
+            }
+        }
+        Err(last_error)
+    }
+
+    pub async fn send<T: Serialize>(&mut self, message: &T) -> Result<(), String> {
+        let mut line = serde_json::to_vec(message).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await.map_err(|e| e.to_string())?;
+        self.writer.flush().await.map_err(|e| e.to_string())
+    }
+
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<T, String> {
+        self.recv_within(READ_TIMEOUT).await
+    }
+
+    /// Like `recv`, for steps that wait on a person (e.g. comparing codes).
+    pub async fn recv_within<T: DeserializeOwned>(&mut self, timeout: Duration) -> Result<T, String> {
+        let mut line = String::new();
+        let mut limited = (&mut self.reader).take(MAX_MESSAGE);
+        let n = tokio::time::timeout(timeout, limited.read_line(&mut line))
+            .await
+            .map_err(|_| "Peer timed out".to_string())?
+            .map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("Peer closed the connection".into());
+        }
+        if !line.ends_with('\n') {
+            return Err("Peer message too large".into());
+        }
+        serde_json::from_str(&line).map_err(|e| format!("Invalid peer message: {}", e))
+    }
+
+    /// Bytes sent before any framing, e.g. a protocol marker.
+    pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.writer.write_all(bytes).await.map_err(|e| e.to_string())?;
+        self.writer.flush().await.map_err(|e| e.to_string())
+    }
+
+    pub async fn recv_raw(&mut self, len: usize) -> Result<Vec<u8>, String> {
+        let mut buf = vec![0u8; len];
+        tokio::time::timeout(READ_TIMEOUT, self.reader.read_exact(&mut buf))
+            .await
+            .map_err(|_| "Peer timed out".to_string())?
+            .map_err(|e| e.to_string())?;
+        Ok(buf)
+    }
+
+    pub async fn send_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        let len = u16::try_from(frame.len()).map_err(|_| "Frame too large".to_string())?;
+        self.writer.write_all(&len.to_be_bytes()).await.map_err(|e| e.to_string())?;
+        self.send_raw(frame).await
+    }
+
+    pub async fn recv_frame(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.recv_raw(2).await?;
+        self.recv_raw(u16::from_be_bytes([len[0], len[1]]) as usize).await
+    }
+}
@@ -0,0 +1,485 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{db, oplog};
+use crate::whisper::languages;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Person {
+    pub id: String,
+    pub name: String,
+    pub relationship: Option<String>,
+    pub birth_date: Option<String>, // "YYYY-MM-DD"
+    pub avatar_memory_id: Option<String>,
+    /// Language this person usually speaks in recordings.
+    pub default_language: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PersonInput {
+    pub name: String,
+    pub relationship: Option<String>,
+    pub birth_date: Option<String>,
+    pub avatar_memory_id: Option<String>,
+    #[serde(default)]
+    pub default_language: Option<String>,
+}
+
+/// Region of the image a person appears in, as fractions of width/height.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Age {
+    pub years: u32,
+    pub months: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaggedPerson {
+    pub person: Person,
+    pub region: Option<Region>,
+    /// Age when the memory was captured, if the birth date is known.
+    pub age_at_capture: Option<Age>,
+}
+
+const PERSON_COLUMNS: &str =
+    "p.id, p.name, p.relationship, p.birth_date, p.avatar_memory_id, p.default_language, p.created_at";
+
+pub fn create_person(input: PersonInput) -> Result<Person, String> {
+    validate(&input)?;
+    let person = Person {
+        id: Uuid::new_v4().to_string(),
+        name: input.name.trim().to_string(),
+        relationship: input.relationship,
+        birth_date: input.birth_date,
+        avatar_memory_id: input.avatar_memory_id,
+        default_language: input.default_language.as_deref().and_then(languages::normalize).map(str::to_string),
+        created_at: Utc::now().to_rfc3339(),
+    };
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO people (id, name, relationship, birth_date, avatar_memory_id, default_language, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            person.id,
+            person.name,
+            person.relationship,
+            person.birth_date,
+            person.avatar_memory_id,
+            person.default_language,
+            person.created_at
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(person)
+}
+
+pub fn update_person(id: &str, input: PersonInput) -> Result<Person, String> {
+    validate(&input)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let updated = conn
+        .execute(
+            "UPDATE people SET name = ?1, relationship = ?2, birth_date = ?3, avatar_memory_id = ?4,
+                    default_language = ?5
+             WHERE id = ?6",
+            params![
+                input.name.trim(),
+                input.relationship,
+                input.birth_date,
+                input.avatar_memory_id,
+                input.default_language.as_deref().and_then(languages::normalize),
+                id
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err("Person not found".into());
+    }
+    get_person(id)
+}
+
+/// Delete a person along with every tag linking them to memories.
+pub fn delete_person(id: &str) -> Result<(), String> {
+    let device_id = oplog::local_device_id()?;
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    require_person(&tx, id)?;
+    let tagged = tx
+        .prepare("SELECT memory_id FROM memory_people WHERE person_id = ?1")
+        .and_then(|mut stmt| {
+            stmt.query_map(params![id], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| e.to_string())?;
+    for memory_id in tagged {
+        oplog::remove_person(&tx, &device_id, &memory_id, id)?;
+    }
+
+    tx.execute("DELETE FROM memory_people WHERE person_id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM people WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+pub fn get_person(id: &str) -> Result<Person, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        &format!("SELECT {} FROM people p WHERE p.id = ?1", PERSON_COLUMNS),
+        params![id],
+        row_to_person,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "Person not found".to_string())
+}
+
+pub fn list_people() -> Result<Vec<Person>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM people p ORDER BY p.name", PERSON_COLUMNS))
+        .map_err(|e| e.to_string())?;
+
+    let people = stmt
+        .query_map([], row_to_person)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(people)
+}
+
+/// Tag a person in a memory, replacing any previous region for that pair.
+pub fn tag_person(memory_id: &str, person_id: &str, region: Option<Region>) -> Result<(), String> {
+    if let Some(r) = region {
+        let in_bounds = |v: f64| (0.0..=1.0).contains(&v);
+        if !(in_bounds(r.x) && in_bounds(r.y) && in_bounds(r.x + r.width) && in_bounds(r.y + r.height)) {
+            return Err("Region must lie within the image".into());
+        }
+    }
+
+    // The op and the row change together, or neither does
+    let device_id = oplog::local_device_id()?;
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    require_memory(&tx, memory_id)?;
+    require_person(&tx, person_id)?;
+    oplog::add_person(&tx, &device_id, memory_id, person_id)?;
+    tx.execute(
+        "INSERT OR REPLACE INTO memory_people (memory_id, person_id, region_x, region_y, region_width, region_height)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            memory_id,
+            person_id,
+            region.map(|r| r.x),
+            region.map(|r| r.y),
+            region.map(|r| r.width),
+            region.map(|r| r.height)
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+pub fn untag_person(memory_id: &str, person_id: &str) -> Result<(), String> {
+    let device_id = oplog::local_device_id()?;
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    require_memory(&tx, memory_id)?;
+    require_person(&tx, person_id)?;
+    oplog::remove_person(&tx, &device_id, memory_id, person_id)?;
+    tx.execute(
+        "DELETE FROM memory_people WHERE memory_id = ?1 AND person_id = ?2",
+        params![memory_id, person_id],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+/// People tagged in a memory, with their age at the memory's capture time.
+pub fn people_in_memory(memory_id: &str) -> Result<Vec<TaggedPerson>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {}, mp.region_x, mp.region_y, mp.region_width, mp.region_height,
+                    COALESCE(m.captured_at, m.created_at)
+             FROM memory_people mp
+             JOIN people p ON p.id = mp.person_id
+             JOIN memories m ON m.id = mp.memory_id
+             WHERE mp.memory_id = ?1
+             ORDER BY p.name",
+            PERSON_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let tagged = stmt
+        .query_map(params![memory_id], |row| {
+            let person = row_to_person(row)?;
+            let region = match (
+                row.get::<_, Option<f64>>(7)?,
+                row.get::<_, Option<f64>>(8)?,
+                row.get::<_, Option<f64>>(9)?,
+                row.get::<_, Option<f64>>(10)?,
+            ) {
+                (Some(x), Some(y), Some(width), Some(height)) => Some(Region { x, y, width, height }),
+                _ => None,
+            };
+            let captured_at: String = row.get(11)?;
+            let age_at_capture = person
+                .birth_date
+                .as_deref()
+                .and_then(|birth| age_at(birth, &captured_at));
+            Ok(TaggedPerson {
+                person,
+                region,
+                age_at_capture,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(tagged)
+}
+
+/// Memories in which every one of `person_ids` is tagged.
+pub fn memories_with_people(person_ids: Vec<String>) -> Result<Vec<db::Memory>, String> {
+    if person_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let placeholders = (1..=person_ids.len())
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT m.id, m.title, m.tags, m.created_at, m.media_type, m.filename
+             FROM memories m
+             JOIN memory_people mp ON mp.memory_id = m.id
+             WHERE mp.person_id IN ({}) AND m.deleted_at IS NULL
+             GROUP BY m.id
+             HAVING COUNT(DISTINCT mp.person_id) = {}
+             ORDER BY COALESCE(m.captured_at, m.created_at)",
+            placeholders,
+            person_ids.len()
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let memories = stmt
+        .query_map(params_from_iter(person_ids.iter()), |row| {
+            Ok(db::Memory {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                tags: row.get(2)?,
+                created_at: row.get(3)?,
+                media_type: row.get(4)?,
+                filename: row.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(memories)
+}
+
+/// Completed years and months between a birth date ("YYYY-MM-DD") and an
+/// RFC 3339 capture timestamp. `None` if either is unparsable or the memory
+/// predates the birth.
+pub fn age_at(birth_date: &str, captured_at: &str) -> Option<Age> {
+    let birth = NaiveDate::parse_from_str(birth_date, "%Y-%m-%d").ok()?;
+    let captured = DateTime::parse_from_rfc3339(captured_at).ok()?.date_naive();
+    if captured < birth {
+        return None;
+    }
+
+    let mut months = (captured.year() - birth.year()) * 12 + captured.month() as i32 - birth.month() as i32;
+    if captured.day() < birth.day() {
+        months -= 1;
+    }
+    let months = months.max(0) as u32;
+
+    Some(Age {
+        years: months / 12,
+        months: months % 12,
+    })
+}
+
+fn validate(input: &PersonInput) -> Result<(), String> {
+    if input.name.trim().is_empty() {
+        return Err("Name is required".into());
+    }
+    if let Some(birth_date) = &input.birth_date {
+        NaiveDate::parse_from_str(birth_date, "%Y-%m-%d")
+            .map_err(|_| "Birth date must be YYYY-MM-DD".to_string())?;
+    }
+    if let Some(language) = &input.default_language {
+        if languages::normalize(language).is_none() {
+            return Err(format!("Unknown language '{}'", language));
+        }
+    }
+    Ok(())
+}
+
+fn require_memory(conn: &Connection, memory_id: &str) -> Result<(), String> {
+    let exists: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM memories WHERE id = ?1 AND deleted_at IS NULL)",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if !exists {
+        return Err("Memory not found".into());
+    }
+    Ok(())
+}
+
+fn require_person(conn: &Connection, person_id: &str) -> Result<(), String> {
+    let exists: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM people WHERE id = ?1)",
+            params![person_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if !exists {
+        return Err("Person not found".into());
+    }
+    Ok(())
+}
+
+fn row_to_person(row: &rusqlite::Row) -> rusqlite::Result<Person> {
+    Ok(Person {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        relationship: row.get(2)?,
+        birth_date: row.get(3)?,
+        avatar_memory_id: row.get(4)?,
+        default_language: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+
+    fn add_memory(id: &str, captured_at: &str) {
+        db::add_memory(db::Memory {
+            id: id.into(),
+            title: id.into(),
+            tags: String::new(),
+            created_at: captured_at.into(),
+            media_type: "image/jpeg".into(),
+            filename: String::new(),
+        })
+        .unwrap();
+    }
+
+    fn add_person(name: &str, birth_date: Option<&str>) -> Person {
+        create_person(PersonInput {
+            name: name.into(),
+            relationship: None,
+            birth_date: birth_date.map(str::to_string),
+            avatar_memory_id: None,
+            default_language: None,
+        })
+        .unwrap()
+    }
+
+    fn op_count(memory_id: &str) -> usize {
+        oplog::history(memory_id).unwrap().len()
+    }
+
+    #[test]
+    fn age_counts_completed_years_and_months() {
+        let age = |birth, captured| age_at(birth, captured).map(|a| (a.years, a.months));
+        assert_eq!(age("2015-03-20", "2019-08-15T10:00:00Z"), Some((4, 4)));
+        assert_eq!(age("2015-03-20", "2016-03-19T10:00:00Z"), Some((0, 11)));
+        assert_eq!(age("2015-03-20", "2016-03-20T00:00:00Z"), Some((1, 0)));
+        assert_eq!(age("2015-03-20", "2015-03-20T12:00:00+02:00"), Some((0, 0)));
+        assert_eq!(age("2015-03-20", "2015-03-19T10:00:00Z"), None);
+        assert_eq!(age("20/03/2015", "2019-08-15T10:00:00Z"), None);
+        assert_eq!(age("2015-03-20", "yesterday"), None);
+    }
+
+    #[test]
+    fn tagging_records_the_region_and_age() {
+        let _vault = TempVault::new();
+        add_memory("m1", "2019-08-15T10:00:00Z");
+        let asha = add_person("Asha", Some("2015-03-20"));
+        let region = Region { x: 0.1, y: 0.2, width: 0.3, height: 0.4 };
+
+        tag_person("m1", &asha.id, Some(region)).unwrap();
+        let tagged = people_in_memory("m1").unwrap();
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].person.name, "Asha");
+        assert_eq!(tagged[0].region.map(|r| r.width), Some(0.3));
+        assert_eq!(tagged[0].age_at_capture.map(|a| (a.years, a.months)), Some((4, 4)));
+
+        // Tagging again moves the region without a second op
+        let ops = op_count("m1");
+        tag_person("m1", &asha.id, None).unwrap();
+        assert_eq!(op_count("m1"), ops);
+        assert!(people_in_memory("m1").unwrap()[0].region.is_none());
+
+        untag_person("m1", &asha.id).unwrap();
+        assert!(people_in_memory("m1").unwrap().is_empty());
+        assert!(!oplog::fold(&oplog::history("m1").unwrap()).people.contains(&asha.id));
+    }
+
+    #[test]
+    fn tagging_unknown_rows_changes_nothing() {
+        let _vault = TempVault::new();
+        add_memory("m1", "2019-08-15T10:00:00Z");
+        let asha = add_person("Asha", None);
+
+        assert_eq!(tag_person("m1", "nobody", None).unwrap_err(), "Person not found");
+        assert_eq!(tag_person("missing", &asha.id, None).unwrap_err(), "Memory not found");
+        let outside = Region { x: 0.8, y: 0.0, width: 0.3, height: 0.1 };
+        assert!(tag_person("m1", &asha.id, Some(outside)).is_err());
+        assert_eq!(untag_person("m1", "nobody").unwrap_err(), "Person not found");
+        assert_eq!(op_count("m1"), 0);
+        assert_eq!(op_count("missing"), 0);
+        assert!(people_in_memory("m1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn deleting_a_person_untags_them_everywhere() {
+        let _vault = TempVault::new();
+        add_memory("m1", "2019-08-15T10:00:00Z");
+        add_memory("m2", "2020-01-01T10:00:00Z");
+        let asha = add_person("Asha", None);
+        let ravi = add_person("Ravi", None);
+        for memory_id in ["m1", "m2"] {
+            tag_person(memory_id, &asha.id, None).unwrap();
+        }
+        tag_person("m2", &ravi.id, None).unwrap();
+
+        let both = memories_with_people(vec![asha.id.clone(), ravi.id.clone()]).unwrap();
+        assert_eq!(both.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), ["m2"]);
+        assert_eq!(memories_with_people(vec![asha.id.clone()]).unwrap().len(), 2);
+
+        delete_person(&asha.id).unwrap();
+        assert_eq!(get_person(&asha.id).unwrap_err(), "Person not found");
+        assert!(memories_with_people(vec![asha.id.clone()]).unwrap().is_empty());
+        assert_eq!(people_in_memory("m2").unwrap().len(), 1);
+        assert!(!oplog::fold(&oplog::history("m1").unwrap()).people.contains(&asha.id));
+        assert_eq!(delete_person(&asha.id).unwrap_err(), "Person not found");
+    }
+}
@@ -0,0 +1,42 @@
+use rusqlite::{params, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::db;
+
+/// Read a raw setting value.
+pub fn get_setting(key: &str) -> Result<Option<String>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT value FROM settings WHERE key = ?1",
+        params![key],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn set_setting(key: &str, value: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO settings (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, value],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Read a JSON-encoded settings section, falling back to its default when
+/// it has never been saved.
+pub fn get_json<T: DeserializeOwned + Default>(key: &str) -> Result<T, String> {
+    match get_setting(key)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| e.to_string()),
+        None => Ok(T::default()),
+    }
+}
+
+pub fn set_json<T: Serialize>(key: &str, value: &T) -> Result<(), String> {
+    let value = serde_json::to_string(value).map_err(|e| e.to_string())?;
+    set_setting(key, &value)
+}
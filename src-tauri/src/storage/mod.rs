@@ -0,0 +1,462 @@
+use chrono::{Months, Utc};
+use image::ImageOutputFormat;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::p2p::replication;
+use crate::p2p::P2pState;
+use crate::{crypto, db, ipfs, media, settings, vault};
+use crate::vault::MEDIA_DIR;
+
+const SETTINGS_KEY: &str = "storage_policy";
+const THUMBNAIL_SIZE: u32 = 320;
+/// A paired device must have confirmed holding a blob this recently for
+/// the local original to be evicted. Older confirmations may predate the
+/// device evicting (or losing) its own copy.
+const REPLICA_MAX_AGE_HOURS: i64 = 24;
+
+/// Which originals this device keeps on disk. Everything else may be
+/// evicted once another device or the IPFS node holds a copy; thumbnails
+/// of images stay local.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoragePolicy {
+    pub enabled: bool,
+    /// Keep memories captured within this many months.
+    pub recent_months: Option<u32>,
+    /// Keep memories in any of these collections.
+    pub collections: Vec<String>,
+    /// Evict the oldest other originals until local ones fit in this many bytes.
+    /// Without a budget every original not kept above is evicted.
+    pub size_budget: Option<u64>,
+}
+
+impl StoragePolicy {
+    pub fn load() -> Result<Self, String> {
+        settings::get_json(SETTINGS_KEY)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        settings::set_json(SETTINGS_KEY, self)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EvictionReport {
+    pub evicted: usize,
+    pub freed_bytes: u64,
+    pub kept: usize,
+    /// Would have been evicted but no other copy is known yet.
+    pub not_replicated: usize,
+    pub errors: Vec<String>,
+}
+
+struct Original {
+    memory_id: String,
+    filename: String,
+    media_type: String,
+    size: u64,
+    keep: bool,
+}
+
+/// Note that `device_id` holds the encrypted blob with `hash`.
+pub fn record_replica(memory_id: &str, device_id: &str, hash: &str) -> Result<(), String> {
+    vault::check_hash(hash)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO blob_replicas (memory_id, device_id, hash, seen_at) VALUES (?1, ?2, ?3, ?4)",
+        params![memory_id, device_id, hash, Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Note that `device_id` no longer holds a blob for the memory.
+pub fn forget_replica(memory_id: &str, device_id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM blob_replicas WHERE memory_id = ?1 AND device_id = ?2",
+        params![memory_id, device_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Memories whose original was evicted on purpose and should not be
+/// downloaded again by a sync.
+pub fn evicted_ids() -> Result<HashSet<String>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT id FROM memories WHERE evicted_at IS NOT NULL")
+        .map_err(|e| e.to_string())?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(ids)
+}
+
+/// Evict local originals the policy does not keep, oldest first, as long
+/// as a copy exists elsewhere.
+pub fn apply_policy() -> Result<EvictionReport, String> {
+    let policy = StoragePolicy::load()?;
+    let mut report = EvictionReport::default();
+    if !policy.enabled {
+        return Ok(report);
+    }
+
+    let originals = local_originals(&policy)?;
+    let mut total: u64 = originals.iter().map(|o| o.size).sum();
+    for original in &originals {
+        if original.keep {
+            report.kept += 1;
+            continue;
+        }
+        if policy.size_budget.map_or(false, |budget| total <= budget) {
+            report.kept += 1;
+            continue;
+        }
+        match evict(original) {
+            Ok(true) => {
+                report.evicted += 1;
+                report.freed_bytes += original.size;
+                total = total.saturating_sub(original.size);
+            }
+            Ok(false) => report.not_replicated += 1,
+            Err(e) => report.errors.push(format!("{}: {}", original.memory_id, e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Make sure a memory's original is on disk, fetching an evicted (or
+/// lost) one from a paired device or, failing that, the IPFS node.
+pub async fn ensure_original(p2p: &P2pState, memory_id: &str) -> Result<(), String> {
+    let filename = memory_file(memory_id)?;
+    let blob = Path::new(MEDIA_DIR).join(format!("{}.enc", filename));
+    if filename.is_empty() || blob.exists() || Path::new(MEDIA_DIR).join(&filename).is_file() {
+        return Ok(());
+    }
+    fetch_original(p2p, memory_id, &blob).await?;
+    mark_restored(memory_id)
+}
+
+/// Download a memory's original to `destination`, which is only written
+/// once the blob matches its known hash.
+pub async fn fetch_original(p2p: &P2pState, memory_id: &str, destination: &Path) -> Result<(), String> {
+    let hash = known_hash(memory_id)?.ok_or("No copy of this original is known")?;
+    vault::check_hash(&hash)?;
+
+    let mut errors = Vec::new();
+    match p2p.fetch_blob(memory_id, &hash, destination).await {
+        Ok(()) => return Ok(()),
+        Err(e) => errors.push(format!("peers: {}", e)),
+    }
+    match fetch_from_ipfs(memory_id, &hash, destination).await {
+        Ok(()) => return Ok(()),
+        Err(e) => errors.push(format!("IPFS: {}", e)),
+    }
+    Err(format!("Could not fetch the original ({})", errors.join("; ")))
+}
+
+/// Seal originals that earlier versions kept in the clear and remove the
+/// plain copies, returning how many were sealed. Run once at startup; new
+/// originals are sealed as they are added.
+pub fn seal_plain_originals() -> Result<usize, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT id, filename FROM memories WHERE filename != ''")
+        .map_err(|e| e.to_string())?;
+    let memories = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let media_dir = Path::new(MEDIA_DIR);
+    let mut sealed = 0;
+    for (memory_id, filename) in memories {
+        let plain = media_dir.join(&filename);
+        if !plain.is_file() {
+            continue;
+        }
+        if !media_dir.join(format!("{}.enc", filename)).exists() {
+            let key = db::get_memory_key(&memory_id)?;
+            let bytes = fs::read(&plain).map_err(|e| e.to_string())?;
+            media::seal_media(media_dir, &filename, &bytes, &key)?;
+            sealed += 1;
+        }
+        fs::remove_file(&plain).map_err(|e| e.to_string())?;
+    }
+    Ok(sealed)
+}
+
+/// Decrypted original, fetched first if it was evicted.
+pub async fn open_original(p2p: &P2pState, memory_id: &str) -> Result<Vec<u8>, String> {
+    ensure_original(p2p, memory_id).await?;
+    let filename = memory_file(memory_id)?;
+    let key = db::get_memory_key(memory_id)?;
+    media::load_media_bytes(Path::new(MEDIA_DIR), &filename, &key)
+}
+
+/// JPEG thumbnail of an image memory, made from the original on first
+/// use and kept encrypted next to it.
+pub fn thumbnail(memory_id: &str) -> Result<Vec<u8>, String> {
+    let filename = memory_file(memory_id)?;
+    let key = db::get_memory_key(memory_id)?;
+    let path = thumbnail_path(&filename);
+    if path.exists() {
+        let data = fs::read(&path).map_err(|e| e.to_string())?;
+        return crypto::decrypt_bytes(&data, &key);
+    }
+    make_thumbnail(&filename, &key)
+}
+
+pub fn thumbnail_path(filename: &str) -> PathBuf {
+    Path::new(MEDIA_DIR).join(format!("{}.thumb.enc", filename))
+}
+
+fn make_thumbnail(filename: &str, key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let bytes = media::load_media_bytes(Path::new(MEDIA_DIR), filename, key)?;
+    let image = image::load_from_memory(&bytes).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let mut jpeg = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut jpeg, ImageOutputFormat::Jpeg(80))
+        .map_err(|e| e.to_string())?;
+    let jpeg = jpeg.into_inner();
+
+    let sealed = crypto::encrypt_bytes(&jpeg, key)?;
+    vault::write_atomic(&thumbnail_path(filename), &sealed).map_err(|e| e.to_string())?;
+    Ok(jpeg)
+}
+
+/// Returns `false` without touching anything when no other copy of the
+/// exact blob is known.
+fn evict(original: &Original) -> Result<bool, String> {
+    let (blob, hash) = match replication::sealed_blob(&original.memory_id)? {
+        Some(sealed) => sealed,
+        None => return Ok(false),
+    };
+    if !is_replicated(&original.memory_id, &hash)? {
+        return Ok(false);
+    }
+
+    if original.media_type.starts_with("image/") && !thumbnail_path(&original.filename).exists() {
+        let key = db::get_memory_key(&original.memory_id)?;
+        make_thumbnail(&original.filename, &key)?;
+    }
+
+    // Mark first: a sync running meanwhile must not fetch it straight back.
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE memories SET evicted_at = ?1 WHERE id = ?2",
+        params![Utc::now().to_rfc3339(), original.memory_id],
+    )
+    .map_err(|e| e.to_string())?;
+    fs::remove_file(&blob).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Pinned on the IPFS node with this exact ciphertext, or recently
+/// confirmed by a device that is still paired.
+fn is_replicated(memory_id: &str, hash: &str) -> Result<bool, String> {
+    if ipfs::get_pin(memory_id)?.is_some_and(|pin| pin.hash.as_deref() == Some(hash)) {
+        return Ok(true);
+    }
+    let since = (Utc::now() - chrono::Duration::hours(REPLICA_MAX_AGE_HOURS)).to_rfc3339();
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT EXISTS(
+            SELECT 1 FROM blob_replicas r JOIN devices d ON d.id = r.device_id
+            WHERE r.memory_id = ?1 AND r.hash = ?2 AND r.seen_at >= ?3
+         )",
+        params![memory_id, hash, since],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Originals on disk, oldest capture first, flagged with whether the
+/// policy keeps them.
+fn local_originals(policy: &StoragePolicy) -> Result<Vec<Original>, String> {
+    let cutoff = policy
+        .recent_months
+        .and_then(|months| Utc::now().checked_sub_months(Months::new(months)))
+        .map(|cutoff| cutoff.to_rfc3339());
+    let keep_collections: BTreeSet<&str> = policy.collections.iter().map(String::as_str).collect();
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, filename, media_type, COALESCE(captured_at, created_at), collections FROM memories
+             WHERE evicted_at IS NULL AND deleted_at IS NULL AND filename != ''
+             ORDER BY COALESCE(captured_at, created_at)",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut originals = Vec::new();
+    for (memory_id, filename, media_type, taken_at, collections) in rows {
+        let media_dir = Path::new(MEDIA_DIR);
+        let size = [media_dir.join(format!("{}.enc", filename)), media_dir.join(&filename)]
+            .iter()
+            .find_map(|path| fs::metadata(path).ok().filter(|m| m.is_file()))
+            .map(|m| m.len());
+        let Some(size) = size else {
+            continue;
+        };
+
+        let recent = cutoff.as_deref().map_or(false, |cutoff| taken_at.as_str() >= cutoff);
+        let collections: Vec<String> = collections
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        let in_collection = collections.iter().any(|c| keep_collections.contains(c.as_str()));
+
+        originals.push(Original {
+            memory_id,
+            filename,
+            media_type,
+            size,
+            keep: recent || in_collection,
+        });
+    }
+    Ok(originals)
+}
+
+fn memory_file(memory_id: &str) -> Result<String, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT filename FROM memories WHERE id = ?1",
+        params![memory_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "Memory not found".to_string())
+}
+
+/// Hash of the blob as last seen locally or on another device.
+pub fn known_hash(memory_id: &str) -> Result<Option<String>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT hash FROM replica_blobs WHERE memory_id = ?1
+         UNION ALL
+         SELECT hash FROM blob_replicas WHERE memory_id = ?1
+         LIMIT 1",
+        params![memory_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+async fn fetch_from_ipfs(memory_id: &str, hash: &str, blob: &Path) -> Result<(), String> {
+    let pin = ipfs::get_pin(memory_id)?.ok_or("not pinned")?;
+    let bytes = ipfs::IpfsSettings::load()?.client().cat(&pin.cid).await?;
+    if format!("{:x}", Sha256::digest(&bytes)) != hash {
+        return Err("Blob failed verification".into());
+    }
+    vault::write_atomic(blob, &bytes).map_err(|e| e.to_string())
+}
+
+fn mark_restored(memory_id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute("UPDATE memories SET evicted_at = NULL WHERE id = ?1", params![memory_id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+    use crate::p2p::devices;
+
+    #[test]
+    fn only_fresh_replicas_on_paired_devices_count() {
+        let _vault = TempVault::new();
+        let hash = format!("{:x}", Sha256::digest(b"blob"));
+        devices::save_device("laptop", "Laptop", "bGFwdG9w", None).unwrap();
+
+        record_replica("m1", "laptop", &hash).unwrap();
+        assert!(is_replicated("m1", &hash).unwrap());
+        assert!(!is_replicated("m1", &format!("{:x}", Sha256::digest(b"other"))).unwrap());
+
+        // The laptop reported the blob gone
+        forget_replica("m1", "laptop").unwrap();
+        assert!(!is_replicated("m1", &hash).unwrap());
+
+        // Confirmed too long ago
+        record_replica("m2", "laptop", &hash).unwrap();
+        let stale = (Utc::now() - chrono::Duration::hours(REPLICA_MAX_AGE_HOURS + 1)).to_rfc3339();
+        let conn = db::get_connection().unwrap();
+        conn.execute("UPDATE blob_replicas SET seen_at = ?1 WHERE memory_id = 'm2'", params![stale])
+            .unwrap();
+        assert!(!is_replicated("m2", &hash).unwrap());
+
+        // Held by a device that has since been unpaired
+        record_replica("m3", "phone", &hash).unwrap();
+        assert!(!is_replicated("m3", &hash).unwrap());
+    }
+
+    #[test]
+    fn only_a_pin_of_the_same_ciphertext_counts() {
+        let _vault = TempVault::new();
+        let hash = format!("{:x}", Sha256::digest(b"blob"));
+        let conn = db::get_connection().unwrap();
+        for (memory_id, pinned) in [("m1", Some(hash.clone())), ("m2", Some("stale".to_string())), ("m3", None)] {
+            conn.execute(
+                "INSERT INTO ipfs_pins (memory_id, cid, size, pinned_at, hash) VALUES (?1, 'cid', 4, ?2, ?3)",
+                params![memory_id, Utc::now().to_rfc3339(), pinned],
+            )
+            .unwrap();
+        }
+
+        assert!(is_replicated("m1", &hash).unwrap());
+        // Pinned before the original was re-sealed
+        assert!(!is_replicated("m2", &hash).unwrap());
+        // Pinned before hashes were recorded
+        assert!(!is_replicated("m3", &hash).unwrap());
+    }
+
+    #[test]
+    fn plain_originals_are_sealed_once() {
+        let _vault = TempVault::new();
+        let conn = db::get_connection().unwrap();
+        conn.execute(
+            "INSERT INTO memories (id, title, tags, created_at, media_type, filename, key_encrypted, transcription)
+             VALUES ('m1', 'Old', '', '2020-01-01T00:00:00Z', 'image/jpeg', 'old.jpg', '', '')",
+            [],
+        )
+        .unwrap();
+        let plain = Path::new(MEDIA_DIR).join("old.jpg");
+        fs::write(&plain, b"pixels").unwrap();
+
+        assert_eq!(seal_plain_originals().unwrap(), 1);
+        assert!(!plain.exists());
+        let key = db::get_memory_key("m1").unwrap();
+        assert_eq!(media::load_media_bytes(Path::new(MEDIA_DIR), "old.jpg", &key).unwrap(), b"pixels");
+        let (_, hash) = replication::sealed_blob("m1").unwrap().unwrap();
+        assert_eq!(hash, vault::hash_file(&Path::new(MEDIA_DIR).join("old.jpg.enc")).unwrap());
+        assert_eq!(seal_plain_originals().unwrap(), 0);
+    }
+}
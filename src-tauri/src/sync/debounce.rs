@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long a path must go without events before it is looked at.
+pub const QUIET_WINDOW: Duration = Duration::from_millis(1000);
+
+struct Pending {
+    due: Instant,
+    /// Size seen when the window last elapsed, `None` until first checked.
+    size: Option<u64>,
+}
+
+/// Coalesces raw watcher events per path. A path becomes ready once it
+/// has been quiet for `QUIET_WINDOW` and its size did not change across
+/// the last window, so files still being written are not synced half-way.
+#[derive(Default)]
+pub struct Debouncer {
+    pending: HashMap<PathBuf, Pending>,
+}
+
+impl Debouncer {
+    pub fn push(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.push_at(paths, Instant::now());
+    }
+
+    fn push_at(&mut self, paths: impl IntoIterator<Item = PathBuf>, now: Instant) {
+        let due = now + QUIET_WINDOW;
+        for path in paths {
+            self.pending
+                .entry(path)
+                .and_modify(|p| {
+                    p.due = due;
+                    p.size = None;
+                })
+                .or_insert(Pending { due, size: None });
+        }
+    }
+
+    /// Paths still waiting to settle.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Time until the next path is due, or `None` when nothing is pending.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.pending
+            .values()
+            .map(|p| p.due.saturating_duration_since(Instant::now()))
+            .min()
+    }
+
+    /// Remove and return every path that is due and stable. Due paths
+    /// whose size is still changing get another window.
+    pub fn take_ready(&mut self) -> Vec<PathBuf> {
+        self.take_ready_at(Instant::now())
+    }
+
+    fn take_ready_at(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+
+        self.pending.retain(|path, pending| {
+            if pending.due > now {
+                return true;
+            }
+            let size = match fs::metadata(path) {
+                Ok(meta) if meta.is_file() => meta.len(),
+                // Removed, or a directory: nothing to wait for.
+                _ => {
+                    ready.push(path.clone());
+                    return false;
+                }
+            };
+            if pending.size == Some(size) {
+                ready.push(path.clone());
+                false
+            } else {
+                pending.size = Some(size);
+                pending.due = now + QUIET_WINDOW;
+                true
+            }
+        });
+
+        ready.sort();
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("aethersync-test-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn paths_wait_for_the_quiet_window_and_a_stable_size() {
+        let dir = Dir::new();
+        let file = dir.0.join("video.mp4");
+        fs::write(&file, b"part").unwrap();
+        let start = Instant::now();
+        let mut debouncer = Debouncer::default();
+
+        debouncer.push_at([file.clone()], start);
+        assert!(debouncer.take_ready_at(start).is_empty());
+        // Due, but its size is only now known: one more window
+        let first = start + QUIET_WINDOW;
+        assert!(debouncer.take_ready_at(first).is_empty());
+        assert_eq!(debouncer.pending_count(), 1);
+
+        // Still growing: another window
+        fs::write(&file, b"partial write").unwrap();
+        let second = first + QUIET_WINDOW;
+        assert!(debouncer.take_ready_at(second).is_empty());
+
+        let third = second + QUIET_WINDOW;
+        assert_eq!(debouncer.take_ready_at(third), vec![file]);
+        assert_eq!(debouncer.pending_count(), 0);
+        assert_eq!(debouncer.next_timeout(), None);
+    }
+
+    #[test]
+    fn new_events_restart_the_window() {
+        let dir = Dir::new();
+        let file = dir.0.join("notes.txt");
+        fs::write(&file, b"notes").unwrap();
+        let start = Instant::now();
+        let mut debouncer = Debouncer::default();
+
+        debouncer.push_at([file.clone()], start);
+        debouncer.take_ready_at(start + QUIET_WINDOW);
+        // An event just before it would have been ready starts over
+        let again = start + QUIET_WINDOW * 2 - Duration::from_millis(1);
+        debouncer.push_at([file.clone(), file.clone()], again);
+        assert_eq!(debouncer.pending_count(), 1);
+        assert!(debouncer.take_ready_at(start + QUIET_WINDOW * 2).is_empty());
+        assert!(debouncer.take_ready_at(again + QUIET_WINDOW).is_empty());
+        assert_eq!(debouncer.take_ready_at(again + QUIET_WINDOW * 2), vec![file]);
+    }
+
+    #[test]
+    fn removed_files_and_directories_are_ready_once_due() {
+        let dir = Dir::new();
+        let removed = dir.0.join("removed.jpg");
+        let folder = dir.0.join("album");
+        fs::create_dir_all(&folder).unwrap();
+        let start = Instant::now();
+        let mut debouncer = Debouncer::default();
+
+        debouncer.push_at([removed.clone(), folder.clone()], start);
+        assert!(debouncer.take_ready_at(start + QUIET_WINDOW / 2).is_empty());
+        let mut expected = vec![removed, folder];
+        expected.sort();
+        assert_eq!(debouncer.take_ready_at(start + QUIET_WINDOW), expected);
+    }
+}
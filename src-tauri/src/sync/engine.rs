@@ -0,0 +1,935 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use super::index::{IndexEntry, SyncIndex};
+use super::profiles::{Direction, PathFilter};
+use super::status::{SyncEvent, SyncTracker};
+use crate::vault;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Source,
+    Target,
+}
+
+impl Side {
+    fn other(self) -> Side {
+        match self {
+            Side::Source => Side::Target,
+            Side::Target => Side::Source,
+        }
+    }
+}
+
+/// A file as currently found on one side. The hash is computed lazily,
+/// only when size or mtime no longer match the index.
+#[derive(Debug, Clone)]
+struct FileState {
+    size: u64,
+    mtime: i64,
+    hash: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Change {
+    Unchanged,
+    Created,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub copied: usize,
+    pub deleted: usize,
+    pub renamed: usize,
+    pub conflicts: Vec<String>,
+    /// Paths that could not be synced; they are retried on their next change.
+    pub failed: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    pub direction: Direction,
+    pub filter: PathFilter,
+    /// Bytes per second per copy.
+    pub bandwidth_limit: Option<u64>,
+}
+
+/// Sync between a source and a target folder, two-way by default. Changes on each
+/// side are found by comparing it against the last synced state in the
+/// index, so either side may be edited while sync is stopped.
+pub struct SyncEngine {
+    source: PathBuf,
+    target: PathBuf,
+    index: SyncIndex,
+    options: EngineOptions,
+    tracker: Arc<SyncTracker>,
+}
+
+impl SyncEngine {
+    pub fn new(source: &Path, target: &Path, options: EngineOptions, tracker: Arc<SyncTracker>) -> Result<Self, String> {
+        fs::create_dir_all(source).map_err(|e| e.to_string())?;
+        fs::create_dir_all(target).map_err(|e| e.to_string())?;
+        let source = source.canonicalize().map_err(|e| e.to_string())?;
+        let target = target.canonicalize().map_err(|e| e.to_string())?;
+        if source.starts_with(&target) || target.starts_with(&source) {
+            return Err("Sync folders must not be nested inside each other".into());
+        }
+
+        let pair_id = pair_id(&source, &target);
+        Ok(Self {
+            index: SyncIndex::open(&pair_id)?,
+            source,
+            target,
+            options,
+            tracker,
+        })
+    }
+
+    pub fn roots(&self) -> (&Path, &Path) {
+        (&self.source, &self.target)
+    }
+
+    /// Compare both folders in full against the index.
+    pub fn reconcile_all(&self) -> Result<SyncReport, String> {
+        self.reconcile(&[String::new()])
+    }
+
+    /// Reconcile the paths touched by a batch of watcher events. Absolute
+    /// paths on either side are accepted and directories are expanded to
+    /// their contents. The batch is compared in one pass so both halves
+    /// of a rename are seen together.
+    pub fn reconcile_paths(&self, paths: &[PathBuf]) -> Result<SyncReport, String> {
+        let prefixes: Vec<String> = paths
+            .iter()
+            .filter_map(|p| self.relative(p))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if prefixes.is_empty() {
+            return Ok(SyncReport::default());
+        }
+        self.reconcile(&prefixes)
+    }
+
+    fn relative(&self, path: &Path) -> Option<String> {
+        let rel = path
+            .strip_prefix(&self.source)
+            .or_else(|_| path.strip_prefix(&self.target))
+            .ok()?;
+        Some(to_rel_string(rel))
+    }
+
+    /// The side whose changes win outright, or `None` for two-way sync.
+    fn authority(&self) -> Option<Side> {
+        match self.options.direction {
+            Direction::Push | Direction::Mirror => Some(Side::Source),
+            Direction::Pull => Some(Side::Target),
+            Direction::TwoWay => None,
+        }
+    }
+
+    fn root(&self, side: Side) -> &Path {
+        match side {
+            Side::Source => &self.source,
+            Side::Target => &self.target,
+        }
+    }
+
+    fn reconcile(&self, prefixes: &[String]) -> Result<SyncReport, String> {
+        let mut source = HashMap::new();
+        let mut target = HashMap::new();
+        let mut index = HashMap::new();
+        let mut dirs = BTreeSet::new();
+        let mut indexed_dirs = BTreeSet::new();
+        for prefix in prefixes {
+            let (files, found) = scan(&self.source, prefix).map_err(|e| e.to_string())?;
+            source.extend(files);
+            dirs.extend(found);
+            let (files, found) = scan(&self.target, prefix).map_err(|e| e.to_string())?;
+            target.extend(files);
+            dirs.extend(found);
+            index.extend(self.index.entries_under(prefix)?);
+            indexed_dirs.extend(self.index.dirs_under(prefix)?);
+        }
+        dirs.extend(indexed_dirs.iter().cloned());
+        // Filtered out paths are neither copied nor deleted, even when the
+        // index still lists them from before the filter changed.
+        let filter = &self.options.filter;
+        dirs.retain(|d| filter.includes_dir(d));
+
+        let paths: BTreeSet<String> = source
+            .keys()
+            .chain(target.keys())
+            .chain(index.keys())
+            .filter(|p| filter.includes_file(p))
+            .cloned()
+            .collect();
+
+        let mut changes: HashMap<String, (Change, Change)> = HashMap::new();
+        for path in &paths {
+            let base = index.get(path);
+            let sc = self.detect(Side::Source, path, source.get_mut(path), base)?;
+            let tc = self.detect(Side::Target, path, target.get_mut(path), base)?;
+            changes.insert(path.clone(), (sc, tc));
+        }
+
+        let mut report = SyncReport::default();
+        let mut handled = BTreeSet::new();
+        if self.authority() != Some(Side::Target) {
+            self.propagate_renames(Side::Source, &changes, &mut source, &index, &mut handled, &mut report)?;
+        }
+        if self.authority() != Some(Side::Source) {
+            self.propagate_renames(Side::Target, &changes, &mut target, &index, &mut handled, &mut report)?;
+        }
+
+        for path in &paths {
+            if handled.contains(path) {
+                continue;
+            }
+            // One unreadable or locked file must not hold up the rest.
+            if let Err(error) = self.apply(path, &changes[path], &mut source, &mut target, &mut report) {
+                report.failed += 1;
+                self.tracker.record(SyncEvent::Failed {
+                    path: path.clone(),
+                    error,
+                });
+            }
+        }
+
+        self.reconcile_dirs(&dirs, &indexed_dirs, &mut report)?;
+        Ok(report)
+    }
+
+    fn apply(
+        &self,
+        path: &str,
+        change: &(Change, Change),
+        source: &mut HashMap<String, FileState>,
+        target: &mut HashMap<String, FileState>,
+        report: &mut SyncReport,
+    ) -> Result<(), String> {
+        if let Some(side) = self.authority() {
+            let (ours, theirs) = match side {
+                Side::Source => (&change.0, &change.1),
+                Side::Target => (&change.1, &change.0),
+            };
+            let states = match side {
+                Side::Source => source,
+                Side::Target => target,
+            };
+            // Only a mirror undoes changes made on the other side.
+            let mirror = self.options.direction == Direction::Mirror;
+            if *ours != Change::Unchanged || (mirror && *theirs != Change::Unchanged) {
+                return self.propagate(side, path, states.get_mut(path), report);
+            }
+            return Ok(());
+        }
+
+        match change {
+            (Change::Unchanged, Change::Unchanged) => Ok(()),
+            (_, Change::Unchanged) => self.propagate(Side::Source, path, source.get_mut(path), report),
+            (Change::Unchanged, _) => self.propagate(Side::Target, path, target.get_mut(path), report),
+            (Change::Deleted, Change::Deleted) => self.index.remove(path),
+            // Edits win over deletes so no data is lost.
+            (Change::Deleted, _) => self.propagate(Side::Target, path, target.get_mut(path), report),
+            (_, Change::Deleted) => self.propagate(Side::Source, path, source.get_mut(path), report),
+            _ => {
+                let (s, t) = match (source.get_mut(path), target.get_mut(path)) {
+                    (Some(s), Some(t)) => (s, t),
+                    _ => return Ok(()),
+                };
+                let s_hash = ensure_hash(&self.source.join(path), s)?;
+                let t_hash = ensure_hash(&self.target.join(path), t)?;
+                if s_hash == t_hash {
+                    // Same edit made on both sides.
+                    self.index.record(path, s.size, &s_hash, s.mtime, t.mtime)
+                } else {
+                    self.resolve_conflict(path, s, t, report)
+                }
+            }
+        }
+    }
+
+    /// Mirror directory creation and removal, after file changes have been
+    /// applied. Deepest paths go first so emptied parents can be removed,
+    /// e.g. the old side of a renamed folder.
+    fn reconcile_dirs(&self, dirs: &BTreeSet<String>, indexed: &BTreeSet<String>, report: &mut SyncReport) -> Result<(), String> {
+        for dir in dirs.iter().rev() {
+            let in_source = self.source.join(dir).is_dir();
+            let in_target = self.target.join(dir).is_dir();
+            if let Some(side) = self.authority() {
+                self.reconcile_dir_one_way(side, dir, (in_source, in_target), indexed.contains(dir), report)?;
+                continue;
+            }
+            match (in_source, in_target, indexed.contains(dir)) {
+                (true, true, _) => self.index.record_dir(dir)?,
+                (false, false, _) => self.index.remove(dir)?,
+                // Created on one side since the last sync.
+                (true, false, false) | (false, true, false) => {
+                    let missing = if in_source { &self.target } else { &self.source };
+                    fs::create_dir_all(missing.join(dir)).map_err(|e| e.to_string())?;
+                    self.index.record_dir(dir)?;
+                }
+                // Removed on one side. Anything still inside was created or
+                // edited there meanwhile, so the directory stays.
+                (true, false, true) | (false, true, true) => {
+                    let remaining = if in_source { &self.source } else { &self.target };
+                    match fs::remove_dir(remaining.join(dir)) {
+                        Ok(()) => {
+                            self.index.remove(dir)?;
+                            report.deleted += 1;
+                        }
+                        Err(_) => {
+                            let missing = if in_source { &self.target } else { &self.source };
+                            fs::create_dir_all(missing.join(dir)).map_err(|e| e.to_string())?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Directories follow `side`: its folders are created on the other
+    /// side, and folders it removed are removed there once empty. Folders
+    /// only on the other side are left alone unless mirroring.
+    fn reconcile_dir_one_way(&self, side: Side, dir: &str, present: (bool, bool), indexed: bool, report: &mut SyncReport) -> Result<(), String> {
+        let (ours, theirs) = match side {
+            Side::Source => present,
+            Side::Target => (present.1, present.0),
+        };
+        let other = self.root(side.other()).join(dir);
+        match (ours, theirs) {
+            (true, true) => self.index.record_dir(dir),
+            (false, false) => self.index.remove(dir),
+            (true, false) => {
+                fs::create_dir_all(&other).map_err(|e| e.to_string())?;
+                self.index.record_dir(dir)
+            }
+            (false, true) => {
+                if indexed || self.options.direction == Direction::Mirror {
+                    // Still holding files of its own: keep it.
+                    if fs::remove_dir(&other).is_ok() {
+                        report.deleted += 1;
+                        self.index.remove(dir)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn detect(&self, side: Side, path: &str, state: Option<&mut FileState>, base: Option<&IndexEntry>) -> Result<Change, String> {
+        let (state, base) = match (state, base) {
+            (None, None) => return Ok(Change::Unchanged),
+            (None, Some(_)) => return Ok(Change::Deleted),
+            (Some(_), None) => return Ok(Change::Created),
+            (Some(state), Some(base)) => (state, base),
+        };
+
+        let base_mtime = match side {
+            Side::Source => base.source_mtime,
+            Side::Target => base.target_mtime,
+        };
+        if state.size == base.size && state.mtime == base_mtime {
+            return Ok(Change::Unchanged);
+        }
+        // Touched but identical content (e.g. a re-save) is not a change.
+        let hash = ensure_hash(&self.root(side).join(path), state)?;
+        if hash == base.hash {
+            Ok(Change::Unchanged)
+        } else {
+            Ok(Change::Modified)
+        }
+    }
+
+    /// A path deleted on `side` and a new path created there with the
+    /// content the deleted one last had is a rename; repeat it on the
+    /// other side instead of deleting and copying. Only content that
+    /// names exactly one deleted and one created path counts: empty files
+    /// and duplicates could pair up the wrong way, so they go through a
+    /// plain delete and copy.
+    fn propagate_renames(
+        &self,
+        side: Side,
+        changes: &HashMap<String, (Change, Change)>,
+        states: &mut HashMap<String, FileState>,
+        index: &HashMap<String, IndexEntry>,
+        handled: &mut BTreeSet<String>,
+        report: &mut SyncReport,
+    ) -> Result<(), String> {
+        let pick = |c: &(Change, Change)| match side {
+            Side::Source => (c.0.clone(), c.1.clone()),
+            Side::Target => (c.1.clone(), c.0.clone()),
+        };
+
+        let mut deleted: HashMap<String, Vec<String>> = HashMap::new();
+        let mut sizes = BTreeSet::new();
+        for (path, change) in changes {
+            if pick(change) == (Change::Deleted, Change::Unchanged) {
+                if let Some(entry) = index.get(path).filter(|e| e.size > 0) {
+                    deleted.entry(entry.hash.clone()).or_default().push(path.clone());
+                    sizes.insert(entry.size);
+                }
+            }
+        }
+        deleted.retain(|_, paths| paths.len() == 1);
+        if deleted.is_empty() {
+            return Ok(());
+        }
+
+        let root = self.root(side).to_path_buf();
+        let other_root = self.root(side.other()).to_path_buf();
+        let mut created: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, change) in changes {
+            if pick(change) != (Change::Created, Change::Unchanged) {
+                continue;
+            }
+            // Only files that could match a deleted one are hashed.
+            let state = match states.get_mut(path) {
+                Some(state) if sizes.contains(&state.size) => state,
+                _ => continue,
+            };
+            let hash = ensure_hash(&root.join(path), state)?;
+            if deleted.contains_key(&hash) {
+                created.entry(hash).or_default().push(path.clone());
+            }
+        }
+        let mut renames: Vec<(String, String)> = created
+            .into_iter()
+            .filter(|(_, paths)| paths.len() == 1)
+            .filter_map(|(hash, mut paths)| Some((deleted.remove(&hash)?.pop()?, paths.pop()?)))
+            .collect();
+        renames.sort();
+
+        for (old_path, new_path) in renames {
+            let state = match states.get(&new_path) {
+                Some(state) => state,
+                None => continue,
+            };
+
+            let from = other_root.join(&old_path);
+            let to = other_root.join(&new_path);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::rename(&from, &to).map_err(|e| e.to_string())?;
+            let other_mtime = mtime_of(&to).map_err(|e| e.to_string())?;
+
+            let (source_mtime, target_mtime) = match side {
+                Side::Source => (state.mtime, other_mtime),
+                Side::Target => (other_mtime, state.mtime),
+            };
+            self.index.rename(&old_path, &new_path, source_mtime, target_mtime)?;
+            self.record_parents(&new_path)?;
+            self.tracker.record(SyncEvent::Renamed {
+                from: old_path.clone(),
+                to: new_path.clone(),
+            });
+            handled.insert(old_path);
+            handled.insert(new_path);
+            report.renamed += 1;
+        }
+        Ok(())
+    }
+
+    /// Make the other side match `from` for one path.
+    fn propagate(&self, from: Side, path: &str, state: Option<&mut FileState>, report: &mut SyncReport) -> Result<(), String> {
+        let src = self.root(from).join(path);
+        let dst = self.root(from.other()).join(path);
+
+        let state = match state {
+            Some(state) => state,
+            None => {
+                match fs::remove_file(&dst) {
+                    Ok(()) => {
+                        report.deleted += 1;
+                        self.tracker.record(SyncEvent::Deleted { path: path.to_string() });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.to_string()),
+                }
+                return self.index.remove(path);
+            }
+        };
+
+        let hash = ensure_hash(&src, state)?;
+        // Fails without touching `dst` if the file is still being written;
+        // the next event for it picks it up again.
+        vault::copy_atomic_limited(&src, &dst, Some(&hash), self.options.bandwidth_limit).map_err(|e| e.to_string())?;
+        let copied_mtime = mtime_of(&dst).map_err(|e| e.to_string())?;
+        let (source_mtime, target_mtime) = match from {
+            Side::Source => (state.mtime, copied_mtime),
+            Side::Target => (copied_mtime, state.mtime),
+        };
+        self.index.record(path, state.size, &hash, source_mtime, target_mtime)?;
+        self.record_parents(path)?;
+        report.copied += 1;
+        self.tracker.record(SyncEvent::Transferred {
+            path: path.to_string(),
+            bytes: state.size,
+        });
+        Ok(())
+    }
+
+    /// Directories created to hold a copied file now exist on both sides.
+    fn record_parents(&self, path: &str) -> Result<(), String> {
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            self.index.record_dir(parent)?;
+            dir = parent;
+        }
+        Ok(())
+    }
+
+    /// Both sides edited the same file. The most recent edit keeps the
+    /// original name; the other is kept next to it as a conflict copy on
+    /// both sides and recorded for the user to resolve.
+    fn resolve_conflict(&self, path: &str, source: &mut FileState, target: &mut FileState, report: &mut SyncReport) -> Result<(), String> {
+        let (winner, loser_state) = if source.mtime >= target.mtime {
+            (Side::Source, target)
+        } else {
+            (Side::Target, source)
+        };
+        let loser = winner.other();
+        let conflict_path = conflict_name(path);
+
+        let loser_file = self.root(loser).join(path);
+        let conflict_file = self.root(loser).join(&conflict_path);
+        fs::rename(&loser_file, &conflict_file).map_err(|e| e.to_string())?;
+        let conflict_hash = loser_state.hash.clone().unwrap_or_default();
+        let conflict_mtime = mtime_of(&conflict_file).map_err(|e| e.to_string())?;
+        let mut conflict_state = FileState {
+            size: loser_state.size,
+            mtime: conflict_mtime,
+            hash: Some(conflict_hash),
+        };
+        self.propagate(loser, &conflict_path, Some(&mut conflict_state), report)?;
+
+        let mut winner_state = match winner {
+            Side::Source => stat(&self.source.join(path)),
+            Side::Target => stat(&self.target.join(path)),
+        }
+        .map_err(|e| e.to_string())?;
+        self.propagate(winner, path, Some(&mut winner_state), report)?;
+
+        self.index.record_conflict(path, &conflict_path)?;
+        self.tracker.record(SyncEvent::Conflict {
+            path: path.to_string(),
+            conflict_path,
+        });
+        report.conflicts.push(path.to_string());
+        Ok(())
+    }
+}
+
+/// Stable id for a folder pair, so each pair keeps its own index.
+fn pair_id(source: &Path, target: &Path) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(source.to_string_lossy().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(target.to_string_lossy().as_bytes());
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// "2020/img.jpg" -> "2020/img.conflict-20240101-120000.jpg"
+fn conflict_name(path: &str) -> String {
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let (dir, file) = match path.rsplit_once('/') {
+        Some((dir, file)) => (format!("{}/", dir), file),
+        None => (String::new(), path),
+    };
+    match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{}.conflict-{}.{}", dir, stem, stamp, ext),
+        _ => format!("{}{}.conflict-{}", dir, file, stamp),
+    }
+}
+
+fn to_rel_string(rel: &Path) -> String {
+    rel.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Files and directories under `root/prefix`, keyed by path relative to
+/// `root`. The prefix itself is included when it is a directory.
+fn scan(root: &Path, prefix: &str) -> io::Result<(HashMap<String, FileState>, BTreeSet<String>)> {
+    let mut files = HashMap::new();
+    let mut dirs = BTreeSet::new();
+    let start = if prefix.is_empty() { root.to_path_buf() } else { root.join(prefix) };
+    let meta = match fs::symlink_metadata(&start) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((files, dirs)),
+        Err(e) => return Err(e),
+    };
+
+    if vault::is_temp_file(&start) {
+        return Ok((files, dirs));
+    }
+    if meta.is_file() {
+        files.insert(prefix.to_string(), stat(&start)?);
+        return Ok((files, dirs));
+    }
+    if !meta.is_dir() {
+        return Ok((files, dirs));
+    }
+    if !prefix.is_empty() {
+        dirs.insert(prefix.to_string());
+    }
+
+    let mut pending = vec![start];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            let rel = match path.strip_prefix(root) {
+                Ok(rel) => to_rel_string(rel),
+                Err(_) => continue,
+            };
+            if file_type.is_dir() {
+                dirs.insert(rel);
+                pending.push(path);
+            } else if file_type.is_file() && !vault::is_temp_file(&path) {
+                files.insert(rel, stat(&path)?);
+            }
+        }
+    }
+    Ok((files, dirs))
+}
+
+fn stat(path: &Path) -> io::Result<FileState> {
+    let meta = fs::metadata(path)?;
+    Ok(FileState {
+        size: meta.len(),
+        mtime: mtime_of(path)?,
+        hash: None,
+    })
+}
+
+fn mtime_of(path: &Path) -> io::Result<i64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0))
+}
+
+fn ensure_hash(path: &Path, state: &mut FileState) -> Result<String, String> {
+    if let Some(hash) = &state.hash {
+        return Ok(hash.clone());
+    }
+    let hash = vault::hash_file(path).map_err(|e| e.to_string())?;
+    state.hash = Some(hash.clone());
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+    use crate::sync::profiles::SyncProfile;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn engine(vault: &TempVault, direction: Direction) -> SyncEngine {
+        let profile = SyncProfile {
+            id: "test".into(),
+            name: "test".into(),
+            source: String::new(),
+            target: String::new(),
+            direction,
+            schedule_minutes: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            bandwidth_limit: None,
+            enabled: false,
+            created_at: String::new(),
+        };
+        let options = EngineOptions {
+            direction,
+            ..Default::default()
+        };
+        let tracker = Arc::new(SyncTracker::new(None, &profile));
+        SyncEngine::new(&vault.path().join("source"), &vault.path().join("target"), options, tracker).unwrap()
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let file = root.join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, content).unwrap();
+    }
+
+    fn read(root: &Path, path: &str) -> Option<String> {
+        fs::read_to_string(root.join(path)).ok()
+    }
+
+    fn conflict_copies(root: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".conflict-"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn two_way_renames_move_the_file_on_the_other_side() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::TwoWay);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&source, "a.txt", "photo notes");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&target, "a.txt").as_deref(), Some("photo notes"));
+
+        fs::create_dir_all(source.join("2024")).unwrap();
+        fs::rename(source.join("a.txt"), source.join("2024/b.txt")).unwrap();
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!((report.renamed, report.copied, report.deleted), (1, 0, 0));
+        assert_eq!(read(&target, "a.txt"), None);
+        assert_eq!(read(&target, "2024/b.txt").as_deref(), Some("photo notes"));
+
+        // And back the other way
+        fs::rename(target.join("2024/b.txt"), target.join("c.txt")).unwrap();
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!(report.renamed, 1);
+        assert_eq!(read(&source, "2024/b.txt"), None);
+        assert_eq!(read(&source, "c.txt").as_deref(), Some("photo notes"));
+    }
+
+    #[test]
+    fn duplicate_and_empty_files_are_copied_instead_of_renamed() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::TwoWay);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&source, "a.txt", "same");
+        write(&source, "b.txt", "same");
+        write(&source, "empty.txt", "");
+        engine.reconcile_all().unwrap();
+
+        fs::rename(source.join("a.txt"), source.join("c.txt")).unwrap();
+        fs::rename(source.join("b.txt"), source.join("d.txt")).unwrap();
+        fs::rename(source.join("empty.txt"), source.join("still-empty.txt")).unwrap();
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!((report.renamed, report.copied, report.deleted), (0, 3, 3));
+        for gone in ["a.txt", "b.txt", "empty.txt"] {
+            assert_eq!(read(&target, gone), None);
+        }
+        assert_eq!(read(&target, "c.txt").as_deref(), Some("same"));
+        assert_eq!(read(&target, "d.txt").as_deref(), Some("same"));
+        assert_eq!(read(&target, "still-empty.txt").as_deref(), Some(""));
+
+        // A single copy of duplicated content still renames
+        fs::rename(source.join("c.txt"), source.join("e.txt")).unwrap();
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!(report.renamed, 1);
+        assert_eq!(read(&target, "e.txt").as_deref(), Some("same"));
+        assert_eq!(read(&target, "d.txt").as_deref(), Some("same"));
+    }
+
+    #[test]
+    fn deletes_on_either_side_reach_the_other() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::TwoWay);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&source, "from-source.txt", "one");
+        write(&target, "from-target.txt", "two");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&source, "from-target.txt").as_deref(), Some("two"));
+
+        fs::remove_file(source.join("from-target.txt")).unwrap();
+        fs::remove_file(target.join("from-source.txt")).unwrap();
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!(report.deleted, 2);
+        for root in [&source, &target] {
+            assert_eq!(read(root, "from-source.txt"), None);
+            assert_eq!(read(root, "from-target.txt"), None);
+        }
+
+        // Nothing left to do, and the index agrees
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!((report.copied, report.deleted), (0, 0));
+        assert!(engine.index.entries_under("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn edits_on_both_sides_keep_the_older_one_as_a_conflict_copy() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::TwoWay);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&source, "doc.txt", "original");
+        engine.reconcile_all().unwrap();
+
+        write(&source, "doc.txt", "edited in source");
+        write(&target, "doc.txt", "edited in target!");
+        let older = SystemTime::now() - Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(target.join("doc.txt"))
+            .unwrap()
+            .set_modified(older)
+            .unwrap();
+
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!(report.conflicts, vec!["doc.txt".to_string()]);
+        for root in [&source, &target] {
+            assert_eq!(read(root, "doc.txt").as_deref(), Some("edited in source"));
+            let copies = conflict_copies(root);
+            assert_eq!(copies.len(), 1);
+            assert!(copies[0].starts_with("doc.conflict-") && copies[0].ends_with(".txt"));
+            assert_eq!(read(root, &copies[0]).as_deref(), Some("edited in target!"));
+        }
+
+        // Settled: the next pass changes nothing
+        let report = engine.reconcile_all().unwrap();
+        assert!(report.conflicts.is_empty());
+        assert_eq!(report.copied, 0);
+    }
+
+    #[test]
+    fn an_edit_wins_over_a_delete_on_the_other_side() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::TwoWay);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&source, "kept.txt", "v1");
+        write(&source, "restored.txt", "v1");
+        engine.reconcile_all().unwrap();
+
+        write(&source, "kept.txt", "v2 from source");
+        fs::remove_file(target.join("kept.txt")).unwrap();
+        write(&target, "restored.txt", "v2 from target");
+        fs::remove_file(source.join("restored.txt")).unwrap();
+        let report = engine.reconcile_all().unwrap();
+        assert_eq!((report.copied, report.deleted), (2, 0));
+        for root in [&source, &target] {
+            assert_eq!(read(root, "kept.txt").as_deref(), Some("v2 from source"));
+            assert_eq!(read(root, "restored.txt").as_deref(), Some("v2 from target"));
+        }
+    }
+
+    #[test]
+    fn push_copies_source_changes_and_leaves_target_edits_alone() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::Push);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&source, "shared.txt", "v1");
+        write(&source, "gone.txt", "v1");
+        write(&target, "target-only.txt", "mine");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&target, "shared.txt").as_deref(), Some("v1"));
+        assert_eq!(read(&source, "target-only.txt"), None);
+
+        write(&target, "shared.txt", "edited in target");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&target, "shared.txt").as_deref(), Some("edited in target"));
+        assert_eq!(read(&source, "shared.txt").as_deref(), Some("v1"));
+
+        write(&source, "shared.txt", "v2 from source");
+        fs::remove_file(source.join("gone.txt")).unwrap();
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&target, "shared.txt").as_deref(), Some("v2 from source"));
+        assert_eq!(read(&target, "gone.txt"), None);
+        assert_eq!(read(&target, "target-only.txt").as_deref(), Some("mine"));
+    }
+
+    #[test]
+    fn pull_copies_target_changes_and_leaves_source_edits_alone() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::Pull);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&target, "shared.txt", "v1");
+        write(&source, "source-only.txt", "mine");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&source, "shared.txt").as_deref(), Some("v1"));
+        assert_eq!(read(&target, "source-only.txt"), None);
+
+        write(&source, "shared.txt", "edited in source");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&source, "shared.txt").as_deref(), Some("edited in source"));
+        assert_eq!(read(&target, "shared.txt").as_deref(), Some("v1"));
+
+        fs::rename(target.join("shared.txt"), target.join("renamed.txt")).unwrap();
+        write(&target, "renamed.txt", "v2 from target");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&source, "shared.txt"), None);
+        assert_eq!(read(&source, "renamed.txt").as_deref(), Some("v2 from target"));
+        assert_eq!(read(&source, "source-only.txt").as_deref(), Some("mine"));
+    }
+
+    #[test]
+    fn mirror_undoes_target_edits_and_removes_target_only_files() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::Mirror);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        write(&source, "shared.txt", "v1");
+        write(&source, "deleted-in-target.txt", "v1");
+        write(&target, "target-only.txt", "stray");
+        write(&target, "stray-dir/nested.txt", "stray");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&target, "target-only.txt"), None);
+        assert!(!target.join("stray-dir").exists());
+        assert_eq!(read(&source, "target-only.txt"), None);
+
+        write(&target, "shared.txt", "edited in target");
+        fs::remove_file(target.join("deleted-in-target.txt")).unwrap();
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&target, "shared.txt").as_deref(), Some("v1"));
+        assert_eq!(read(&target, "deleted-in-target.txt").as_deref(), Some("v1"));
+    }
+
+    #[test]
+    fn directories_are_created_and_removed_on_the_other_side() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::TwoWay);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        fs::create_dir_all(source.join("albums/empty")).unwrap();
+        fs::create_dir_all(target.join("from-target")).unwrap();
+        engine.reconcile_all().unwrap();
+        assert!(target.join("albums/empty").is_dir());
+        assert!(source.join("from-target").is_dir());
+
+        fs::remove_dir(source.join("albums/empty")).unwrap();
+        fs::remove_dir(target.join("from-target")).unwrap();
+        engine.reconcile_all().unwrap();
+        assert!(!target.join("albums/empty").exists());
+        assert!(target.join("albums").is_dir());
+        assert!(!source.join("from-target").exists());
+
+        // A removed folder stays while the other side still adds to it
+        fs::remove_dir(target.join("albums")).unwrap();
+        write(&source, "albums/new.jpg", "pixels");
+        engine.reconcile_all().unwrap();
+        assert_eq!(read(&target, "albums/new.jpg").as_deref(), Some("pixels"));
+        assert!(source.join("albums").is_dir());
+    }
+
+    #[test]
+    fn push_mirrors_new_and_removed_directories() {
+        let vault = TempVault::new();
+        let engine = engine(&vault, Direction::Push);
+        let (source, target) = (engine.source.clone(), engine.target.clone());
+        fs::create_dir_all(source.join("trip/day1")).unwrap();
+        fs::create_dir_all(target.join("target-folder")).unwrap();
+        engine.reconcile_all().unwrap();
+        assert!(target.join("trip/day1").is_dir());
+        assert!(target.join("target-folder").is_dir());
+        assert!(!source.join("target-folder").exists());
+
+        fs::remove_dir_all(source.join("trip")).unwrap();
+        engine.reconcile_all().unwrap();
+        assert!(!target.join("trip").exists());
+        assert!(target.join("target-folder").is_dir());
+    }
+}
@@ -0,0 +1,155 @@
+use rusqlite::{params, Connection};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::db;
+
+/// Last synced state of a path, identical on both sides at the time it
+/// was recorded. Each side keeps its own mtime since copies get a fresh one.
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub size: u64,
+    pub hash: String,
+    pub source_mtime: i64,
+    pub target_mtime: i64,
+}
+
+/// Per folder-pair view of the `sync_index` table.
+pub struct SyncIndex {
+    pair_id: String,
+    conn: Connection,
+}
+
+impl SyncIndex {
+    pub fn open(pair_id: &str) -> Result<Self, String> {
+        Ok(Self {
+            pair_id: pair_id.to_string(),
+            conn: db::get_connection().map_err(|e| e.to_string())?,
+        })
+    }
+
+    /// Every indexed file equal to or below `prefix` ("" for all).
+    pub fn entries_under(&self, prefix: &str) -> Result<HashMap<String, IndexEntry>, String> {
+        let pattern = like_pattern(prefix);
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT path, size, hash, source_mtime, target_mtime FROM sync_index
+                 WHERE pair_id = ?1 AND kind = 'file' AND (path = ?2 OR path LIKE ?3 ESCAPE '\\')",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let rows = stmt
+            .query_map(params![self.pair_id, prefix, pattern], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    IndexEntry {
+                        size: row.get::<_, i64>(1)? as u64,
+                        hash: row.get(2)?,
+                        source_mtime: row.get(3)?,
+                        target_mtime: row.get(4)?,
+                    },
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<HashMap<_, _>, _>>()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Every indexed directory equal to or below `prefix`.
+    pub fn dirs_under(&self, prefix: &str) -> Result<BTreeSet<String>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT path FROM sync_index
+                 WHERE pair_id = ?1 AND kind = 'dir' AND (path = ?2 OR path LIKE ?3 ESCAPE '\\')",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let rows = stmt
+            .query_map(params![self.pair_id, prefix, like_pattern(prefix)], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<BTreeSet<_>, _>>()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Record the synced state of a path, bumping its version.
+    pub fn record(&self, path: &str, size: u64, hash: &str, source_mtime: i64, target_mtime: i64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO sync_index (pair_id, path, kind, size, hash, source_mtime, target_mtime, version)
+                 VALUES (?1, ?2, 'file', ?3, ?4, ?5, ?6, 1)
+                 ON CONFLICT(pair_id, path) DO UPDATE SET
+                    kind = 'file',
+                    size = excluded.size,
+                    hash = excluded.hash,
+                    source_mtime = excluded.source_mtime,
+                    target_mtime = excluded.target_mtime,
+                    version = version + 1",
+                params![self.pair_id, path, size as i64, hash, source_mtime, target_mtime],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Record that a directory exists on both sides.
+    pub fn record_dir(&self, path: &str) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO sync_index (pair_id, path, kind, size, hash, source_mtime, target_mtime)
+                 VALUES (?1, ?2, 'dir', 0, '', 0, 0)",
+                params![self.pair_id, path],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &str) -> Result<(), String> {
+        self.conn
+            .execute(
+                "DELETE FROM sync_index WHERE pair_id = ?1 AND path = ?2",
+                params![self.pair_id, path],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Move an entry to a new path after a rename, keeping its history.
+    pub fn rename(&self, from: &str, to: &str, source_mtime: i64, target_mtime: i64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "DELETE FROM sync_index WHERE pair_id = ?1 AND path = ?2",
+                params![self.pair_id, to],
+            )
+            .map_err(|e| e.to_string())?;
+        self.conn
+            .execute(
+                "UPDATE sync_index SET path = ?3, source_mtime = ?4, target_mtime = ?5, version = version + 1
+                 WHERE pair_id = ?1 AND path = ?2",
+                params![self.pair_id, from, to, source_mtime, target_mtime],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn record_conflict(&self, path: &str, conflict_path: &str) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO sync_conflicts (pair_id, path, conflict_path, detected_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![self.pair_id, path, conflict_path, chrono::Utc::now().to_rfc3339()],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// LIKE pattern matching everything below `prefix`.
+fn like_pattern(prefix: &str) -> String {
+    if prefix.is_empty() {
+        "%".to_string()
+    } else {
+        format!("{}/%", prefix.replace('%', "\\%").replace('_', "\\_"))
+    }
+}
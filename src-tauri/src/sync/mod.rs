@@ -1,88 +1,394 @@
-use notify::{RecommendedWatcher, RecursiveMode, Result as NotifyResult, Watcher, Event};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::path::{Path, PathBuf};
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::time::Duration;
-use std::fs;
-use std::sync::mpsc::{channel, Sender, Receiver};
+use tauri::AppHandle;
 
+mod debounce;
+mod engine;
+mod index;
+pub mod profiles;
+pub mod status;
+
+use debounce::Debouncer;
+use engine::{EngineOptions, SyncEngine, SyncReport};
+use profiles::{canonical_root, overlaps, PathFilter, SyncProfile};
+use status::{SyncEvent, SyncPhase, SyncStatus, SyncTracker};
+use crate::syncthing::DeviceCompletion;
+use crate::vault;
+
+/// Messages to the sync worker thread.
+enum Message {
+    Event(Event),
+    Stop,
+}
+
+/// Runs one sync profile: a watcher-driven worker, or a timer for
+/// scheduled profiles.
 pub struct SyncManager {
+    profile: SyncProfile,
     watcher: Option<RecommendedWatcher>,
-    watch_path: PathBuf,
-    sync_target_path: PathBuf,
-    running: Arc<Mutex<bool>>,
+    control: Option<Sender<Message>>,
+    worker: Option<JoinHandle<()>>,
+    tracker: Arc<SyncTracker>,
 }
 
 impl SyncManager {
-    pub fn new(watch_path: impl AsRef<Path>, sync_target_path: impl AsRef<Path>) -> Self {
+    /// `app` receives progress events; without it sync runs silently.
+    pub fn new(app: Option<AppHandle>, profile: SyncProfile) -> Self {
+        let tracker = SyncTracker::new(app, &profile);
         Self {
+            profile,
             watcher: None,
-            watch_path: watch_path.as_ref().to_path_buf(),
-            sync_target_path: sync_target_path.as_ref().to_path_buf(),
-            running: Arc::new(Mutex::new(false)),
+            control: None,
+            worker: None,
+            tracker: Arc::new(tracker),
         }
     }
 
-    pub fn start_sync(&mut self) -> NotifyResult<()> {
-        let (tx, rx): (Sender<Event>, Receiver<Event>) = channel();
-        let watch_path = self.watch_path.clone();
-        let sync_target_path = self.sync_target_path.clone();
-        let running = self.running.clone();
+    pub fn status(&self) -> SyncStatus {
+        self.tracker.status()
+    }
 
-        // Mark running true
-        {
-            let mut r = running.lock().unwrap();
-            *r = true;
+    /// Catch up on changes made on either side while sync was stopped,
+    /// then keep both folders in step from watcher events or on schedule.
+    pub fn start_sync(&mut self) -> Result<(), String> {
+        let options = EngineOptions {
+            direction: self.profile.direction,
+            filter: PathFilter::new(&self.profile.include, &self.profile.exclude)?,
+            bandwidth_limit: self.profile.bandwidth_limit,
+        };
+        let engine = SyncEngine::new(
+            Path::new(&self.profile.source),
+            Path::new(&self.profile.target),
+            options,
+            self.tracker.clone(),
+        )?;
+        // Copies interrupted by a crash never replaced their destination;
+        // only the temp files are left to clear. Recent ones may still be
+        // written by a profile sharing a folder that is stopping.
+        let (source, target) = engine.roots();
+        for root in [source, target] {
+            vault::cleanup_temp_files_older_than(root, vault::TEMP_GRACE).map_err(|e| e.to_string())?;
         }
+        if let Err(e) = full_pass(&engine, &self.tracker) {
+            self.tracker.set_state(SyncPhase::Stopped);
+            return Err(e);
+        }
+
+        let (tx, rx): (Sender<Message>, Receiver<Message>) = channel();
+        let tracker = self.tracker.clone();
 
-        // Create watcher
-        let mut watcher: RecommendedWatcher = Watcher::new_immediate(move |res| {
+        if let Some(minutes) = self.profile.schedule_minutes {
+            let interval = Duration::from_secs(u64::from(minutes) * 60);
+            self.control = Some(tx);
+            self.worker = Some(thread::spawn(move || run_scheduled(engine, tracker, rx, interval)));
+            return Ok(());
+        }
+
+        // Watch both sides; the index tells our own copies apart from user edits
+        let events = tx.clone();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
             if let Ok(event) = res {
-                tx.send(event).unwrap();
+                let _ = events.send(Message::Event(event));
             }
-        })?;
+        })
+        .map_err(|e| e.to_string())?;
 
-        watcher.watch(&watch_path, RecursiveMode::Recursive)?;
+        let (source, target) = engine.roots();
+        watcher.watch(source, RecursiveMode::Recursive).map_err(|e| e.to_string())?;
+        watcher.watch(target, RecursiveMode::Recursive).map_err(|e| e.to_string())?;
 
         self.watcher = Some(watcher);
+        self.control = Some(tx);
+        self.worker = Some(thread::spawn(move || run_worker(engine, tracker, rx)));
+
+        Ok(())
+    }
+
+    /// Stop watching and wait for the worker to finish its current batch.
+    pub fn stop_sync(&mut self) {
+        self.watcher = None; // drop watcher to stop watching
+        if let Some(control) = self.control.take() {
+            let _ = control.send(Message::Stop);
+        }
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                self.tracker.fail_batch("Sync worker stopped unexpectedly");
+            }
+        }
+        self.tracker.set_state(SyncPhase::Stopped);
+    }
+}
+
+/// Managed Tauri state: the running managers by profile id, and the
+/// devices Syncthing shares the vault with by Syncthing device id.
+#[derive(Default)]
+pub struct SyncRunner {
+    running: Mutex<Running>,
+    syncthing: Mutex<HashMap<String, SyncthingDevice>>,
+}
+
+#[derive(Default)]
+struct Running {
+    managers: HashMap<String, SyncManager>,
+    /// Profiles in their first pass, holding their folders until started.
+    starting: HashMap<String, SyncProfile>,
+}
+
+impl Running {
+    fn profiles(&self) -> impl Iterator<Item = &SyncProfile> {
+        self.managers.values().map(|m| &m.profile).chain(self.starting.values())
+    }
+}
+
+struct SyncthingDevice {
+    tracker: SyncTracker,
+    /// Last error reported, so a lasting one is logged once.
+    error: Option<String>,
+}
+
+impl SyncRunner {
+    /// Start a profile and mark it to be resumed at the next launch.
+    pub fn start(&self, app: AppHandle, profile_id: &str) -> Result<(), String> {
+        let profile = profiles::get_profile(profile_id)?;
+        {
+            let mut running = self.running.lock().unwrap();
+            check_free(&running, &profile)?;
+            running.starting.insert(profile_id.to_string(), profile.clone());
+        }
+
+        // The first pass can take a while; don't hold the lock through it.
+        let mut manager = SyncManager::new(Some(app), profile);
+        let started = manager.start_sync();
+
+        let mut running = self.running.lock().unwrap();
+        running.starting.remove(profile_id);
+        started?;
+        running.managers.insert(profile_id.to_string(), manager);
+        drop(running);
+        profiles::set_enabled(profile_id, true)
+    }
+
+    pub fn stop(&self, profile_id: &str) -> Result<(), String> {
+        let manager = self.running.lock().unwrap().managers.remove(profile_id);
+        match manager {
+            Some(mut manager) => {
+                manager.stop_sync();
+                profiles::set_enabled(profile_id, false)
+            }
+            None => Err("Sync is not running".into()),
+        }
+    }
+
+    /// Stop every running profile, returning how many there were.
+    pub fn stop_all(&self) -> Result<usize, String> {
+        let managers: Vec<(String, SyncManager)> = self.running.lock().unwrap().managers.drain().collect();
+        let count = managers.len();
+        for (profile_id, mut manager) in managers {
+            manager.stop_sync();
+            profiles::set_enabled(&profile_id, false)?;
+        }
+        Ok(count)
+    }
 
-        // Spawn thread to handle events
-        thread::spawn(move || {
-            while *running.lock().unwrap() {
-                if let Ok(event) = rx.recv_timeout(Duration::from_secs(1)) {
-                    // For simplicity, sync any created or modified files
-                    for path in event.paths {
-                        if path.is_file() {
-                            if let Err(e) = SyncManager::sync_file(&path, &sync_target_path) {
-                                eprintln!("Sync error: {}", e);
-                            }
-                        }
+    pub fn is_running(&self, profile_id: &str) -> bool {
+        self.running.lock().unwrap().managers.contains_key(profile_id)
+    }
+
+    pub fn statuses(&self) -> Vec<SyncStatus> {
+        let mut statuses: Vec<SyncStatus> = self.running.lock().unwrap().managers.values().map(|m| m.status()).collect();
+        statuses.extend(self.syncthing.lock().unwrap().values().map(|d| d.tracker.status()));
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    /// Take in Syncthing's progress for each device sharing the vault
+    /// folder `folder`, dropping devices no longer shared.
+    pub fn update_syncthing(&self, app: &AppHandle, folder: &str, completions: &[DeviceCompletion]) {
+        let mut devices = self.syncthing.lock().unwrap();
+        devices.retain(|id, _| completions.iter().any(|c| c.device_id == *id));
+        for completion in completions {
+            let device = devices
+                .entry(completion.device_id.clone())
+                .or_insert_with(|| SyncthingDevice {
+                    tracker: SyncTracker::for_syncthing_device(Some(app.clone()), &completion.device_id, folder),
+                    error: None,
+                });
+            device.tracker.set_pending(completion.need_items as usize);
+            match &completion.error {
+                Some(error) => {
+                    if device.error.as_ref() != Some(error) {
+                        device.tracker.fail_batch(error);
                     }
                 }
+                None if completion.need_items == 0 && completion.completion >= 100.0 => {
+                    device.tracker.finish_batch(None, 0);
+                }
+                None => device.tracker.set_state(SyncPhase::Syncing),
             }
-            println!("Sync thread stopped");
-        });
+            device.error = completion.error.clone();
+        }
+    }
+
+    /// Syncthing could not be asked for progress. Devices it shares the
+    /// vault with show the error, logged once while it lasts.
+    pub fn syncthing_failed(&self, error: &str) {
+        for device in self.syncthing.lock().unwrap().values_mut() {
+            if device.error.as_deref() != Some(error) {
+                device.tracker.fail_batch(error);
+                device.error = Some(error.to_string());
+            }
+        }
+    }
 
+    /// Restart every profile that was running when the app last quit.
+    /// A profile whose folder is missing (e.g. an unplugged drive) stays
+    /// enabled so it resumes once the folder is back.
+    pub fn resume_enabled(&self, app: &AppHandle) -> Result<(), String> {
+        for profile in profiles::list_profiles()?.into_iter().filter(|p| p.enabled) {
+            if let Err(e) = self.start(app.clone(), &profile.id) {
+                status::log_error(Some(&profile.id), &format!("Could not resume sync: {}", e));
+            }
+        }
         Ok(())
     }
+}
 
-    pub fn stop_sync(&mut self) {
-        let mut r = self.running.lock().unwrap();
-        *r = false;
-        self.watcher = None; // drop watcher to stop watching
+/// Two managers sharing a folder, whichever side it is on or nested in
+/// another, would fight over its files and clear each other's temp files.
+fn check_free(running: &Running, profile: &SyncProfile) -> Result<(), String> {
+    if running.managers.contains_key(&profile.id) || running.starting.contains_key(&profile.id) {
+        return Err("Sync already running".into());
+    }
+    let roots = [canonical_root(&profile.source), canonical_root(&profile.target)];
+    if let Some(other) = running.profiles().find(|other| {
+        [canonical_root(&other.source), canonical_root(&other.target)]
+            .iter()
+            .any(|theirs| roots.iter().any(|ours| overlaps(ours, theirs)))
+    }) {
+        return Err(format!("Profile '{}' already syncs one of these folders", other.name));
     }
+    Ok(())
+}
 
-    fn sync_file(file_path: &Path, target_dir: &Path) -> std::io::Result<()> {
-        if !target_dir.exists() {
-            fs::create_dir_all(target_dir)?;
+fn full_pass(engine: &SyncEngine, tracker: &SyncTracker) -> Result<(), String> {
+    tracker.set_state(SyncPhase::Scanning);
+    match engine.reconcile_all() {
+        Ok(report) => {
+            tracker.finish_batch(summary(&report), report.failed);
+            Ok(())
+        }
+        Err(e) => {
+            tracker.fail_batch(&e);
+            Err(e)
         }
-        let file_name = file_path.file_name().ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::Other, "Invalid file name")
-        })?;
-        let target_path = target_dir.join(file_name);
+    }
+}
 
-        fs::copy(file_path, target_path)?;
-        println!("Synced file: {:?}", file_path);
-        Ok(())
+/// Compare both folders in full every `interval` until stopped.
+fn run_scheduled(engine: SyncEngine, tracker: Arc<SyncTracker>, rx: Receiver<Message>, interval: Duration) {
+    loop {
+        match rx.recv_timeout(interval) {
+            Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+            Ok(Message::Event(_)) => continue,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        // Failures are logged; the next pass tries again.
+        let _ = full_pass(&engine, &tracker);
+    }
+}
+
+/// Collect watcher events until paths settle, then reconcile every ready
+/// path in one batch so both halves of a rename are seen together.
+/// Blocks while idle instead of polling.
+fn run_worker(engine: SyncEngine, tracker: Arc<SyncTracker>, rx: Receiver<Message>) {
+    let mut debouncer = Debouncer::default();
+    loop {
+        let message = match debouncer.next_timeout() {
+            Some(timeout) => rx.recv_timeout(timeout),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match message {
+            Ok(Message::Event(event)) => debouncer.push(event.paths),
+            Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+
+        let ready = debouncer.take_ready();
+        tracker.set_pending(debouncer.pending_count() + ready.len());
+        if ready.is_empty() {
+            continue;
+        }
+
+        tracker.set_state(SyncPhase::Syncing);
+        for path in &ready {
+            tracker.record(SyncEvent::Queued {
+                path: path.to_string_lossy().into_owned(),
+            });
+        }
+        match engine.reconcile_paths(&ready) {
+            Ok(report) => tracker.finish_batch(summary(&report), report.failed),
+            Err(e) => tracker.fail_batch(&e),
+        }
+        tracker.set_pending(debouncer.pending_count());
+    }
+}
+
+/// One-line description of a batch for the sync log, if it did anything.
+fn summary(report: &SyncReport) -> Option<String> {
+    if report.copied + report.deleted + report.renamed + report.conflicts.len() + report.failed == 0 {
+        return None;
+    }
+    Some(format!(
+        "{} copied, {} deleted, {} renamed, {} conflicts, {} failed",
+        report.copied,
+        report.deleted,
+        report.renamed,
+        report.conflicts.len(),
+        report.failed
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::profiles::Direction;
+
+    fn profile(id: &str, source: &str, target: &str) -> SyncProfile {
+        SyncProfile {
+            id: id.into(),
+            name: id.into(),
+            source: source.into(),
+            target: target.into(),
+            direction: Direction::TwoWay,
+            schedule_minutes: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            bandwidth_limit: None,
+            enabled: true,
+            created_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn profiles_may_not_share_or_nest_folders() {
+        let root = std::env::temp_dir().join(format!("aethersync-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("photos")).unwrap();
+        let path = |rel: &str| root.join(rel).to_string_lossy().into_owned();
+        let mut running = Running::default();
+        running
+            .starting
+            .insert("one".into(), profile("one", &path("photos"), &path("backup")));
+
+        assert!(check_free(&running, &profile("one", &path("x"), &path("y"))).is_err());
+        assert!(check_free(&running, &profile("two", &path("docs"), &path("docs-backup"))).is_ok());
+        assert!(check_free(&running, &profile("two", &path("photos/./"), &path("elsewhere"))).is_err());
+        assert!(check_free(&running, &profile("two", &path("docs"), &path("backup/docs"))).is_err());
+        assert!(check_free(&running, &profile("two", &root.to_string_lossy(), &path("docs"))).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
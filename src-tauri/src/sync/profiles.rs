@@ -0,0 +1,336 @@
+use chrono::Utc;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::db;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Copy source changes to the target; target edits are left alone.
+    Push,
+    /// Copy target changes to the source.
+    Pull,
+    #[default]
+    TwoWay,
+    /// Keep the target an exact copy of the source, undoing target edits.
+    Mirror,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Push => "push",
+            Direction::Pull => "pull",
+            Direction::TwoWay => "two_way",
+            Direction::Mirror => "mirror",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "push" => Some(Direction::Push),
+            "pull" => Some(Direction::Pull),
+            "two_way" => Some(Direction::TwoWay),
+            "mirror" => Some(Direction::Mirror),
+            _ => None,
+        }
+    }
+}
+
+/// A named folder pair and how to keep it in sync.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncProfile {
+    pub id: String,
+    pub name: String,
+    pub source: String,
+    pub target: String,
+    pub direction: Direction,
+    /// Minutes between full passes; `None` syncs continuously on changes.
+    pub schedule_minutes: Option<u32>,
+    /// Globs relative to the folder root; empty means every file.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Copy speed cap in bytes per second.
+    pub bandwidth_limit: Option<u64>,
+    /// Running, and resumed when the app starts.
+    pub enabled: bool,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileInput {
+    pub name: String,
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub direction: Direction,
+    pub schedule_minutes: Option<u32>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub bandwidth_limit: Option<u64>,
+}
+
+const PROFILE_COLUMNS: &str =
+    "id, name, source, target, direction, schedule_minutes, include, exclude, bandwidth_limit, enabled, created_at";
+
+pub fn create_profile(input: ProfileInput) -> Result<SyncProfile, String> {
+    validate(&input)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let id = Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO sync_profiles (id, name, source, target, direction, schedule_minutes, include, exclude,
+                                    bandwidth_limit, enabled, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, 0, ?10)",
+        params![
+            id,
+            input.name.trim(),
+            input.source,
+            input.target,
+            input.direction.as_str(),
+            input.schedule_minutes,
+            serde_json::to_string(&input.include).map_err(|e| e.to_string())?,
+            serde_json::to_string(&input.exclude).map_err(|e| e.to_string())?,
+            input.bandwidth_limit.map(|b| b as i64),
+            Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    get_profile(&id)
+}
+
+/// Change a profile's settings. A running profile picks them up when it
+/// is restarted.
+pub fn update_profile(id: &str, input: ProfileInput) -> Result<SyncProfile, String> {
+    validate(&input)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let updated = conn
+        .execute(
+            "UPDATE sync_profiles SET name = ?1, source = ?2, target = ?3, direction = ?4, schedule_minutes = ?5,
+                    include = ?6, exclude = ?7, bandwidth_limit = ?8
+             WHERE id = ?9",
+            params![
+                input.name.trim(),
+                input.source,
+                input.target,
+                input.direction.as_str(),
+                input.schedule_minutes,
+                serde_json::to_string(&input.include).map_err(|e| e.to_string())?,
+                serde_json::to_string(&input.exclude).map_err(|e| e.to_string())?,
+                input.bandwidth_limit.map(|b| b as i64),
+                id
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err("Sync profile not found".into());
+    }
+    get_profile(id)
+}
+
+pub fn delete_profile(id: &str) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let deleted = conn
+        .execute("DELETE FROM sync_profiles WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    if deleted == 0 {
+        return Err("Sync profile not found".into());
+    }
+    Ok(())
+}
+
+pub fn set_enabled(id: &str, enabled: bool) -> Result<(), String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE sync_profiles SET enabled = ?1 WHERE id = ?2",
+        params![enabled, id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn get_profile(id: &str) -> Result<SyncProfile, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        &format!("SELECT {} FROM sync_profiles WHERE id = ?1", PROFILE_COLUMNS),
+        params![id],
+        row_to_profile,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "Sync profile not found".to_string())
+}
+
+/// The profile syncing exactly these two folders, if one exists.
+pub fn find_profile(source: &str, target: &str) -> Result<Option<SyncProfile>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.query_row(
+        &format!("SELECT {} FROM sync_profiles WHERE source = ?1 AND target = ?2", PROFILE_COLUMNS),
+        params![source, target],
+        row_to_profile,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn list_profiles() -> Result<Vec<SyncProfile>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM sync_profiles ORDER BY name", PROFILE_COLUMNS))
+        .map_err(|e| e.to_string())?;
+
+    let profiles = stmt
+        .query_map([], row_to_profile)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(profiles)
+}
+
+/// Which relative paths a profile syncs. Excludes take precedence over
+/// includes; directories are only subject to excludes so included files
+/// keep their folders.
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            include: if include.is_empty() { None } else { Some(build_set(include)?) },
+            exclude: build_set(exclude)?,
+        })
+    }
+
+    pub fn includes_file(&self, path: &str) -> bool {
+        !self.exclude.is_match(path) && self.include.as_ref().map_or(true, |set| set.is_match(path))
+    }
+
+    pub fn includes_dir(&self, path: &str) -> bool {
+        !self.exclude.is_match(path)
+    }
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: GlobSet::empty(),
+        }
+    }
+}
+
+fn build_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+fn validate(input: &ProfileInput) -> Result<(), String> {
+    if input.name.trim().is_empty() {
+        return Err("Profile name is required".into());
+    }
+    if input.source.trim().is_empty() || input.target.trim().is_empty() {
+        return Err("Both folders are required".into());
+    }
+    if overlaps(&canonical_root(&input.source), &canonical_root(&input.target)) {
+        return Err("Source and target must be different folders, neither inside the other".into());
+    }
+    if input.schedule_minutes == Some(0) {
+        return Err("Schedule must be at least one minute".into());
+    }
+    if input.bandwidth_limit == Some(0) {
+        return Err("Bandwidth limit must be positive".into());
+    }
+    PathFilter::new(&input.include, &input.exclude).map(|_| ())
+}
+
+/// `path` made absolute with symlinks resolved as far as it exists, so
+/// two spellings of one folder compare equal even before it is created.
+pub fn canonical_root(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing.iter().rev().fold(canonical, |root, part| root.join(part));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// The same folder, or one inside the other.
+pub fn overlaps(a: &Path, b: &Path) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+fn row_to_profile(row: &rusqlite::Row) -> rusqlite::Result<SyncProfile> {
+    let direction: String = row.get(4)?;
+    let include: String = row.get(6)?;
+    let exclude: String = row.get(7)?;
+    Ok(SyncProfile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        source: row.get(2)?,
+        target: row.get(3)?,
+        direction: Direction::parse(&direction).unwrap_or_default(),
+        schedule_minutes: row.get(5)?,
+        include: serde_json::from_str(&include).unwrap_or_default(),
+        exclude: serde_json::from_str(&exclude).unwrap_or_default(),
+        bandwidth_limit: row.get::<_, Option<i64>>(8)?.map(|b| b as u64),
+        enabled: row.get(9)?,
+        created_at: row.get(10)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(source: &Path, target: &Path) -> ProfileInput {
+        ProfileInput {
+            name: "Photos".into(),
+            source: source.to_string_lossy().into_owned(),
+            target: target.to_string_lossy().into_owned(),
+            direction: Direction::TwoWay,
+            schedule_minutes: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            bandwidth_limit: None,
+        }
+    }
+
+    #[test]
+    fn folders_must_not_overlap_however_they_are_spelled() {
+        let root = std::env::temp_dir().join(format!("aethersync-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("photos")).unwrap();
+
+        assert!(validate(&input(&root.join("photos"), &root.join("backup"))).is_ok());
+        assert!(validate(&input(&root.join("photos"), &root.join("photos/../photos/"))).is_err());
+        assert!(validate(&input(&root.join("photos"), &root.join("photos/backup"))).is_err());
+        // Neither exists yet
+        assert!(validate(&input(&root.join("new/a"), &root.join("new"))).is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("photos"), root.join("link")).unwrap();
+            assert!(validate(&input(&root.join("link/sub"), &root.join("photos"))).is_err());
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
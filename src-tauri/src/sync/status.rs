@@ -0,0 +1,355 @@
+use chrono::Utc;
+use rusqlite::params;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+use super::profiles::SyncProfile;
+use crate::db;
+
+/// Event emitted to the frontend for every file the sync touches.
+pub const SYNC_EVENT: &str = "sync-progress";
+
+/// Throughput is averaged over this much recent activity.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(30);
+const RECENT_ERRORS: usize = 20;
+/// Rows kept in `sync_log`; older ones are pruned as new ones arrive.
+const LOG_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SyncEvent {
+    Queued { path: String },
+    Transferred { path: String, bytes: u64 },
+    Deleted { path: String },
+    Renamed { from: String, to: String },
+    Failed { path: String, error: String },
+    Conflict { path: String, conflict_path: String },
+}
+
+/// What the frontend receives: the event and the profile it belongs to.
+#[derive(Serialize)]
+struct EventPayload<'a> {
+    profile_id: &'a str,
+    #[serde(flatten)]
+    event: &'a SyncEvent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncPhase {
+    /// Comparing both folders in full.
+    Scanning,
+    /// Applying a batch of changes.
+    Syncing,
+    /// Watching for changes.
+    Idle,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncLogEntry {
+    pub id: i64,
+    pub profile_id: Option<String>,
+    pub level: String, // info, warning, error
+    pub message: String,
+    pub path: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    pub profile_id: String,
+    pub name: String,
+    pub state: SyncPhase,
+    pub source: String,
+    pub target: String,
+    pub last_success_at: Option<String>,
+    /// Changed paths waiting for their batch.
+    pub pending: usize,
+    pub bytes_per_second: f64,
+    pub recent_errors: Vec<SyncLogEntry>,
+}
+
+struct Inner {
+    state: SyncPhase,
+    last_success_at: Option<String>,
+    pending: usize,
+    transfers: VecDeque<(Instant, u64)>,
+    recent_errors: VecDeque<SyncLogEntry>,
+}
+
+/// Live state of one sync profile, shared by the worker that updates it
+/// and the commands that read it. Each event is also forwarded to the
+/// frontend; problems and batch summaries are written to `sync_log`.
+pub struct SyncTracker {
+    app: Option<AppHandle>,
+    profile_id: String,
+    name: String,
+    source: String,
+    target: String,
+    inner: Mutex<Inner>,
+}
+
+impl SyncTracker {
+    pub fn new(app: Option<AppHandle>, profile: &SyncProfile) -> Self {
+        Self::build(app, &profile.id, &profile.name, &profile.source, &profile.target)
+    }
+
+    /// Tracker for a device Syncthing shares `folder` with. Such devices
+    /// have no sync profile; their status comes from Syncthing's reports.
+    pub fn for_syncthing_device(app: Option<AppHandle>, device_id: &str, folder: &str) -> Self {
+        let short_id = device_id.split('-').next().unwrap_or(device_id);
+        Self::build(
+            app,
+            &format!("syncthing:{}", device_id),
+            &format!("Syncthing {}", short_id),
+            folder,
+            device_id,
+        )
+    }
+
+    fn build(app: Option<AppHandle>, profile_id: &str, name: &str, source: &str, target: &str) -> Self {
+        Self {
+            app,
+            profile_id: profile_id.to_string(),
+            name: name.to_string(),
+            source: source.to_string(),
+            target: target.to_string(),
+            inner: Mutex::new(Inner {
+                state: SyncPhase::Stopped,
+                last_success_at: None,
+                pending: 0,
+                transfers: VecDeque::new(),
+                recent_errors: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn set_state(&self, state: SyncPhase) {
+        self.inner.lock().unwrap().state = state;
+    }
+
+    pub fn set_pending(&self, pending: usize) {
+        self.inner.lock().unwrap().pending = pending;
+    }
+
+    pub fn record(&self, event: SyncEvent) {
+        match &event {
+            SyncEvent::Transferred { bytes, .. } => {
+                self.inner.lock().unwrap().transfers.push_back((Instant::now(), *bytes));
+            }
+            SyncEvent::Failed { path, error } => self.log("error", error, Some(path)),
+            SyncEvent::Conflict { path, conflict_path } => self.log(
+                "warning",
+                &format!("Edited on both sides; other version kept as {}", conflict_path),
+                Some(path),
+            ),
+            _ => {}
+        }
+
+        if let Some(app) = &self.app {
+            let payload = EventPayload {
+                profile_id: &self.profile_id,
+                event: &event,
+            };
+            let _ = app.emit(SYNC_EVENT, &payload);
+        }
+    }
+
+    /// Close a batch: note the summary and, if nothing failed, the time
+    /// of the last successful sync.
+    pub fn finish_batch(&self, summary: Option<String>, failed: usize) {
+        if let Some(summary) = summary {
+            self.log("info", &summary, None);
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if failed == 0 {
+            inner.last_success_at = Some(Utc::now().to_rfc3339());
+        }
+        inner.state = SyncPhase::Idle;
+    }
+
+    /// A whole batch could not run, e.g. a folder became unreachable.
+    pub fn fail_batch(&self, error: &str) {
+        self.log("error", error, None);
+        self.inner.lock().unwrap().state = SyncPhase::Idle;
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        while inner
+            .transfers
+            .front()
+            .map_or(false, |(at, _)| now.duration_since(*at) > THROUGHPUT_WINDOW)
+        {
+            inner.transfers.pop_front();
+        }
+        let bytes: u64 = inner.transfers.iter().map(|(_, b)| b).sum();
+
+        SyncStatus {
+            profile_id: self.profile_id.clone(),
+            name: self.name.clone(),
+            state: inner.state,
+            source: self.source.clone(),
+            target: self.target.clone(),
+            last_success_at: inner.last_success_at.clone(),
+            pending: inner.pending,
+            bytes_per_second: bytes as f64 / THROUGHPUT_WINDOW.as_secs_f64(),
+            recent_errors: inner.recent_errors.iter().cloned().collect(),
+        }
+    }
+
+    fn log(&self, level: &str, message: &str, path: Option<&str>) {
+        let entry = match write_log(Some(&self.profile_id), level, message, path) {
+            Ok(_) if level != "error" => return,
+            Ok(entry) => entry,
+            // Missing from the log, but the profile's status still shows it.
+            Err(e) => SyncLogEntry {
+                id: 0,
+                profile_id: Some(self.profile_id.clone()),
+                level: "error".into(),
+                message: format!("{} (not logged: {})", message, e),
+                path: path.map(str::to_string),
+                created_at: Utc::now().to_rfc3339(),
+            },
+        };
+        let mut inner = self.inner.lock().unwrap();
+        inner.recent_errors.push_back(entry);
+        if inner.recent_errors.len() > RECENT_ERRORS {
+            inner.recent_errors.pop_front();
+        }
+    }
+}
+
+/// Log an error outside a running sync, e.g. a profile failing to resume
+/// or a problem at startup (`profile_id` is `None` for the whole vault).
+pub fn log_error(profile_id: Option<&str>, message: &str) {
+    // With the database failing too there is nowhere left to record it.
+    let _ = write_log(profile_id, "error", message, None);
+}
+
+fn write_log(profile_id: Option<&str>, level: &str, message: &str, path: Option<&str>) -> Result<SyncLogEntry, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let created_at = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO sync_log (profile_id, level, message, path, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![profile_id, level, message, path, created_at],
+    )
+    .map_err(|e| e.to_string())?;
+    let id = conn.last_insert_rowid();
+    conn.execute("DELETE FROM sync_log WHERE id <= ?1", params![id - LOG_LIMIT])
+        .map_err(|e| e.to_string())?;
+
+    Ok(SyncLogEntry {
+        id,
+        profile_id: profile_id.map(str::to_string),
+        level: level.to_string(),
+        message: message.to_string(),
+        path: path.map(str::to_string),
+        created_at,
+    })
+}
+
+/// Most recent sync log entries, newest first, optionally for one profile.
+pub fn recent_log(profile_id: Option<&str>, limit: usize) -> Result<Vec<SyncLogEntry>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, profile_id, level, message, path, created_at FROM sync_log
+             WHERE ?1 IS NULL OR profile_id = ?1
+             ORDER BY id DESC LIMIT ?2",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let entries = stmt
+        .query_map(params![profile_id, limit as i64], |row| {
+            Ok(SyncLogEntry {
+                id: row.get(0)?,
+                profile_id: row.get(1)?,
+                level: row.get(2)?,
+                message: row.get(3)?,
+                path: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+
+    fn tracker() -> SyncTracker {
+        SyncTracker::build(None, "p1", "Photos", "/a", "/b")
+    }
+
+    #[test]
+    fn throughput_only_counts_recent_transfers() {
+        let tracker = tracker();
+        let old = Instant::now()
+            .checked_sub(THROUGHPUT_WINDOW + Duration::from_secs(1))
+            .unwrap();
+        tracker.inner.lock().unwrap().transfers.push_back((old, 1_000_000));
+        tracker.record(SyncEvent::Transferred {
+            path: "a.jpg".into(),
+            bytes: 3000,
+        });
+
+        let status = tracker.status();
+        assert_eq!(status.bytes_per_second, 3000.0 / THROUGHPUT_WINDOW.as_secs_f64());
+        assert_eq!(tracker.inner.lock().unwrap().transfers.len(), 1);
+    }
+
+    #[test]
+    fn keeps_only_the_most_recent_errors() {
+        let _vault = TempVault::new();
+        let tracker = tracker();
+        for i in 0..RECENT_ERRORS + 5 {
+            tracker.record(SyncEvent::Failed {
+                path: format!("{}.jpg", i),
+                error: "locked".into(),
+            });
+        }
+        tracker.finish_batch(Some("1 copied".into()), 0);
+
+        let errors = tracker.status().recent_errors;
+        assert_eq!(errors.len(), RECENT_ERRORS);
+        assert_eq!(errors[0].path.as_deref(), Some("5.jpg"));
+        assert!(errors.iter().all(|e| e.level == "error"));
+        // Everything is in the log, newest first
+        let log = recent_log(Some("p1"), 100).unwrap();
+        assert_eq!(log.len(), RECENT_ERRORS + 6);
+        assert_eq!(log[0].message, "1 copied");
+    }
+
+    #[test]
+    fn the_log_drops_its_oldest_rows() {
+        let _vault = TempVault::new();
+        let conn = db::get_connection().unwrap();
+        conn.execute_batch(&format!(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {})
+             INSERT INTO sync_log (profile_id, level, message, created_at)
+             SELECT 'p1', 'info', 'old ' || i, '2024-01-01T00:00:00Z' FROM n",
+            LOG_LIMIT
+        ))
+        .unwrap();
+
+        log_error(None, "Failed to clean media_store");
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM sync_log", [], |r| r.get(0)).unwrap();
+        assert_eq!(count, LOG_LIMIT);
+        let log = recent_log(None, LOG_LIMIT as usize).unwrap();
+        assert_eq!(log[0].profile_id, None);
+        assert_eq!(log[0].message, "Failed to clean media_store");
+        assert_eq!(log.last().unwrap().message, "old 2");
+        assert!(recent_log(Some("p1"), 1).unwrap()[0].message.starts_with("old"));
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::settings;
+use crate::vault::MEDIA_DIR;
+
+mod rest;
+
+pub use rest::SyncthingClient;
+use rest::{FolderConfig, FolderDevice};
+
+const SETTINGS_KEY: &str = "syncthing";
+
+/// Generated `.stignore`: share encrypted blobs only. In-flight writes,
+/// partial downloads and media not yet sealed never leave the device.
+/// Syncthing uses the first matching pattern; keep `is_synced` in step.
+const IGNORE_RULES: &[&str] = &[
+    "// Generated by AetherSync; changes will be overwritten.",
+    "(?d)*.aethersync-tmp",
+    "(?d).partial",
+    "!*.enc",
+    "(?d)*",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedDevice {
+    pub device_id: String,
+    /// Share in Syncthing's untrusted mode: the device stores the folder
+    /// encrypted with `encryption_password` and cannot read names or data.
+    #[serde(default)]
+    pub untrusted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncthingSettings {
+    pub api_url: String,
+    pub api_key: String,
+    pub folder_id: String,
+    pub devices: Vec<SharedDevice>,
+    pub encryption_password: Option<String>,
+}
+
+impl Default for SyncthingSettings {
+    fn default() -> Self {
+        Self {
+            api_url: "http://127.0.0.1:8384".into(),
+            api_key: String::new(),
+            folder_id: "aethersync-vault".into(),
+            devices: Vec::new(),
+            encryption_password: None,
+        }
+    }
+}
+
+/// `SyncthingSettings` as shown to the UI. The API key and encryption
+/// password are write-only: the webview only learns whether they are set.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncthingSettingsView {
+    pub api_url: String,
+    pub api_key_set: bool,
+    pub folder_id: String,
+    pub devices: Vec<SharedDevice>,
+    pub encryption_password_set: bool,
+}
+
+impl SyncthingSettings {
+    pub fn load() -> Result<Self, String> {
+        settings::get_json(SETTINGS_KEY)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        settings::set_json(SETTINGS_KEY, self)
+    }
+
+    pub fn redacted(&self) -> SyncthingSettingsView {
+        SyncthingSettingsView {
+            api_url: self.api_url.clone(),
+            api_key_set: !self.api_key.is_empty(),
+            folder_id: self.folder_id.clone(),
+            devices: self.devices.clone(),
+            encryption_password_set: self.encryption_password.as_deref().is_some_and(|p| !p.is_empty()),
+        }
+    }
+
+    /// Save settings edited in the UI, which never sees the stored
+    /// secrets: an empty API key or a missing password keeps the stored
+    /// one, and an empty password clears it.
+    pub fn update(mut self) -> Result<(), String> {
+        let stored = Self::load()?;
+        if self.api_key.is_empty() {
+            self.api_key = stored.api_key;
+        }
+        self.encryption_password = match self.encryption_password {
+            None => stored.encryption_password,
+            Some(password) if password.is_empty() => None,
+            password => password,
+        };
+        self.save()
+    }
+
+    /// Whether `register_vault` has anyone to share `media_store` with.
+    pub fn shares_vault(&self) -> bool {
+        !self.api_key.is_empty() && !self.devices.is_empty()
+    }
+
+    pub fn client(&self) -> Result<SyncthingClient, String> {
+        if self.api_key.is_empty() {
+            return Err("Syncthing API key is not set".into());
+        }
+        Ok(SyncthingClient::new(&self.api_url, &self.api_key))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceCompletion {
+    pub device_id: String,
+    pub untrusted: bool,
+    /// Percent of the folder the device has, 0-100.
+    pub completion: f64,
+    pub need_bytes: u64,
+    pub need_items: u64,
+    pub error: Option<String>,
+}
+
+/// Whether Syncthing carries `file` (relative to `media_store`) under
+/// `IGNORE_RULES`. The folder is send-receive, so moving such a file away
+/// deletes it on every shared device too.
+pub fn is_synced(file: &str) -> bool {
+    file.ends_with(".enc") && !file.split('/').any(|part| part == ".partial")
+}
+
+/// Share the vault's blob directory with the configured devices, creating
+/// or updating the Syncthing folder and its ignore rules.
+pub async fn register_vault() -> Result<(), String> {
+    let settings = SyncthingSettings::load()?;
+    let client = settings.client()?;
+    let password = settings.encryption_password.clone().unwrap_or_default();
+    if settings.devices.iter().any(|d| d.untrusted) && password.is_empty() {
+        return Err("Untrusted devices need an encryption password".into());
+    }
+
+    fs::create_dir_all(MEDIA_DIR).map_err(|e| e.to_string())?;
+    let path = fs::canonicalize(MEDIA_DIR).map_err(|e| e.to_string())?;
+
+    let mut devices = vec![FolderDevice {
+        device_id: client.my_id().await?,
+        encryption_password: String::new(),
+    }];
+    for device in &settings.devices {
+        client.ensure_device(&device.device_id).await?;
+        devices.push(FolderDevice {
+            device_id: device.device_id.clone(),
+            encryption_password: if device.untrusted { password.clone() } else { String::new() },
+        });
+    }
+
+    let folder = FolderConfig {
+        id: settings.folder_id.clone(),
+        label: "AetherSync vault".into(),
+        path: path.to_string_lossy().into_owned(),
+        folder_type: "sendreceive".into(),
+        devices,
+    };
+    client.put_folder(&folder).await?;
+    client.set_ignores(&settings.folder_id, IGNORE_RULES).await
+}
+
+/// How far each shared device has caught up with the vault folder. A
+/// device that cannot be queried is reported with its error.
+pub async fn completion() -> Result<Vec<DeviceCompletion>, String> {
+    let settings = SyncthingSettings::load()?;
+    let client = settings.client()?;
+    let mut result = Vec::new();
+    for device in &settings.devices {
+        let entry = match client.completion(&settings.folder_id, &device.device_id).await {
+            Ok(c) => DeviceCompletion {
+                device_id: device.device_id.clone(),
+                untrusted: device.untrusted,
+                completion: c.completion,
+                need_bytes: c.need_bytes,
+                need_items: c.need_items,
+                error: None,
+            },
+            Err(e) => DeviceCompletion {
+                device_id: device.device_id.clone(),
+                untrusted: device.untrusted,
+                completion: 0.0,
+                need_bytes: 0,
+                need_items: 0,
+                error: Some(e),
+            },
+        };
+        result.push(entry);
+    }
+    Ok(result)
+}
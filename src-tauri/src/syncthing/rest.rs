@@ -0,0 +1,123 @@
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Client for a Syncthing instance's REST API, authenticated with the
+/// API key from its GUI settings.
+#[derive(Debug, Clone)]
+pub struct SyncthingClient {
+    api_url: String,
+    api_key: String,
+    client: Client,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderDevice {
+    #[serde(rename = "deviceID")]
+    pub device_id: String,
+    /// Non-empty for untrusted devices, which only ever get ciphertext.
+    #[serde(default)]
+    pub encryption_password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderConfig {
+    pub id: String,
+    pub label: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub folder_type: String,
+    pub devices: Vec<FolderDevice>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Completion {
+    pub completion: f64,
+    pub need_bytes: u64,
+    pub need_items: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SystemStatus {
+    #[serde(rename = "myID")]
+    my_id: String,
+}
+
+impl SyncthingClient {
+    pub fn new(api_url: &str, api_key: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    /// Device ID of the local instance.
+    pub async fn my_id(&self) -> Result<String, String> {
+        let status: SystemStatus = self.get_json("/rest/system/status", &[]).await?;
+        Ok(status.my_id)
+    }
+
+    /// Add the device to Syncthing's configuration unless it is there.
+    pub async fn ensure_device(&self, device_id: &str) -> Result<(), String> {
+        let path = format!("/rest/config/devices/{}", device_id);
+        let res = self.send(self.client.get(self.url(&path))).await?;
+        if res.status() != StatusCode::NOT_FOUND {
+            return check(res).await.map(|_| ());
+        }
+        let body = serde_json::json!({ "deviceID": device_id });
+        let res = self.send(self.client.put(self.url(&path)).json(&body)).await?;
+        check(res).await.map(|_| ())
+    }
+
+    /// Create the folder or replace its configuration.
+    pub async fn put_folder(&self, folder: &FolderConfig) -> Result<(), String> {
+        let url = self.url(&format!("/rest/config/folders/{}", folder.id));
+        let res = self.send(self.client.put(url).json(folder)).await?;
+        check(res).await.map(|_| ())
+    }
+
+    /// Replace the folder's `.stignore`.
+    pub async fn set_ignores(&self, folder_id: &str, lines: &[&str]) -> Result<(), String> {
+        let body = serde_json::json!({ "ignore": lines });
+        let req = self
+            .client
+            .post(self.url("/rest/db/ignores"))
+            .query(&[("folder", folder_id)])
+            .json(&body);
+        check(self.send(req).await?).await.map(|_| ())
+    }
+
+    pub async fn completion(&self, folder_id: &str, device_id: &str) -> Result<Completion, String> {
+        self.get_json("/rest/db/completion", &[("folder", folder_id), ("device", device_id)])
+            .await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.api_url, path)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T, String> {
+        let res = self.send(self.client.get(self.url(path)).query(query)).await?;
+        check(res).await?.json().await.map_err(|e| e.to_string())
+    }
+
+    async fn send(&self, req: RequestBuilder) -> Result<Response, String> {
+        req.header("X-API-Key", &self.api_key)
+            .send()
+            .await
+            .map_err(|e| format!("Syncthing request failed: {}", e))
+    }
+}
+
+async fn check(res: Response) -> Result<Response, String> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status();
+    let text = res.text().await.unwrap_or_default();
+    Err(format!("Syncthing API error {}: {}", status, text.trim()))
+}
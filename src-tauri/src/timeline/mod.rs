@@ -0,0 +1,312 @@
+use chrono::{Datelike, NaiveDate, Utc};
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::db;
+use crate::geo::escape_like;
+
+/// Number of representative memories returned for each bucket.
+const THUMBNAILS_PER_BUCKET: i64 = 4;
+
+/// Capture time of a memory, falling back to the upload time for rows
+/// imported before capture timestamps were recorded.
+const CAPTURE_TS: &str = "COALESCE(captured_at, created_at)";
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Day,
+    Month,
+    Year,
+}
+
+impl Granularity {
+    fn strftime_format(self) -> &'static str {
+        match self {
+            Granularity::Day => "%Y-%m-%d",
+            Granularity::Month => "%Y-%m",
+            Granularity::Year => "%Y",
+        }
+    }
+}
+
+/// Inclusive range of RFC 3339 timestamps or dates (e.g. "2019-01-01").
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TimelineRange {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TimelineFilters {
+    /// Matches the start of the MIME type, so "image" selects all images.
+    pub media_type: Option<String>,
+    /// One whole tag, ignoring case.
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineThumbnail {
+    pub id: String,
+    pub title: String,
+    pub media_type: String,
+    pub filename: String,
+    pub captured_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineBucket {
+    /// "2019-08-15", "2019-08" or "2019" depending on granularity.
+    pub period: String,
+    pub count: i64,
+    pub thumbnails: Vec<TimelineThumbnail>,
+}
+
+/// Count memories per day/month/year and pick a few representatives for each.
+pub fn timeline_buckets(
+    granularity: Granularity,
+    range: TimelineRange,
+    filters: TimelineFilters,
+) -> Result<Vec<TimelineBucket>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let period = format!("strftime('{}', {})", granularity.strftime_format(), CAPTURE_TS);
+    let (where_clause, values) = build_where(&range, &filters);
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {period} AS period, COUNT(*) FROM memories {where_clause}
+             GROUP BY period ORDER BY period DESC"
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let mut buckets: Vec<TimelineBucket> = stmt
+        .query_map(params_from_iter(values.iter()), |row| {
+            Ok(TimelineBucket {
+                period: row.get(0)?,
+                count: row.get(1)?,
+                thumbnails: Vec::new(),
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT period, id, title, media_type, filename, ts FROM (
+                SELECT {period} AS period, id, title, media_type, filename, {CAPTURE_TS} AS ts,
+                       ROW_NUMBER() OVER (PARTITION BY {period} ORDER BY {CAPTURE_TS}) AS rank
+                FROM memories {where_clause}
+             ) WHERE rank <= {THUMBNAILS_PER_BUCKET}"
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let mut thumbnails: HashMap<String, Vec<TimelineThumbnail>> = HashMap::new();
+    let rows = stmt
+        .query_map(params_from_iter(values.iter()), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                TimelineThumbnail {
+                    id: row.get(1)?,
+                    title: row.get(2)?,
+                    media_type: row.get(3)?,
+                    filename: row.get(4)?,
+                    captured_at: row.get(5)?,
+                },
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+    for row in rows {
+        let (period, thumbnail) = row.map_err(|e| e.to_string())?;
+        thumbnails.entry(period).or_default().push(thumbnail);
+    }
+
+    for bucket in &mut buckets {
+        bucket.thumbnails = thumbnails.remove(&bucket.period).unwrap_or_default();
+    }
+
+    Ok(buckets)
+}
+
+/// Memories captured on the same calendar date as `date` in previous years.
+/// `date` is "YYYY-MM-DD" and defaults to today.
+pub fn on_this_day(date: Option<String>, filters: TimelineFilters) -> Result<Vec<TimelineThumbnail>, String> {
+    let date = match date {
+        Some(d) => NaiveDate::parse_from_str(&d, "%Y-%m-%d").map_err(|e| e.to_string())?,
+        None => Utc::now().date_naive(),
+    };
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let (where_clause, mut values) = build_where(&TimelineRange::default(), &filters);
+
+    values.push(Value::Text(date.format("%m-%d").to_string()));
+    let month_day = values.len();
+    values.push(Value::Text(format!("{:04}", date.year())));
+    let year = values.len();
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, title, media_type, filename, {CAPTURE_TS} FROM memories {where_clause}
+             AND strftime('%m-%d', {CAPTURE_TS}) = ?{month_day}
+               AND strftime('%Y', {CAPTURE_TS}) < ?{year}
+             ORDER BY {CAPTURE_TS} DESC"
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let memories = stmt
+        .query_map(params_from_iter(values.iter()), |row| {
+            Ok(TimelineThumbnail {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                media_type: row.get(2)?,
+                filename: row.get(3)?,
+                captured_at: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(memories)
+}
+
+fn build_where(range: &TimelineRange, filters: &TimelineFilters) -> (String, Vec<Value>) {
+    // Tombstoned memories stay in the table for sync but are never shown.
+    let mut conditions = vec!["deleted_at IS NULL".to_string()];
+    let mut values = Vec::new();
+
+    if let Some(start) = &range.start {
+        values.push(Value::Text(start.clone()));
+        conditions.push(format!("{} >= ?{}", CAPTURE_TS, values.len()));
+    }
+    if let Some(end) = &range.end {
+        // A bare date as upper bound should include that whole day.
+        let end = if end.len() == 10 { format!("{}T23:59:59.999", end) } else { end.clone() };
+        values.push(Value::Text(end));
+        conditions.push(format!("{} <= ?{}", CAPTURE_TS, values.len()));
+    }
+    if let Some(media_type) = &filters.media_type {
+        values.push(Value::Text(format!("{}%", escape_like(media_type))));
+        conditions.push(format!("media_type LIKE ?{} ESCAPE '\\'", values.len()));
+    }
+    if let Some(tag) = &filters.tag {
+        // Tags are stored as "a, b"; compare against ",a,b," so only
+        // whole tags match.
+        values.push(Value::Text(format!("%,{},%", escape_like(tag.trim()))));
+        conditions.push(format!(
+            "',' || REPLACE(REPLACE(tags, ', ', ','), ' ,', ',') || ',' LIKE ?{} ESCAPE '\\'",
+            values.len()
+        ));
+    }
+
+    (format!("WHERE {}", conditions.join(" AND ")), values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+
+    fn add(id: &str, captured_at: &str, media_type: &str, tags: &str) {
+        db::add_memory(db::Memory {
+            id: id.into(),
+            title: id.into(),
+            tags: tags.into(),
+            created_at: captured_at.into(),
+            media_type: media_type.into(),
+            filename: format!("{}.bin", id),
+        })
+        .unwrap();
+    }
+
+    fn periods(buckets: &[TimelineBucket]) -> Vec<(&str, i64)> {
+        buckets.iter().map(|b| (b.period.as_str(), b.count)).collect()
+    }
+
+    fn seed() {
+        add("a", "2019-08-15T10:00:00Z", "image/jpeg", "party, beach");
+        add("b", "2019-08-15T18:00:00Z", "video/mp4", "art");
+        add("c", "2019-09-01T09:00:00Z", "image/png", "");
+        add("d", "2021-08-15T12:00:00Z", "image/jpeg", "Art,family");
+    }
+
+    #[test]
+    fn buckets_by_day_month_and_year_newest_first() {
+        let _vault = TempVault::new();
+        seed();
+        let all = || (TimelineRange::default(), TimelineFilters::default());
+
+        let (range, filters) = all();
+        let days = timeline_buckets(Granularity::Day, range, filters).unwrap();
+        assert_eq!(periods(&days), [("2021-08-15", 1), ("2019-09-01", 1), ("2019-08-15", 2)]);
+        let thumbnails: Vec<&str> = days[2].thumbnails.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(thumbnails, ["a", "b"]);
+
+        let (range, filters) = all();
+        let months = timeline_buckets(Granularity::Month, range, filters).unwrap();
+        assert_eq!(periods(&months), [("2021-08", 1), ("2019-09", 1), ("2019-08", 2)]);
+
+        let (range, filters) = all();
+        let years = timeline_buckets(Granularity::Year, range, filters).unwrap();
+        assert_eq!(periods(&years), [("2021", 1), ("2019", 3)]);
+
+        let range = TimelineRange {
+            start: Some("2019-08-15".into()),
+            end: Some("2019-08-15".into()),
+        };
+        let day = timeline_buckets(Granularity::Day, range, TimelineFilters::default()).unwrap();
+        assert_eq!(periods(&day), [("2019-08-15", 2)]);
+    }
+
+    #[test]
+    fn filters_match_whole_tags_and_media_type_prefixes() {
+        let _vault = TempVault::new();
+        seed();
+        let by = |media_type: Option<&str>, tag: Option<&str>| {
+            let filters = TimelineFilters {
+                media_type: media_type.map(str::to_string),
+                tag: tag.map(str::to_string),
+            };
+            let buckets = timeline_buckets(Granularity::Year, TimelineRange::default(), filters).unwrap();
+            buckets.iter().map(|b| b.count).sum::<i64>()
+        };
+
+        assert_eq!(by(Some("image"), None), 3);
+        assert_eq!(by(Some("video/"), None), 1);
+        // "art" is a tag of b and d, and part of "party"
+        assert_eq!(by(None, Some("art")), 2);
+        assert_eq!(by(None, Some("beach")), 1);
+        assert_eq!(by(None, Some("par%")), 0);
+        assert_eq!(by(None, Some("_rt")), 0);
+        assert_eq!(by(Some("image"), Some("art")), 1);
+    }
+
+    #[test]
+    fn on_this_day_lists_earlier_years_only() {
+        let _vault = TempVault::new();
+        seed();
+        add("e", "2018-08-15T08:00:00Z", "image/jpeg", "");
+        add("deleted", "2017-08-15T08:00:00Z", "image/jpeg", "");
+        let conn = db::get_connection().unwrap();
+        conn.execute("UPDATE memories SET deleted_at = '2020-01-01' WHERE id = 'deleted'", [])
+            .unwrap();
+
+        let ids = |date: &str, filters| -> Vec<String> {
+            on_this_day(Some(date.into()), filters)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.id)
+                .collect()
+        };
+        assert_eq!(ids("2021-08-15", TimelineFilters::default()), ["b", "a", "e"]);
+        assert_eq!(ids("2022-08-15", TimelineFilters::default()), ["d", "b", "a", "e"]);
+        let images = TimelineFilters {
+            media_type: Some("image".into()),
+            tag: None,
+        };
+        assert_eq!(ids("2021-08-15", images), ["a", "e"]);
+        assert!(on_this_day(Some("15/08/2021".into()), TimelineFilters::default()).is_err());
+    }
+}
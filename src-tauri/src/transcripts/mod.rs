@@ -0,0 +1,650 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::crypto;
+use crate::db;
+use crate::media::{self, audio};
+use crate::vault::MEDIA_DIR;
+use crate::whisper::subtitles::{self, SubtitleFormat};
+use crate::whisper::{self, languages, Audio, LanguageSettings, Segment, TranscribeOptions, Transcript, Transcriber, WordTiming};
+
+/// Length of the windows long recordings are split into, and how much
+/// consecutive windows overlap.
+const CHUNK_SECS: f64 = 30.0;
+const CHUNK_OVERLAP_SECS: f64 = 2.0;
+
+/// A stored segment, addressed by its position in the memory's transcript.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredSegment {
+    pub index: i64,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub confidence: Option<f32>,
+    pub speaker: Option<String>,
+    pub words: Vec<WordTiming>,
+}
+
+/// One manual correction of a segment's text.
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentEdit {
+    pub id: i64,
+    pub segment_index: i64,
+    pub previous_text: String,
+    pub new_text: String,
+    pub edited_at: String,
+}
+
+/// A transcript search hit pointing at the moment the phrase is spoken.
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentMatch {
+    pub memory_id: String,
+    pub index: i64,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// Transcribe a stored memory and persist the result: the full text goes
+/// into `memories.transcription` and the timed segments alongside it, both
+/// encrypted with the memory's key.
+///
+/// The audio track is decoded in-process (videos included), resampled to
+/// 16 kHz mono and fed to the backend in overlapping chunks. Without an
+/// explicit language the memory's, its people's or the vault default is
+/// used, else the language is detected and stored on the memory. When the
+/// vault asks for it, non-English recordings also get an English translation.
+pub async fn transcribe_memory(
+    transcriber: Arc<dyn Transcriber>,
+    memory_id: &str,
+    mut options: TranscribeOptions,
+) -> Result<Transcript, String> {
+    let memory = db::get_memory_by_id(memory_id.to_string())?;
+    let key = db::get_memory_key(memory_id)?;
+    let bytes = media::load_media_bytes(Path::new(MEDIA_DIR), &memory.filename, &key)?;
+    let extension = Path::new(&memory.filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_string);
+
+    let language_settings = LanguageSettings::load()?;
+    if options.language.is_none() {
+        options.language = preferred_language(memory_id)?.or(language_settings.default_language.clone());
+    }
+    if let Some(language) = &options.language {
+        options.language = Some(whisper::validate_language(transcriber.as_ref(), language)?);
+    }
+
+    let samples = tokio::task::spawn_blocking(move || audio::decode_to_mono_16k(bytes, extension.as_deref()))
+        .await
+        .map_err(|e| e.to_string())??;
+    let chunks = audio::chunk_with_overlap(&samples, CHUNK_SECS, CHUNK_OVERLAP_SECS);
+    drop(samples);
+
+    let (segments, language) = transcribe_chunks(transcriber.as_ref(), &chunks, options.clone()).await?;
+    let transcript = Transcript {
+        text: Transcript::text_from_segments(&segments),
+        language,
+        backend: transcriber.name().into(),
+        segments,
+    };
+    save_text(memory_id, &transcript.text)?;
+    save_segments(memory_id, &transcript.segments)?;
+    set_memory_language(memory_id, transcript.language.as_deref())?;
+
+    let is_english = transcript.language.as_deref() == Some("en");
+    if language_settings.translate_to_english && !is_english {
+        let translate = TranscribeOptions {
+            language: transcript.language.clone(),
+            translate: true,
+            ..options
+        };
+        let (translated, _) = transcribe_chunks(transcriber.as_ref(), &chunks, translate).await?;
+        save_translation(memory_id, &Transcript::text_from_segments(&translated))?;
+    }
+
+    Ok(transcript)
+}
+
+/// Run the backend over each chunk and stitch the segments back onto the
+/// recording's timeline. Returns the segments and the language used.
+async fn transcribe_chunks(
+    transcriber: &dyn Transcriber,
+    chunks: &[audio::AudioChunk],
+    options: TranscribeOptions,
+) -> Result<(Vec<Segment>, Option<String>), String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut language = options.language.clone();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let offset = chunk.offset_secs;
+        let chunk_end = offset + chunk.samples.len() as f64 / audio::TARGET_SAMPLE_RATE as f64;
+        let audio = Audio::Samples {
+            samples: chunk.samples.clone(),
+            sample_rate: audio::TARGET_SAMPLE_RATE,
+        };
+        let transcript = transcriber
+            .transcribe(audio, TranscribeOptions { language: language.clone(), ..options.clone() })
+            .await?;
+        // Keep the first chunk's detected language for the rest of the recording.
+        language = language.or(transcript.language);
+
+        // Each overlap is owned by whichever chunk covers its first half / second half.
+        let keep_from = if i == 0 { f64::MIN } else { offset + CHUNK_OVERLAP_SECS / 2.0 };
+        let keep_until = if i + 1 == chunks.len() { f64::MAX } else { chunk_end - CHUNK_OVERLAP_SECS / 2.0 };
+        for mut segment in transcript.segments {
+            segment.start += offset;
+            segment.end += offset;
+            if segment.start < keep_from || segment.start >= keep_until {
+                continue;
+            }
+            for word in &mut segment.words {
+                word.start += offset;
+                word.end += offset;
+            }
+            segments.push(segment);
+        }
+    }
+    Ok((segments, language))
+}
+
+/// Language set on the memory, else the default shared by everyone tagged in it.
+fn preferred_language(memory_id: &str) -> Result<Option<String>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT language FROM memories WHERE id = ?1",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if stored.is_some() {
+        return Ok(stored);
+    }
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT DISTINCT p.default_language FROM memory_people mp
+             JOIN people p ON p.id = mp.person_id
+             WHERE mp.memory_id = ?1 AND p.default_language IS NOT NULL",
+        )
+        .map_err(|e| e.to_string())?;
+    let languages = stmt
+        .query_map(params![memory_id], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    // Mixed-language households: only trust the people when they agree.
+    Ok(if languages.len() == 1 { languages.into_iter().next() } else { None })
+}
+
+/// Set (or clear, to re-enable detection) the spoken language of a memory.
+pub fn set_memory_language(memory_id: &str, language: Option<&str>) -> Result<(), String> {
+    let language = match language {
+        Some(language) => Some(
+            languages::normalize(language).ok_or_else(|| format!("Unknown language '{}'", language))?,
+        ),
+        None => None,
+    };
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE memories SET language = ?1 WHERE id = ?2",
+        params![language, memory_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn save_translation(memory_id: &str, text: &str) -> Result<(), String> {
+    let key = db::get_memory_key(memory_id)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE memories SET translation = ?1 WHERE id = ?2",
+        params![seal(text.as_bytes(), &key)?, memory_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Decrypted English translation, if one was produced.
+pub fn load_translation(memory_id: &str) -> Result<Option<String>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT translation FROM memories WHERE id = ?1",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    match stored {
+        Some(sealed) if !sealed.is_empty() => {
+            let key = db::get_memory_key(memory_id)?;
+            let text = String::from_utf8(open(&sealed, &key)?).map_err(|e| e.to_string())?;
+            Ok(Some(text))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn save_text(memory_id: &str, text: &str) -> Result<(), String> {
+    let key = db::get_memory_key(memory_id)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE memories SET transcription = ?1 WHERE id = ?2",
+        params![seal(text.as_bytes(), &key)?, memory_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Decrypted full transcript text, if the memory has been transcribed.
+pub fn load_text(memory_id: &str) -> Result<Option<String>, String> {
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT transcription FROM memories WHERE id = ?1",
+            params![memory_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    match stored {
+        Some(sealed) if !sealed.is_empty() => {
+            let key = db::get_memory_key(memory_id)?;
+            let text = String::from_utf8(open(&sealed, &key)?).map_err(|e| e.to_string())?;
+            Ok(Some(text))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Replace the stored segments of a memory. Text and word timings are
+/// encrypted with the memory's key; times stay in clear for seeking.
+///
+/// Edits are addressed by segment index, which means nothing once the
+/// segments are replaced, so the edit history goes with them.
+pub fn save_segments(memory_id: &str, segments: &[Segment]) -> Result<(), String> {
+    let key = db::get_memory_key(memory_id)?;
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    tx.execute(
+        "DELETE FROM transcript_segments WHERE memory_id = ?1",
+        params![memory_id],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.execute(
+        "DELETE FROM transcript_edits WHERE memory_id = ?1",
+        params![memory_id],
+    )
+    .map_err(|e| e.to_string())?;
+
+    for (index, segment) in segments.iter().enumerate() {
+        let words = serde_json::to_vec(&segment.words).map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO transcript_segments (memory_id, segment_index, start_ms, end_ms, text, confidence, speaker, words)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                memory_id,
+                index as i64,
+                to_millis(segment.start),
+                to_millis(segment.end),
+                seal(segment.text.as_bytes(), &key)?,
+                segment.confidence,
+                segment.speaker,
+                seal(&words, &key)?
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    tx.commit().map_err(|e| e.to_string())
+}
+
+pub fn load_segments(memory_id: &str) -> Result<Vec<StoredSegment>, String> {
+    let key = db::get_memory_key(memory_id)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT segment_index, start_ms, end_ms, text, confidence, speaker, words
+             FROM transcript_segments WHERE memory_id = ?1 ORDER BY segment_index",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params![memory_id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<f32>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, String>(6)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut segments = Vec::new();
+    for row in rows {
+        let (index, start_ms, end_ms, text, confidence, speaker, words) = row.map_err(|e| e.to_string())?;
+        let text = String::from_utf8(open(&text, &key)?).map_err(|e| e.to_string())?;
+        let words = serde_json::from_slice(&open(&words, &key)?).map_err(|e| e.to_string())?;
+        segments.push(StoredSegment {
+            index,
+            start: start_ms as f64 / 1000.0,
+            end: end_ms as f64 / 1000.0,
+            text,
+            confidence,
+            speaker,
+            words,
+        });
+    }
+    Ok(segments)
+}
+
+/// Correct the text of one segment, recording the change so it can be
+/// reviewed or undone. Word timings no longer match edited text and are
+/// dropped for that segment.
+pub fn edit_segment(memory_id: &str, segment_index: i64, text: &str) -> Result<StoredSegment, String> {
+    let segments = load_segments(memory_id)?;
+    let segment = segments
+        .iter()
+        .find(|s| s.index == segment_index)
+        .ok_or("Segment not found")?;
+    let new_text = text.trim();
+    if new_text == segment.text {
+        return Ok(segment.clone());
+    }
+
+    let key = db::get_memory_key(memory_id)?;
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT INTO transcript_edits (memory_id, segment_index, previous_text, new_text, edited_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            memory_id,
+            segment_index,
+            seal(segment.text.as_bytes(), &key)?,
+            seal(new_text.as_bytes(), &key)?,
+            Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    write_segment_text(&tx, memory_id, segment_index, new_text, &key)?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    refresh_text(memory_id)?;
+    let mut edited = segment.clone();
+    edited.text = new_text.to_string();
+    edited.words.clear();
+    Ok(edited)
+}
+
+/// Revert the most recent edit of a segment. Returns the restored text,
+/// or `None` when the segment has no edits left.
+pub fn undo_segment_edit(memory_id: &str, segment_index: i64) -> Result<Option<String>, String> {
+    let key = db::get_memory_key(memory_id)?;
+    let mut conn = db::get_connection().map_err(|e| e.to_string())?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let last: Option<(i64, String)> = tx
+        .query_row(
+            "SELECT id, previous_text FROM transcript_edits
+             WHERE memory_id = ?1 AND segment_index = ?2
+             ORDER BY id DESC LIMIT 1",
+            params![memory_id, segment_index],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    let Some((edit_id, previous)) = last else {
+        return Ok(None);
+    };
+    let previous = String::from_utf8(open(&previous, &key)?).map_err(|e| e.to_string())?;
+
+    write_segment_text(&tx, memory_id, segment_index, &previous, &key)?;
+    tx.execute("DELETE FROM transcript_edits WHERE id = ?1", params![edit_id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    refresh_text(memory_id)?;
+    Ok(Some(previous))
+}
+
+pub fn segment_history(memory_id: &str, segment_index: i64) -> Result<Vec<SegmentEdit>, String> {
+    let key = db::get_memory_key(memory_id)?;
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, segment_index, previous_text, new_text, edited_at FROM transcript_edits
+             WHERE memory_id = ?1 AND segment_index = ?2 ORDER BY id",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params![memory_id, segment_index], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut edits = Vec::new();
+    for row in rows {
+        let (id, segment_index, previous, new, edited_at) = row.map_err(|e| e.to_string())?;
+        edits.push(SegmentEdit {
+            id,
+            segment_index,
+            previous_text: String::from_utf8(open(&previous, &key)?).map_err(|e| e.to_string())?,
+            new_text: String::from_utf8(open(&new, &key)?).map_err(|e| e.to_string())?,
+            edited_at,
+        });
+    }
+    Ok(edits)
+}
+
+/// Render the (edited) transcript of a memory as SRT, WebVTT or plain text.
+pub fn export(memory_id: &str, format: SubtitleFormat) -> Result<String, String> {
+    let segments: Vec<Segment> = load_segments(memory_id)?
+        .into_iter()
+        .map(Segment::from)
+        .collect();
+    if segments.is_empty() {
+        return Err("Memory has no transcript".into());
+    }
+    Ok(subtitles::render(&segments, format))
+}
+
+fn write_segment_text(
+    conn: &rusqlite::Connection,
+    memory_id: &str,
+    segment_index: i64,
+    text: &str,
+    key: &[u8; 32],
+) -> Result<(), String> {
+    conn.execute(
+        "UPDATE transcript_segments SET text = ?1, words = ?2 WHERE memory_id = ?3 AND segment_index = ?4",
+        params![seal(text.as_bytes(), key)?, seal(b"[]", key)?, memory_id, segment_index],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Keep `memories.transcription` in step with the segments after edits.
+fn refresh_text(memory_id: &str) -> Result<(), String> {
+    let segments: Vec<Segment> = load_segments(memory_id)?
+        .into_iter()
+        .map(Segment::from)
+        .collect();
+    save_text(memory_id, &Transcript::text_from_segments(&segments))
+}
+
+impl From<StoredSegment> for Segment {
+    fn from(stored: StoredSegment) -> Self {
+        Segment {
+            start: stored.start,
+            end: stored.end,
+            text: stored.text,
+            confidence: stored.confidence,
+            speaker: stored.speaker,
+            words: stored.words,
+        }
+    }
+}
+
+/// Case-insensitive search across the transcripts of memories that are
+/// not deleted. Segments are encrypted, so matching happens after
+/// decryption rather than in SQL.
+pub fn search_segments(query: &str) -> Result<Vec<SegmentMatch>, String> {
+    let needle = query.trim().to_lowercase();
+    if needle.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let conn = db::get_connection().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT DISTINCT s.memory_id FROM transcript_segments s
+             JOIN memories m ON m.id = s.memory_id
+             WHERE m.deleted_at IS NULL",
+        )
+        .map_err(|e| e.to_string())?;
+    let memory_ids = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut matches = Vec::new();
+    for memory_id in memory_ids {
+        for segment in load_segments(&memory_id)? {
+            if !segment.text.to_lowercase().contains(&needle) {
+                continue;
+            }
+            // Jump to the first matching word when word timings exist.
+            let start = segment
+                .words
+                .iter()
+                .find(|w| w.word.to_lowercase().contains(needle.split_whitespace().next().unwrap_or("")))
+                .map(|w| w.start)
+                .unwrap_or(segment.start);
+            matches.push(SegmentMatch {
+                memory_id: memory_id.clone(),
+                index: segment.index,
+                start,
+                end: segment.end,
+                text: segment.text,
+            });
+        }
+    }
+    Ok(matches)
+}
+
+fn to_millis(seconds: f64) -> i64 {
+    (seconds * 1000.0).round() as i64
+}
+
+fn seal(data: &[u8], key: &[u8; 32]) -> Result<String, String> {
+    Ok(STANDARD.encode(crypto::encrypt_bytes(data, key)?))
+}
+
+fn open(data: &str, key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let bytes = STANDARD.decode(data).map_err(|e| e.to_string())?;
+    crypto::decrypt_bytes(&bytes, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+    use crate::whisper::FakeTranscriber;
+
+    /// Store a memory whose media is `secs` of silence as a 16 kHz WAV.
+    fn add_recording(vault: &TempVault, secs: u32) -> String {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let path = vault.path().join(MEDIA_DIR).join("clip.wav");
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..secs * 16_000 {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let id = uuid::Uuid::new_v4().to_string();
+        db::add_memory(db::Memory {
+            id: id.clone(),
+            title: "Clip".into(),
+            tags: String::new(),
+            created_at: Utc::now().to_rfc3339(),
+            media_type: "audio/wav".into(),
+            filename: "clip.wav".into(),
+        })
+        .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn transcribe_memory_stores_text_and_segments() {
+        let vault = TempVault::new();
+        let id = add_recording(&vault, 2);
+        let fake = FakeTranscriber {
+            text: "hello from the beach".into(),
+        };
+
+        let transcript = transcribe_memory(Arc::new(fake), &id, TranscribeOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(transcript.text, "hello from the beach");
+        assert_eq!(transcript.language.as_deref(), Some("en"));
+        assert_eq!(transcript.backend, "fake");
+
+        assert_eq!(load_text(&id).unwrap().as_deref(), Some("hello from the beach"));
+        let segments = load_segments(&id).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].text, "hello from the beach");
+        assert!((segments[0].end - 2.0).abs() < 0.01, "{}", segments[0].end);
+        assert_eq!(segments[0].words.len(), 4);
+        assert_eq!(preferred_language(&id).unwrap().as_deref(), Some("en"));
+    }
+
+    #[tokio::test]
+    async fn retranscribing_drops_the_edit_history() {
+        let vault = TempVault::new();
+        let id = add_recording(&vault, 2);
+        let first = FakeTranscriber {
+            text: "hello from the beach".into(),
+        };
+        transcribe_memory(Arc::new(first), &id, TranscribeOptions::default())
+            .await
+            .unwrap();
+        edit_segment(&id, 0, "hello from the lake").unwrap();
+
+        let second = FakeTranscriber {
+            text: "a different recording".into(),
+        };
+        transcribe_memory(Arc::new(second), &id, TranscribeOptions::default())
+            .await
+            .unwrap();
+
+        assert!(segment_history(&id, 0).unwrap().is_empty());
+        assert_eq!(undo_segment_edit(&id, 0).unwrap(), None);
+        assert_eq!(load_segments(&id).unwrap()[0].text, "a different recording");
+    }
+}
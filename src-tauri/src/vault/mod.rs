@@ -0,0 +1,214 @@
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Originals, thumbnails and partial downloads, relative to the data
+/// directory.
+pub const MEDIA_DIR: &str = "media_store";
+
+/// Suffix of in-flight writes. A file still carrying it after a restart
+/// was left behind by a crash and is safe to delete.
+pub const TEMP_SUFFIX: &str = ".aethersync-tmp";
+
+/// Temp files younger than this may belong to a write still in progress,
+/// e.g. by another instance or a sync that has not stopped yet.
+pub const TEMP_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Write `bytes` to `path` so that readers (and a crash) only ever see
+/// the old content or the complete new one: write a temp file next to
+/// the destination, fsync it, then rename it into place.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        commit(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Copy `src` to `dst` through a temp file, hashing the source as it is
+/// read and the temp file once it is on disk. The destination is only
+/// replaced when both hashes match, and match `expected` if given (the
+/// source changed since it was hashed otherwise). Returns the SHA-256 of
+/// the content.
+pub fn copy_atomic(src: &Path, dst: &Path, expected: Option<&str>) -> io::Result<String> {
+    copy_atomic_limited(src, dst, expected, None)
+}
+
+/// `copy_atomic`, pacing reads so the copy averages no more than
+/// `bytes_per_second`.
+pub fn copy_atomic_limited(src: &Path, dst: &Path, expected: Option<&str>, bytes_per_second: Option<u64>) -> io::Result<String> {
+    let temp = temp_path(dst)?;
+    let started = Instant::now();
+    let mut copied = 0u64;
+    let result = (|| {
+        let mut input = File::open(src)?;
+        let mut output = File::create(&temp)?;
+        let mut hasher = Sha256::new();
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = input.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            output.write_all(&buf[..n])?;
+            copied += n as u64;
+            if let Some(limit) = bytes_per_second.filter(|l| *l > 0) {
+                let due = Duration::from_secs_f64(copied as f64 / limit as f64);
+                if let Some(ahead) = due.checked_sub(started.elapsed()) {
+                    thread::sleep(ahead);
+                }
+            }
+        }
+        output.sync_all()?;
+        drop(output);
+
+        let read = format!("{:x}", hasher.finalize());
+        if expected.map_or(false, |expected| expected != read) {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                format!("{} changed while being copied", src.display()),
+            ));
+        }
+        if hash_file(&temp)? != read {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Copy of {} failed verification", src.display()),
+            ));
+        }
+        commit(&temp, dst)?;
+        Ok(read)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Accept only hashes shaped like `hash_file` output: 64 lowercase hex
+/// digits. Hashes received from elsewhere end up in file names.
+pub fn check_hash(hash: &str) -> Result<(), String> {
+    if hash.len() != 64 || !hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err(format!("Invalid blob hash '{}'", hash));
+    }
+    Ok(())
+}
+
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().ends_with(TEMP_SUFFIX))
+        .unwrap_or(false)
+}
+
+/// Remove temp files orphaned by a crash anywhere under `root`.
+/// Returns how many were removed.
+pub fn cleanup_temp_files(root: &Path) -> io::Result<usize> {
+    cleanup_temp_files_older_than(root, Duration::ZERO)
+}
+
+/// `cleanup_temp_files`, sparing temp files modified within `age`.
+pub fn cleanup_temp_files_older_than(root: &Path, age: Duration) -> io::Result<usize> {
+    let stale_before = SystemTime::now() - age;
+    if !root.is_dir() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file()
+                && is_temp_file(&path)
+                && entry.metadata()?.modified().map_or(true, |at| at <= stale_before)
+            {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Hidden sibling of `path`, so the final rename stays on one filesystem.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(parent)?;
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?
+        .to_string_lossy();
+    Ok(parent.join(format!(".{}.{}{}", name, uuid::Uuid::new_v4().simple(), TEMP_SUFFIX)))
+}
+
+fn commit(temp: &Path, path: &Path) -> io::Result<()> {
+    fs::rename(temp, path)?;
+    // Persist the rename itself; directories cannot be opened on Windows.
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_hash_accepts_only_sha256_hex() {
+        let hash = format!("{:x}", Sha256::digest(b"blob"));
+        assert!(check_hash(&hash).is_ok());
+        assert!(check_hash(&hash.to_uppercase()).is_err());
+        assert!(check_hash(&hash[1..]).is_err());
+        assert!(check_hash(&format!("../{}", &hash[3..])).is_err());
+        assert!(check_hash("").is_err());
+    }
+
+    #[test]
+    fn cleanup_spares_recent_temp_files_within_the_grace() {
+        let root = std::env::temp_dir().join(format!("aethersync-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let temp = root.join("sub").join(format!(".photo.jpg.1{}", TEMP_SUFFIX));
+        fs::write(&temp, b"half").unwrap();
+        fs::write(root.join("photo.jpg"), b"whole").unwrap();
+
+        assert_eq!(cleanup_temp_files_older_than(&root, TEMP_GRACE).unwrap(), 0);
+        assert!(temp.exists());
+        assert_eq!(cleanup_temp_files(&root).unwrap(), 1);
+        assert!(!temp.exists());
+        assert!(root.join("photo.jpg").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
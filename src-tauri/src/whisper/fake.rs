@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use super::{Audio, Segment, TranscribeOptions, Transcript, Transcriber, WordTiming};
+
+/// Backend returning a fixed transcript, so the pipeline can run without
+/// a model or network access.
+pub struct FakeTranscriber {
+    pub text: String,
+}
+
+impl Default for FakeTranscriber {
+    fn default() -> Self {
+        Self {
+            text: "This is a test transcript.".into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transcriber for FakeTranscriber {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn transcribe(&self, audio: Audio, options: TranscribeOptions) -> Result<Transcript, String> {
+        let duration = match &audio {
+            Audio::File(path) => {
+                if !path.exists() {
+                    return Err(format!("File '{}' not found", path.display()));
+                }
+                1.0
+            }
+            Audio::Samples { samples, sample_rate } => samples.len() as f64 / (*sample_rate).max(1) as f64,
+        };
+
+        // Spread the words evenly over the audio so timing-dependent code has data.
+        let words: Vec<&str> = self.text.split_whitespace().collect();
+        let step = duration / words.len().max(1) as f64;
+        let words = words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| WordTiming {
+                start: i as f64 * step,
+                end: (i + 1) as f64 * step,
+                word: word.to_string(),
+            })
+            .collect();
+
+        Ok(Transcript {
+            text: self.text.clone(),
+            language: Some(options.language.unwrap_or_else(|| "en".into())),
+            backend: self.name().into(),
+            segments: vec![Segment {
+                start: 0.0,
+                end: duration,
+                text: self.text.clone(),
+                confidence: Some(1.0),
+                speaker: None,
+                words,
+            }],
+        })
+    }
+}
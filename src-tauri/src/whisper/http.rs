@@ -0,0 +1,196 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use reqwest::Client;
+use std::io::Cursor;
+
+use super::{languages, subtitles, Audio, Segment, TranscribeOptions, Transcript, Transcriber, WordTiming};
+
+/// Backend posting audio to an OpenAI-compatible `/audio/transcriptions`
+/// endpoint. Pointing `base_url` at a local server keeps audio on the LAN;
+/// the default OpenAI URL sends it off-device, so this backend is opt-in.
+#[derive(Debug)]
+pub struct HttpTranscriber {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    client: Client,
+}
+
+/// `verbose_json` response; plain `json` responses only carry `text`.
+#[derive(Debug, Deserialize)]
+pub struct TranscriptionResponse {
+    pub text: String,
+    pub language: Option<String>,
+    #[serde(default)]
+    pub segments: Vec<ResponseSegment>,
+    #[serde(default)]
+    pub words: Vec<WordTiming>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub avg_logprob: Option<f32>,
+}
+
+impl HttpTranscriber {
+    pub fn new(base_url: &str, api_key: Option<String>, model: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: model.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    /// Translation has its own endpoint in the OpenAI API and takes no language.
+    fn endpoint(&self, translate: bool) -> String {
+        if translate {
+            format!("{}/audio/translations", self.base_url)
+        } else {
+            format!("{}/audio/transcriptions", self.base_url)
+        }
+    }
+}
+
+#[async_trait]
+impl Transcriber for HttpTranscriber {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn transcribe(&self, audio: Audio, options: TranscribeOptions) -> Result<Transcript, String> {
+        // Build multipart form
+        let (bytes, file_name) = match audio {
+            Audio::File(path) => {
+                if !path.exists() {
+                    return Err(format!("File '{}' not found", path.display()));
+                }
+                let bytes = tokio::fs::read(&path).await.map_err(|e| e.to_string())?;
+                let file_name = path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "audio".into());
+                (bytes, file_name)
+            }
+            Audio::Samples { samples, sample_rate } => (encode_wav(&samples, sample_rate)?, "audio.wav".into()),
+        };
+
+        let part = reqwest::multipart::Part::bytes(bytes).file_name(file_name);
+        let mut form = reqwest::multipart::Form::new()
+            .text("model", self.model.clone())
+            .text("response_format", "verbose_json")
+            .text("timestamp_granularities[]", "segment")
+            .text("timestamp_granularities[]", "word")
+            .part("file", part);
+
+        if let Some(lang) = options.language.as_ref().filter(|_| !options.translate) {
+            form = form.text("language", lang.clone());
+        }
+        if let Some(prompt) = &options.prompt {
+            form = form.text("prompt", prompt.clone());
+        }
+
+        let mut request = self.client.post(self.endpoint(options.translate)).multipart(form);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let res = request
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let text = res.text().await.unwrap_or_default();
+            return Err(format!("Transcription API error {}: {}", status, text));
+        }
+
+        let body = res.text().await.map_err(|e| e.to_string())?;
+
+        // Some compatible servers ignore `response_format` or only speak SRT.
+        if !body.trim_start().starts_with('{') {
+            let segments = subtitles::parse_srt(&body)?;
+            return Ok(Transcript {
+                text: Transcript::text_from_segments(&segments),
+                language: options.language,
+                backend: self.name().into(),
+                segments,
+            });
+        }
+
+        let transcription: TranscriptionResponse = serde_json::from_str(&body)
+            .map_err(|e| format!("Failed to parse response JSON: {}", e))?;
+
+        let segments = into_segments(transcription.segments, transcription.words, &transcription.text);
+        Ok(Transcript {
+            text: transcription.text,
+            language: transcription
+                .language
+                .as_deref()
+                .and_then(languages::normalize)
+                .map(str::to_string)
+                .or(options.language),
+            backend: self.name().into(),
+            segments,
+        })
+    }
+}
+
+/// Attach word timings to the segment they fall in. Without segments the
+/// whole text becomes one segment spanning the reported words.
+fn into_segments(segments: Vec<ResponseSegment>, words: Vec<WordTiming>, text: &str) -> Vec<Segment> {
+    if segments.is_empty() {
+        let end = words.last().map(|w| w.end).unwrap_or(0.0);
+        return vec![Segment {
+            start: 0.0,
+            end,
+            text: text.trim().to_string(),
+            confidence: None,
+            speaker: None,
+            words,
+        }];
+    }
+
+    let mut words = words.into_iter().peekable();
+    segments
+        .into_iter()
+        .map(|s| {
+            let mut segment_words = Vec::new();
+            while let Some(word) = words.next_if(|w| w.start < s.end) {
+                segment_words.push(word);
+            }
+            Segment {
+                start: s.start,
+                end: s.end,
+                text: s.text.trim().to_string(),
+                confidence: s.avg_logprob.map(|lp| lp.exp().clamp(0.0, 1.0)),
+                speaker: None,
+                words: segment_words,
+            }
+        })
+        .collect()
+}
+
+/// Encode mono f32 samples as a 16-bit PCM WAV file in memory.
+fn encode_wav(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, String> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut cursor = Cursor::new(Vec::new());
+    {
+        let mut writer = hound::WavWriter::new(&mut cursor, spec).map_err(|e| e.to_string())?;
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            writer.write_sample(value).map_err(|e| e.to_string())?;
+        }
+        writer.finalize().map_err(|e| e.to_string())?;
+    }
+    Ok(cursor.into_inner())
+}
@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState};
+
+use crate::media::audio;
+use super::{Audio, Segment, TranscribeOptions, Transcript, Transcriber, WordTiming};
+
+/// Sample rate whisper.cpp models are trained on.
+pub const WHISPER_SAMPLE_RATE: u32 = audio::TARGET_SAMPLE_RATE;
+
+/// On-device backend running a ggml whisper.cpp model on the CPU.
+/// The model is loaded on first use so a missing file only fails transcription.
+pub struct LocalWhisper {
+    model_path: PathBuf,
+    threads: i32,
+    context: Arc<Mutex<Option<Arc<WhisperContext>>>>,
+}
+
+impl LocalWhisper {
+    pub fn new(model_path: impl AsRef<Path>) -> Self {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get().min(8) as i32)
+            .unwrap_or(4);
+        Self {
+            model_path: model_path.as_ref().to_path_buf(),
+            threads,
+            context: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+/// The loaded model, reading it from `model_path` on first use. Blocks
+/// for as long as loading takes.
+fn load_context(model_path: &Path, cache: &Mutex<Option<Arc<WhisperContext>>>) -> Result<Arc<WhisperContext>, String> {
+    let mut guard = cache.lock().unwrap();
+    if let Some(context) = guard.as_ref() {
+        return Ok(context.clone());
+    }
+
+    if !model_path.exists() {
+        return Err(format!("Whisper model '{}' not found", model_path.display()));
+    }
+    let path = model_path.to_str().ok_or("Invalid model path")?;
+    let context = WhisperContext::new_with_params(path, WhisperContextParameters::default())
+        .map_err(|e| format!("Failed to load whisper model: {}", e))?;
+
+    let context = Arc::new(context);
+    *guard = Some(context.clone());
+    Ok(context)
+}
+
+#[async_trait]
+impl Transcriber for LocalWhisper {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn transcribe(&self, audio: Audio, options: TranscribeOptions) -> Result<Transcript, String> {
+        let model_path = self.model_path.clone();
+        let cache = self.context.clone();
+        let threads = self.threads;
+
+        // Loading, decoding and inference are CPU-bound; keep them off the async runtime's worker threads.
+        tokio::task::spawn_blocking(move || {
+            let context = load_context(&model_path, &cache)?;
+            let samples = match audio {
+                Audio::File(path) => {
+                    let bytes = std::fs::read(&path).map_err(|e| e.to_string())?;
+                    let extension = path.extension().and_then(|e| e.to_str());
+                    audio::decode_to_mono_16k(bytes, extension)?
+                }
+                Audio::Samples { samples, sample_rate } => audio::resample(&samples, sample_rate, WHISPER_SAMPLE_RATE)?,
+            };
+
+            let mut state = context.create_state().map_err(|e| e.to_string())?;
+
+            let language = match options.language.clone() {
+                Some(language) => Some(language),
+                None => detect_language(&mut state, &samples, threads)?,
+            };
+
+            let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+            params.set_n_threads(threads);
+            params.set_language(Some(language.as_deref().unwrap_or("auto")));
+            params.set_translate(options.translate);
+            if let Some(prompt) = &options.prompt {
+                params.set_initial_prompt(prompt);
+            }
+            params.set_print_progress(false);
+            params.set_print_realtime(false);
+            params.set_print_special(false);
+            params.set_token_timestamps(true);
+
+            state.full(params, &samples).map_err(|e| e.to_string())?;
+
+            let mut segments = Vec::new();
+            for i in 0..state.full_n_segments().map_err(|e| e.to_string())? {
+                segments.push(read_segment(&state, i)?);
+            }
+            Ok(Transcript {
+                text: Transcript::text_from_segments(&segments),
+                language,
+                backend: "local".into(),
+                segments,
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+}
+
+/// Most probable spoken language from the first 30 seconds of audio.
+fn detect_language(state: &mut WhisperState, samples: &[f32], threads: i32) -> Result<Option<String>, String> {
+    let threads = threads.max(1) as usize;
+    state.pcm_to_mel(samples, threads).map_err(|e| e.to_string())?;
+    let probabilities = state.lang_detect(0, threads).map_err(|e| e.to_string())?;
+
+    let best = probabilities
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(id, _)| id as i32);
+    Ok(best.and_then(whisper_rs::get_lang_str).map(str::to_string))
+}
+
+/// Convert one whisper.cpp segment, grouping its tokens into words.
+/// whisper.cpp reports times in centiseconds.
+fn read_segment(state: &WhisperState, i: i32) -> Result<Segment, String> {
+    let text = state.full_get_segment_text(i).map_err(|e| e.to_string())?;
+    let start = state.full_get_segment_t0(i).map_err(|e| e.to_string())? as f64 / 100.0;
+    let end = state.full_get_segment_t1(i).map_err(|e| e.to_string())? as f64 / 100.0;
+
+    let mut words: Vec<WordTiming> = Vec::new();
+    let mut probability_sum = 0.0f32;
+    let mut token_count = 0;
+    for j in 0..state.full_n_tokens(i).map_err(|e| e.to_string())? {
+        let token_text = state.full_get_token_text(i, j).map_err(|e| e.to_string())?;
+        // Special tokens such as [_BEG_] and [_TT_150] carry no speech.
+        if token_text.starts_with("[_") || token_text.starts_with("<|") {
+            continue;
+        }
+        let data = state.full_get_token_data(i, j).map_err(|e| e.to_string())?;
+        probability_sum += data.p;
+        token_count += 1;
+
+        let token_start = data.t0 as f64 / 100.0;
+        let token_end = data.t1 as f64 / 100.0;
+        match words.last_mut() {
+            // Tokens without a leading space continue the previous word.
+            Some(word) if !token_text.starts_with(' ') => {
+                word.word.push_str(&token_text);
+                word.end = token_end;
+            }
+            _ => words.push(WordTiming {
+                start: token_start,
+                end: token_end,
+                word: token_text.trim_start().to_string(),
+            }),
+        }
+    }
+
+    Ok(Segment {
+        start,
+        end,
+        text: text.trim().to_string(),
+        confidence: (token_count > 0).then(|| probability_sum / token_count as f32),
+        speaker: None,
+        words,
+    })
+}
@@ -1,78 +1,275 @@
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use reqwest::Client;
-use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
-const OPENAI_API_URL: &str = "https://api.openai.com/v1/audio/transcriptions";
+use crate::settings;
 
-#[derive(Debug)]
-pub struct WhisperClient {
-    api_key: String,
-    client: Client,
+mod fake;
+mod http;
+pub mod languages;
+mod local;
+pub mod subtitles;
+
+pub use fake::FakeTranscriber;
+pub use http::HttpTranscriber;
+pub use local::LocalWhisper;
+
+/// Settings key holding the serialized `TranscriberSettings`.
+const SETTINGS_KEY: &str = "transcriber";
+/// Settings key holding the serialized `LanguageSettings`.
+const LANGUAGE_SETTINGS_KEY: &str = "transcription_language";
+
+/// Audio handed to a backend: either a file on disk or decoded samples.
+#[derive(Debug, Clone)]
+pub enum Audio {
+    File(PathBuf),
+    /// Mono PCM samples in the range [-1.0, 1.0].
+    Samples { samples: Vec<f32>, sample_rate: u32 },
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TranscribeOptions {
+    /// ISO 639-1 code; `None` lets the backend detect the language.
+    pub language: Option<String>,
+    /// Optional context (names, spellings) to bias the decoder.
+    pub prompt: Option<String>,
+    /// Produce an English translation instead of a same-language transcript.
+    #[serde(default)]
+    pub translate: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WordTiming {
+    pub start: f64, // seconds
+    pub end: f64,
+    pub word: String,
 }
 
-#[derive(Debug, Serialize)]
-struct TranscriptionRequest<'a> {
-    model: &'a str,
-    file_path: &'a str,
-    language: Option<&'a str>,
+/// A timed stretch of speech, as produced by the backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: f64, // seconds
+    pub end: f64,
+    pub text: String,
+    /// Backend-reported probability in [0, 1], if available.
+    pub confidence: Option<f32>,
+    pub speaker: Option<String>,
+    #[serde(default)]
+    pub words: Vec<WordTiming>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct TranscriptionResponse {
+#[derive(Debug, Clone, Serialize)]
+pub struct Transcript {
     pub text: String,
+    pub language: Option<String>,
+    pub backend: String,
+    pub segments: Vec<Segment>,
+}
+
+impl Transcript {
+    /// Join segment texts, for backends that only report segments.
+    pub fn text_from_segments(segments: &[Segment]) -> String {
+        segments
+            .iter()
+            .map(|s| s.text.trim())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 }
 
-impl WhisperClient {
-    pub fn new(api_key: String) -> Self {
+/// A speech-to-text backend. The local backend is the default; remote
+/// backends must be selected explicitly since they send audio off-device.
+#[async_trait]
+pub trait Transcriber: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Language codes this backend accepts; all Whisper languages by default.
+    fn supported_languages(&self) -> Vec<&'static str> {
+        languages::WHISPER_LANGUAGES.iter().map(|(code, _)| *code).collect()
+    }
+
+    async fn transcribe(&self, audio: Audio, options: TranscribeOptions) -> Result<Transcript, String>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Local,
+    /// Any OpenAI-compatible `/audio/transcriptions` endpoint.
+    Http,
+    /// Deterministic output without a model, for tests and development.
+    Fake,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscriberSettings {
+    pub backend: Backend,
+    pub model_path: String,
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+impl Default for TranscriberSettings {
+    fn default() -> Self {
         Self {
-            api_key,
-            client: Client::new(),
+            backend: Backend::Local,
+            model_path: "models/whisper/ggml-base.bin".into(),
+            base_url: "https://api.openai.com/v1".into(),
+            api_key: None,
+            model: "whisper-1".into(),
         }
     }
+}
+
+/// `TranscriberSettings` as shown to the UI. The API key is write-only:
+/// the webview only learns whether one is stored.
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriberSettingsView {
+    pub backend: Backend,
+    pub model_path: String,
+    pub base_url: String,
+    pub api_key_set: bool,
+    pub model: String,
+}
+
+impl TranscriberSettings {
+    pub fn load() -> Result<Self, String> {
+        settings::get_json(SETTINGS_KEY)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        settings::set_json(SETTINGS_KEY, self)
+    }
 
-    /// Transcribe the audio file at `file_path` using OpenAI Whisper API.
-    pub async fn transcribe_audio(
-        &self,
-        file_path: &str,
-        language: Option<&str>,
-    ) -> Result<String, String> {
-        if !Path::new(file_path).exists() {
-            return Err(format!("File '{}' not found", file_path));
+    pub fn redacted(&self) -> TranscriberSettingsView {
+        TranscriberSettingsView {
+            backend: self.backend,
+            model_path: self.model_path.clone(),
+            base_url: self.base_url.clone(),
+            api_key_set: self.api_key.as_deref().is_some_and(|k| !k.is_empty()),
+            model: self.model.clone(),
         }
+    }
+
+    pub fn build(&self) -> Arc<dyn Transcriber> {
+        match self.backend {
+            Backend::Local => Arc::new(LocalWhisper::new(&self.model_path)),
+            Backend::Http => Arc::new(HttpTranscriber::new(
+                &self.base_url,
+                self.api_key.clone(),
+                &self.model,
+            )),
+            Backend::Fake => Arc::new(FakeTranscriber::default()),
+        }
+    }
+}
+
+/// Normalize a user-supplied language and check the backend supports it.
+pub fn validate_language(transcriber: &dyn Transcriber, language: &str) -> Result<String, String> {
+    let code = languages::normalize(language).ok_or_else(|| format!("Unknown language '{}'", language))?;
+    if !transcriber.supported_languages().contains(&code) {
+        return Err(format!(
+            "Language '{}' is not supported by the {} backend",
+            code,
+            transcriber.name()
+        ));
+    }
+    Ok(code.to_string())
+}
+
+/// Vault-wide language preferences for transcription.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanguageSettings {
+    /// Used when neither the memory nor its people specify a language;
+    /// `None` means detect per recording.
+    pub default_language: Option<String>,
+    /// Also store an English translation for non-English recordings.
+    pub translate_to_english: bool,
+}
+
+impl LanguageSettings {
+    pub fn load() -> Result<Self, String> {
+        settings::get_json(LANGUAGE_SETTINGS_KEY)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        settings::set_json(LANGUAGE_SETTINGS_KEY, self)
+    }
+}
+
+/// Managed Tauri state holding the backend selected in settings.
+pub struct TranscriberState(RwLock<Arc<dyn Transcriber>>);
+
+impl TranscriberState {
+    pub fn from_settings() -> Result<Self, String> {
+        Ok(Self(RwLock::new(TranscriberSettings::load()?.build())))
+    }
+
+    pub fn current(&self) -> Arc<dyn Transcriber> {
+        self.0.read().unwrap().clone()
+    }
 
-        // Build multipart form
-        let part = reqwest::multipart::Part::file(file_path)?;
-        let form = reqwest::multipart::Form::new()
-            .text("model", "whisper-1")
-            .part("file", part)
-            .map_err(|e| format!("Failed to create form: {}", e))?;
-
-        let form = if let Some(lang) = language {
-            form.text("language", lang)
-        } else {
-            form
+    /// Persist new settings and switch backends for subsequent requests.
+    /// Since the UI never sees the stored API key, a missing key keeps it
+    /// and an empty one clears it.
+    pub fn apply(&self, mut settings: TranscriberSettings) -> Result<(), String> {
+        settings.api_key = match settings.api_key {
+            None => TranscriberSettings::load()?.api_key,
+            Some(key) if key.is_empty() => None,
+            key => key,
         };
+        settings.save()?;
+        *self.0.write().unwrap() = settings.build();
+        Ok(())
+    }
+}
 
-        let res = self
-            .client
-            .post(OPENAI_API_URL)
-            .bearer_auth(&self.api_key)
-            .multipart(form)
-            .send()
-            .await
-            .map_err(|e| format!("Request failed: {}", e))?;
-
-        if !res.status().is_success() {
-            let status = res.status();
-            let text = res.text().await.unwrap_or_default();
-            return Err(format!("OpenAI API error {}: {}", status, text));
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempVault;
+
+    #[test]
+    fn api_key_is_write_only() {
+        let _vault = TempVault::new();
+        let state = TranscriberState::from_settings().unwrap();
+        let with_key = TranscriberSettings {
+            backend: Backend::Http,
+            api_key: Some("sk-secret".into()),
+            ..Default::default()
+        };
+        state.apply(with_key.clone()).unwrap();
+
+        let view = serde_json::to_value(TranscriberSettings::load().unwrap().redacted()).unwrap();
+        assert_eq!(view["api_key_set"], true);
+        assert!(!view.to_string().contains("sk-secret"));
 
-        let transcription: TranscriptionResponse = res
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse response JSON: {}", e))?;
+        // Saving what the UI saw (no key) keeps the stored one
+        state
+            .apply(TranscriberSettings {
+                api_key: None,
+                model: "whisper-large".into(),
+                ..with_key.clone()
+            })
+            .unwrap();
+        let stored = TranscriberSettings::load().unwrap();
+        assert_eq!(stored.api_key.as_deref(), Some("sk-secret"));
+        assert_eq!(stored.model, "whisper-large");
 
-        Ok(transcription.text)
+        // An empty key clears it
+        state
+            .apply(TranscriberSettings {
+                api_key: Some(String::new()),
+                ..with_key
+            })
+            .unwrap();
+        assert_eq!(TranscriberSettings::load().unwrap().api_key, None);
+        assert!(!TranscriberSettings::load().unwrap().redacted().api_key_set);
     }
 }
@@ -0,0 +1,220 @@
+use serde::Deserialize;
+
+use super::Segment;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+    Txt,
+}
+
+impl SubtitleFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+            SubtitleFormat::Txt => "txt",
+        }
+    }
+}
+
+pub fn render(segments: &[Segment], format: SubtitleFormat) -> String {
+    match format {
+        SubtitleFormat::Srt => to_srt(segments),
+        SubtitleFormat::Vtt => to_vtt(segments),
+        SubtitleFormat::Txt => to_plain_text(segments),
+    }
+}
+
+/// SubRip: numbered cues with comma-separated milliseconds. SubRip has
+/// no escapes, so an arrow in the text is shortened to keep it from
+/// reading as a timing line.
+pub fn to_srt(segments: &[Segment]) -> String {
+    let mut out = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_timestamp(segment.start, ','),
+            format_timestamp(segment.end, ','),
+            cue_lines(&cue_text(segment)).replace("-->", "->")
+        ));
+    }
+    out
+}
+
+/// WebVTT: header plus cues with dot-separated milliseconds; speakers
+/// become voice spans. Text is escaped, which also breaks up any "-->".
+pub fn to_vtt(segments: &[Segment]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for segment in segments {
+        let text = escape_vtt(&cue_lines(&segment.text));
+        let text = match &segment.speaker {
+            Some(speaker) => format!("<v {}>{}", escape_vtt(speaker.trim()), text),
+            None => text,
+        };
+        out.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(segment.start, '.'),
+            format_timestamp(segment.end, '.'),
+            text
+        ));
+    }
+    out
+}
+
+/// One line per segment, prefixed with the speaker when known.
+pub fn to_plain_text(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(cue_text)
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn cue_text(segment: &Segment) -> String {
+    match &segment.speaker {
+        Some(speaker) => format!("{}: {}", speaker, segment.text.trim()),
+        None => segment.text.trim().to_string(),
+    }
+}
+
+/// Cue text without blank lines, which would end the cue early.
+fn cue_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// "HH:MM:SS,mmm" (SRT) or "HH:MM:SS.mmm" (WebVTT).
+fn format_timestamp(seconds: f64, separator: char) -> String {
+    let total_ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    let hours = total_ms / 3_600_000;
+    let minutes = (total_ms / 60_000) % 60;
+    let secs = (total_ms / 1000) % 60;
+    let millis = total_ms % 1000;
+    format!("{:02}:{:02}:{:02}{}{:03}", hours, minutes, secs, separator, millis)
+}
+
+/// Parse SubRip cues ("1\n00:00:01,000 --> 00:00:02,500\ntext").
+pub fn parse_srt(body: &str) -> Result<Vec<Segment>, String> {
+    let body = body.replace("\r\n", "\n");
+    let mut segments = Vec::new();
+    for block in body.split("\n\n").filter(|b| !b.trim().is_empty()) {
+        let mut lines = block.trim().lines();
+        let mut timing = lines.next().ok_or("Empty SRT cue")?;
+        if !timing.contains("-->") {
+            timing = lines.next().ok_or("SRT cue without timing")?;
+        }
+        let (start, end) = timing.split_once("-->").ok_or("Invalid SRT timing")?;
+        segments.push(Segment {
+            start: parse_srt_time(start)?,
+            end: parse_srt_time(end)?,
+            text: lines.collect::<Vec<_>>().join(" "),
+            confidence: None,
+            speaker: None,
+            words: Vec::new(),
+        });
+    }
+    Ok(segments)
+}
+
+fn parse_srt_time(value: &str) -> Result<f64, String> {
+    let value = value.trim().replace(',', ".");
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() != 3 {
+        return Err(format!("Invalid SRT time '{}'", value));
+    }
+    let hours: f64 = parts[0].parse().map_err(|_| format!("Invalid SRT time '{}'", value))?;
+    let minutes: f64 = parts[1].parse().map_err(|_| format!("Invalid SRT time '{}'", value))?;
+    let seconds: f64 = parts[2].parse().map_err(|_| format!("Invalid SRT time '{}'", value))?;
+    Ok(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, end: f64, text: &str, speaker: Option<&str>) -> Segment {
+        Segment {
+            start,
+            end,
+            text: text.into(),
+            confidence: None,
+            speaker: speaker.map(str::to_string),
+            words: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn srt_round_trips() {
+        let segments = vec![
+            segment(0.0, 1.5, "Hello there.", None),
+            segment(61.25, 3725.004, "Fish & chips <3", None),
+        ];
+        let srt = to_srt(&segments);
+        assert!(srt.starts_with("1\n00:00:00,000 --> 00:00:01,500\nHello there.\n\n2\n00:01:01,250 --> 01:02:05,004\n"));
+
+        let parsed = parse_srt(&srt).unwrap();
+        assert_eq!(parsed.len(), 2);
+        for (parsed, original) in parsed.iter().zip(&segments) {
+            assert!((parsed.start - original.start).abs() < 0.001);
+            assert!((parsed.end - original.end).abs() < 0.001);
+            assert_eq!(parsed.text, original.text);
+        }
+    }
+
+    #[test]
+    fn cues_survive_blank_lines_and_arrows() {
+        let segments = vec![
+            segment(0.0, 1.0, "first line\n\n  second line  ", None),
+            segment(1.0, 2.0, "go --> there", Some("Asha")),
+        ];
+
+        let srt = to_srt(&segments);
+        let parsed = parse_srt(&srt).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].text, "first line second line");
+        assert_eq!(parsed[1].text, "Asha: go -> there");
+
+        let vtt = to_vtt(&segments);
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\nfirst line\nsecond line\n\n\
+             00:00:01.000 --> 00:00:02.000\n<v Asha>go --&gt; there\n\n"
+        );
+    }
+
+    #[test]
+    fn vtt_escapes_markup() {
+        let vtt = to_vtt(&[segment(0.0, 1.0, "a < b & c > d", Some("<Dad>"))]);
+        assert!(vtt.ends_with("<v &lt;Dad&gt;>a &lt; b &amp; c &gt; d\n\n"), "{}", vtt);
+    }
+
+    #[test]
+    fn parse_srt_accepts_crlf_and_rejects_bad_times() {
+        let parsed = parse_srt("1\r\n00:00:01,000 --> 00:00:02,000\r\nHi\r\nthere\r\n\r\n").unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].text, "Hi there");
+        assert!(parse_srt("1\n00:01,000 --> 00:00:02,000\nHi\n").is_err());
+    }
+
+    #[test]
+    fn plain_text_prefixes_speakers_and_skips_empty_segments() {
+        let segments = vec![
+            segment(0.0, 1.0, " Hi ", Some("Asha")),
+            segment(1.0, 2.0, "  ", None),
+            segment(2.0, 3.0, "Bye", None),
+        ];
+        assert_eq!(render(&segments, SubtitleFormat::Txt), "Asha: Hi\nBye");
+    }
+}
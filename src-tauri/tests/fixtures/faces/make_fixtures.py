@@ -0,0 +1,97 @@
+"""Write the tiny ONNX models the face tests load.
+
+detector.onnx ignores its input and always reports two candidates in the
+UltraFace layout: one face over the centre of the image (score 0.9) and
+one below the detection threshold. embedder.onnx averages each colour
+channel of the crop, giving a 3-dimensional embedding.
+
+The protobuf is encoded by hand so no onnx package is needed:
+    python3 make_fixtures.py
+"""
+import struct
+
+
+def varint(n):
+    out = b""
+    while True:
+        byte = n & 0x7F
+        n >>= 7
+        if n:
+            out += bytes([byte | 0x80])
+        else:
+            return out + bytes([byte])
+
+
+def field(number, wire, payload):
+    return varint(number << 3 | wire) + payload
+
+
+def int_field(number, value):
+    return field(number, 0, varint(value))
+
+
+def bytes_field(number, value):
+    if isinstance(value, str):
+        value = value.encode()
+    return field(number, 2, varint(len(value)) + value)
+
+
+def tensor(name, dims, values):
+    raw = b"".join(struct.pack("<f", v) for v in values)
+    body = b"".join(int_field(1, d) for d in dims)
+    return body + int_field(2, 1) + bytes_field(8, name) + bytes_field(9, raw)
+
+
+def value_info(name, dims):
+    shape = b"".join(bytes_field(1, int_field(1, d)) for d in dims)
+    tensor_type = int_field(1, 1) + bytes_field(2, shape)
+    return bytes_field(1, name) + bytes_field(2, bytes_field(1, tensor_type))
+
+
+def node(op, inputs, outputs, attributes=b""):
+    body = b"".join(bytes_field(1, i) for i in inputs)
+    body += b"".join(bytes_field(2, o) for o in outputs)
+    return body + bytes_field(4, op) + attributes
+
+
+def ints_attribute(name, values):
+    body = bytes_field(1, name) + b"".join(int_field(8, v) for v in values) + int_field(20, 7)
+    return bytes_field(5, body)
+
+
+def int_attribute(name, value):
+    return bytes_field(5, bytes_field(1, name) + int_field(3, value) + int_field(20, 2))
+
+
+def model(graph):
+    opset = bytes_field(1, "") + int_field(2, 13)
+    return int_field(1, 8) + bytes_field(2, "aethersync-fixtures") + bytes_field(7, graph) + bytes_field(8, opset)
+
+
+def detector():
+    scores = tensor("scores_value", [1, 2, 2], [0.1, 0.9, 0.8, 0.2])
+    boxes = tensor("boxes_value", [1, 2, 4], [0.25, 0.25, 0.75, 0.75, 0.0, 0.0, 1.0, 1.0])
+    graph = bytes_field(1, node("Identity", ["scores_value"], ["scores"]))
+    graph += bytes_field(1, node("Identity", ["boxes_value"], ["boxes"]))
+    graph += bytes_field(2, "detector")
+    graph += bytes_field(5, scores) + bytes_field(5, boxes)
+    graph += bytes_field(11, value_info("input", [1, 3, 240, 320]))
+    graph += bytes_field(12, value_info("scores", [1, 2, 2]))
+    graph += bytes_field(12, value_info("boxes", [1, 2, 4]))
+    return model(graph)
+
+
+def embedder():
+    attributes = ints_attribute("axes", [2, 3]) + int_attribute("keepdims", 0)
+    graph = bytes_field(1, node("ReduceMean", ["input"], ["embedding"], attributes))
+    graph += bytes_field(2, "embedder")
+    graph += bytes_field(11, value_info("input", [1, 3, 160, 160]))
+    graph += bytes_field(12, value_info("embedding", [1, 3]))
+    return model(graph)
+
+
+if __name__ == "__main__":
+    with open("detector.onnx", "wb") as f:
+        f.write(detector())
+    with open("embedder.onnx", "wb") as f:
+        f.write(embedder())